@@ -1,4 +1,6 @@
+use std::cmp::min;
 use std::io::{stdout, Result, Stdout};
+use std::time::{Duration, Instant};
 
 use copypasta::{ClipboardContext, ClipboardProvider};
 use ratatui::{
@@ -12,27 +14,40 @@ use ratatui::{
         execute,
         terminal::{disable_raw_mode, enable_raw_mode, EnterAlternateScreen, LeaveAlternateScreen},
     },
-    layout::{Constraint, Direction, Layout, Position, Rect},
-    text::Line,
-    widgets::Paragraph,
+    layout::{Alignment, Constraint, Direction, Layout, Position, Rect},
+    style::{Color, Style, Stylize},
+    text::{Line, Span},
+    widgets::{Block, Borders, Clear, ListState, Paragraph},
     Frame, Terminal,
 };
 
 use crate::{
+    commands::Command,
     config::Config,
-    formulas::{balance_parens, extract_references},
-    spreadsheet::{Spreadsheet, SPREADSHEET_MAX_COLS, SPREADSHEET_MAX_ROWS},
+    formats::{self, CellColor, CellFormat, TextStyle},
+    formulas::{
+        balance_parens, cell_to_token, extract_references, shift_references, ShiftableFormula,
+    },
+    references::Reference,
+    search::SearchPattern,
+    spreadsheet::{Spreadsheet, SpreadsheetCell},
     ui::{
         button::{Button, ButtonState},
-        formula_suggestions::{FormulaSuggestions, FormulaSuggestionsState},
-        infinite_table::{InfiniteTable, InfiniteTableState},
+        formula_suggestions::{
+            popup_area, render_list_popup, FormulaSuggestions, FormulaSuggestionsState,
+        },
+        infinite_table::{to_color, InfiniteTable, InfiniteTableState},
         text_input::{TextInput, TextInputState},
     },
     undo_stack,
+    utils::StringPadding,
 };
 
 pub type TUI = Terminal<CrosstermBackend<Stdout>>;
 
+// How long a notification stays in the status line
+const NOTIFICATION_DURATION: Duration = Duration::from_secs(3);
+
 pub fn init() -> Result<TUI> {
     execute!(stdout(), EnterAlternateScreen)?;
     execute!(stdout(), EnableMouseCapture)?;
@@ -63,18 +78,54 @@ pub enum AppArea {
     CommandBar,
 }
 
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum ColorTarget {
+    Text,
+    Background,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum CommandMode {
+    Find,
+    Replace,
+    Command,
+}
+
+#[derive(Debug)]
+struct FormulaClipboard {
+    origin: SpreadsheetCell,
+    text: String,
+    formulas: Vec<Vec<String>>,
+}
+
 #[derive(Debug)]
 pub struct App {
     pub spreadsheet: Spreadsheet,
     pub focused_area: AppArea,
 
     pub formula_editor_state: TextInputState,
+    pub search_input_state: TextInputState,
     pub infinite_table_state: InfiniteTableState,
     pub formula_suggestions_state: FormulaSuggestionsState,
     pub paste_button_state: ButtonState,
 
     pub config: Config,
 
+    formula_clipboard: Option<FormulaClipboard>,
+    formula_preview: Option<(String, String)>, // The formula being edited and its result
+    pending_balance: Option<String>, // A balanced formula waiting for confirmation to commit
+    show_debug_overlay: bool,
+    color_picker: Option<ColorTarget>, // Set while picking a color for the selection
+    command_mode: CommandMode,
+    command_error: Option<String>, // Shown in the command bar, ie. for an invalid pattern
+    search_term: Option<String>,   // Set while cycling through matches with n and N
+    search_regex: bool,
+    search_index: Option<usize>, // Which of the matches the active cell is on
+    search_status: Option<String>,
+    resizing_col: Option<usize>, // The column last resized with + or -, for the guide
+    value_list: Option<ListState>, // Set while picking from the active cell's list of values
+    validation_error: Option<String>, // Why the value being committed isn't allowed
+    notification: Option<(String, Instant)>, // A passing message, and when it goes away
     exit: bool,
 }
 
@@ -85,12 +136,28 @@ impl App {
             focused_area: AppArea::default(),
 
             formula_editor_state: TextInputState::default(),
+            search_input_state: TextInputState::default(),
             infinite_table_state: InfiniteTableState::default(),
             formula_suggestions_state: FormulaSuggestionsState::default(),
             paste_button_state: ButtonState::default(),
 
             config,
 
+            formula_clipboard: None,
+            formula_preview: None,
+            pending_balance: None,
+            show_debug_overlay: false,
+            color_picker: None,
+            command_mode: CommandMode::Find,
+            command_error: None,
+            search_term: None,
+            search_regex: false,
+            search_index: None,
+            search_status: None,
+            resizing_col: None,
+            value_list: None,
+            validation_error: None,
+            notification: None,
             exit: false,
         }
     }
@@ -112,8 +179,7 @@ impl App {
         } else {
             self.formula_editor_state.set_value(
                 self.spreadsheet
-                    .get_cell(&self.infinite_table_state.active_cell)
-                    .to_string(),
+                    .get_cell_entry(&self.infinite_table_state.active_cell),
             );
             // Needed so that cursor position doesn't persist and show text selection when unfocused.
             self.formula_editor_state.set_cursor(0);
@@ -134,6 +200,11 @@ impl App {
                 col_widths: self.spreadsheet.col_widths.clone(),
                 col_space: 1,
                 spreadsheet: &self.spreadsheet,
+                resize_guide: self.resizing_col,
+                stripe_style: self
+                    .config
+                    .zebra
+                    .then(|| Style::new().bg(Color::Indexed(236))),
                 highlights: if self.focused_area == AppArea::Editor
                     && self.formula_editor_state.value().starts_with("=")
                 {
@@ -150,24 +221,151 @@ impl App {
             main_layout[1],
             &mut self.infinite_table_state,
         );
+        if self.value_list.is_some() {
+            self.render_value_list(frame, main_layout[1]);
+        }
         frame.render_stateful_widget(
             TextInput::default(),
             main_layout[0],
             &mut self.formula_editor_state,
         );
 
-        frame.render_widget(
-            Paragraph::new(format!("Undo: {}", self.spreadsheet.undo_stack)),
-            main_layout[2],
-        );
+        // Notifications are cleared by the first frame after they expire
+        if self
+            .notification
+            .as_ref()
+            .is_some_and(|(_, expiry)| Instant::now() >= *expiry)
+        {
+            self.notification = None;
+        }
+        let mut status = vec![Span::raw(format!("Undo: {}", self.spreadsheet.undo_stack))];
+        if !self.spreadsheet.filters().is_empty() {
+            // Like Excel's count of records found, without the header row
+            let rows = self
+                .spreadsheet
+                .data_rows()
+                .saturating_sub(self.spreadsheet.header_rows());
+            let shown = rows.saturating_sub(self.spreadsheet.filtered_rows().len());
+            status.insert(0, Span::raw(" · "));
+            status.insert(
+                0,
+                Span::raw(format!("Filtered: {} of {} rows", shown, rows)),
+            );
+        }
+        if let Some((message, _)) = &self.notification {
+            status.insert(0, Span::raw(" · "));
+            status.insert(0, Span::styled(message.as_str(), Style::new().bold()));
+        }
+        let status_width = Line::from(status.clone()).width() as u16;
+        frame.render_widget(Paragraph::new(Line::from(status)), main_layout[2]);
+
+        self.update_formula_preview();
+        if self.focused_area == AppArea::CommandBar {
+            let label = match (self.command_mode, self.search_regex) {
+                (CommandMode::Find, false) => "Find: ",
+                (CommandMode::Find, true) => "Find (regex): ",
+                (CommandMode::Replace, _) => "Replace with: ",
+                (CommandMode::Command, _) => ":",
+            };
+            let [label_area, input_area] =
+                Layout::horizontal([Constraint::Length(label.len() as u16), Constraint::Fill(1)])
+                    .areas(main_layout[2]);
+            frame.render_widget(Clear, main_layout[2]);
+            frame.render_widget(Paragraph::new(label), label_area);
+            if let Some(error) = &self.command_error {
+                frame.render_widget(
+                    Paragraph::new(error.as_str())
+                        .style(Style::new().red())
+                        .alignment(Alignment::Right),
+                    main_layout[2],
+                );
+            }
+            frame.render_stateful_widget(
+                TextInput::default(),
+                input_area,
+                &mut self.search_input_state,
+            );
+            frame.set_cursor_position(Position {
+                x: input_area.x + self.search_input_state.cursor() as u16,
+                y: input_area.y,
+            });
+        } else if let Some(target) = self.color_picker {
+            // Each color is shown in itself, numbered by the key that picks it
+            let mut spans = vec![Span::raw(match target {
+                ColorTarget::Text => "Text color: ",
+                ColorTarget::Background => "Background: ",
+            })];
+            for (idx, color) in CellColor::PALETTE.iter().enumerate() {
+                spans.push(Span::styled(
+                    format!("{} {:?} ", idx + 1, color),
+                    Style::new().fg(to_color(*color)),
+                ));
+            }
+            spans.push(Span::raw("0 None"));
+            frame.render_widget(
+                Paragraph::new(Line::from(spans)).alignment(Alignment::Right),
+                main_layout[2],
+            );
+        } else if let Some(col) = self.resizing_col {
+            frame.render_widget(
+                Paragraph::new(format!(
+                    "Column {} width: {} (+/- to resize)",
+                    Reference::index_to_alpha(col as u32 + 1),
+                    self.spreadsheet
+                        .get_col_width(&SpreadsheetCell { row: 0, col })
+                ))
+                .alignment(Alignment::Right),
+                main_layout[2],
+            );
+        } else if let Some(status) = &self.search_status {
+            frame.render_widget(
+                Paragraph::new(format!("{} (n/N for next/previous, Esc to stop)", status))
+                    .alignment(Alignment::Right),
+                main_layout[2],
+            );
+        } else if let (AppArea::Editor, Some(error)) = (&self.focused_area, &self.validation_error)
+        {
+            frame.render_widget(
+                Paragraph::new(error.as_str())
+                    .style(Style::new().red())
+                    .alignment(Alignment::Right),
+                main_layout[2],
+            );
+        } else if let Some(balanced) = &self.pending_balance {
+            frame.render_widget(
+                Paragraph::new(format!(
+                    "Commit as {}? (Enter to confirm, Esc to keep editing)",
+                    balanced
+                ))
+                .alignment(Alignment::Right),
+                main_layout[2],
+            );
+        } else if let Some((_, result)) = &self.formula_preview {
+            frame.render_widget(
+                Paragraph::new(format!("= {}", result)).alignment(Alignment::Right),
+                main_layout[2],
+            );
+        } else if let Some(status) = self.active_cell_status() {
+            // Cut to fit beside the rest of the status line, keeping the start of long entries
+            let width = main_layout[2].width.saturating_sub(status_width + 1);
+            frame.render_widget(
+                Paragraph::new(status.ellipsize(width as usize)).alignment(Alignment::Right),
+                main_layout[2],
+            );
+        }
 
         self.formula_suggestions_state.text_input_state = self.formula_editor_state.clone();
+        self.formula_suggestions_state.names = self.spreadsheet.header_names();
         frame.render_stateful_widget(
             FormulaSuggestions::default(),
             frame.area(),
             &mut self.formula_suggestions_state,
         );
 
+        if self.show_debug_overlay {
+            self.render_debug_overlay(frame, main_layout[1]);
+        }
+
         frame.render_stateful_widget(
             Button {
                 text: String::from(if self.config.nerd_font {
@@ -186,14 +384,148 @@ impl App {
         );
     }
 
+    fn active_cell_status(&self) -> Option<String> {
+        // The active cell's whole entry and value, which the grid may only have room for part of
+        if self.focused_area != AppArea::Data || self.value_list.is_some() {
+            return None;
+        }
+        let active_cell = &self.infinite_table_state.active_cell;
+        let entry = self.spreadsheet.get_cell_entry(active_cell);
+        let mut status = Reference::from_cell(active_cell).to_string();
+        if entry.starts_with('=') {
+            let value = match self.spreadsheet.get_cell_value(active_cell) {
+                Ok(value) => value.content,
+                Err(_) => String::from("..."),
+            };
+            status += &format!(": {} = {}", entry, value);
+        } else if let Some(value) = self.spreadsheet.spilled_value(active_cell) {
+            status += &format!(" = {} (spilled)", value.content);
+        } else if !entry.is_empty() {
+            status += &format!(": {}", entry);
+        }
+        if self.spreadsheet.get_validation(active_cell).is_some() {
+            status += " · Alt+Down to pick from the list";
+        }
+        Some(status)
+    }
+
+    fn render_value_list(&mut self, frame: &mut Frame, area: Rect) {
+        // A dropdown under the active cell, like Excel's for cells with a list of values
+        let active_cell = &self.infinite_table_state.active_cell;
+        let (Some(list_state), Some(validation), Some(cell_area)) = (
+            self.value_list.as_mut(),
+            self.spreadsheet.get_validation(active_cell),
+            self.infinite_table_state.cell_area(active_cell),
+        ) else {
+            return;
+        };
+        let values = validation.allowed_values(&self.spreadsheet);
+        let width = values
+            .iter()
+            .map(|value| value.chars().count() as u16 + 2)
+            .max()
+            .unwrap_or(0)
+            .max(cell_area.width)
+            .min(area.width);
+        let list_area = popup_area(area, cell_area, cell_area.x, width, values.len() as u16 + 2);
+        let label_width = list_area.width.saturating_sub(2) as usize;
+        let items = values
+            .iter()
+            .map(|value| Line::from(value.ellipsize(label_width)))
+            .collect();
+        render_list_popup(list_area, frame.buffer_mut(), "List", items, list_state);
+    }
+
+    fn render_debug_overlay(&self, frame: &mut Frame, area: Rect) {
+        let stats = self.spreadsheet.stats();
+        let cache_stats = self.infinite_table_state.cache_stats();
+        let frame_stats = self.infinite_table_state.frame_stats();
+        let lines = vec![
+            Line::from(format!(
+                "Used: {} rows x {} cols",
+                stats.used_rows, stats.used_cols
+            )),
+            Line::from(format!("Cells: {}", stats.used_cells)),
+            Line::from(format!("Undo depth: {}", stats.undo_depth)),
+            Line::from(format!("Cached values: {}", stats.cached_values)),
+            Line::from(format!(
+                "Rendered formulas: {}",
+                self.infinite_table_state.formula_cache.len()
+            )),
+            Line::from(format!(
+                "Cache: {}/{} hits ({:.0}%)",
+                cache_stats.hits,
+                cache_stats.hits + cache_stats.misses,
+                cache_stats.hit_rate() * 100.0
+            )),
+            Line::from(format!(
+                "Frame: {} rendered, {} reused",
+                frame_stats.rendered, frame_stats.reused
+            )),
+            Line::from(format!("Memory: ~{} KiB", stats.approx_bytes / 1024)),
+        ];
+        // Pinned to the top right corner of the table
+        let width = min(32, area.width);
+        let height = min(lines.len() as u16 + 2, area.height);
+        let overlay_area = Rect {
+            x: area.right() - width,
+            y: area.y,
+            width,
+            height,
+        };
+        frame.render_widget(Clear, overlay_area);
+        frame.render_widget(
+            Paragraph::new(lines).block(Block::new().title("Debug").borders(Borders::ALL)),
+            overlay_area,
+        );
+    }
+
+    fn update_formula_preview(&mut self) {
+        let value = self.formula_editor_state.value();
+        if self.focused_area != AppArea::Editor || !value.starts_with("=") {
+            self.formula_preview = None;
+            return;
+        }
+        // Only re-evaluate when the formula has actually changed since the last frame
+        if self
+            .formula_preview
+            .as_ref()
+            .is_some_and(|(formula, _)| *formula == value)
+        {
+            return;
+        }
+
+        let result = match cell_to_token(
+            &balance_parens(&value),
+            &self.spreadsheet,
+            Some(&self.infinite_table_state.active_cell),
+        ) {
+            Ok(token) => token.content,
+            Err(_) => String::from("..."),
+        };
+        self.formula_preview = Some((value, result));
+    }
+
+    pub fn notify(&mut self, message: impl Into<String>) {
+        // Shows a message in the status line for a few seconds, ie. to confirm something happened
+        self.notification = Some((message.into(), Instant::now() + NOTIFICATION_DURATION));
+    }
+
     fn handle_events(&mut self) -> Result<()> {
+        // Waiting for input stops when a notification expires, so it's cleared on time even
+        // without a key press
+        if let Some((_, expiry)) = &self.notification {
+            if !event::poll(expiry.saturating_duration_since(Instant::now()))? {
+                return Ok(());
+            }
+        }
         let event = event::read()?;
         self.handle_global_event(&event);
         match self.focused_area {
             AppArea::Data => self.handle_data_event(&event),
             AppArea::Editor => self.handle_editor_event(&event),
             AppArea::Menu => (),
-            AppArea::CommandBar => (),
+            AppArea::CommandBar => self.handle_command_bar_event(&event),
         }
         Ok(())
     }
@@ -204,6 +536,11 @@ impl App {
                 KeyCode::Char('q') if key_event.modifiers.contains(KeyModifiers::CONTROL) => {
                     self.exit = true
                 }
+                KeyCode::F(12)
+                    if self.config.debug_overlay && key_event.kind == KeyEventKind::Press =>
+                {
+                    self.show_debug_overlay = !self.show_debug_overlay
+                }
                 _ => (),
             },
             _ => (),
@@ -211,7 +548,66 @@ impl App {
     }
 
     fn handle_data_event(&mut self, event: &Event) {
+        if let (Some(target), Event::Key(key_event)) = (self.color_picker, event) {
+            if key_event.kind != KeyEventKind::Press {
+                return;
+            }
+            // Any key closes the palette, with digits picking a color (or none) first
+            self.color_picker = None;
+            if let KeyCode::Char(c @ '0'..='9') = key_event.code {
+                let idx = c.to_digit(10).unwrap() as usize;
+                let color = idx.checked_sub(1).and_then(|i| CellColor::PALETTE.get(i));
+                if idx == 0 || color.is_some() {
+                    for range in self.infinite_table_state.selections() {
+                        self.spreadsheet.color_range(&range, |colors| match target {
+                            ColorTarget::Text => colors.fg = color.copied(),
+                            ColorTarget::Background => colors.bg = color.copied(),
+                        });
+                    }
+                }
+            }
+            return;
+        }
+        if let (Some(list_state), Event::Key(key_event)) = (&mut self.value_list, event) {
+            if key_event.kind != KeyEventKind::Press {
+                return;
+            }
+            // Arrows move through the values and Enter picks one, while anything else closes it
+            match key_event.code {
+                KeyCode::Up => list_state.select_previous(),
+                KeyCode::Down => list_state.select_next(),
+                KeyCode::Enter => {
+                    let selected = list_state.selected();
+                    self.value_list = None;
+                    self.pick_value(selected.unwrap_or(0));
+                }
+                _ => self.value_list = None,
+            }
+            return;
+        }
+
         self.infinite_table_state.handle_event(event);
+        if let Some(resize) = self.infinite_table_state.col_resize {
+            let cell = SpreadsheetCell {
+                row: 0,
+                col: resize.col,
+            };
+            if resize.done {
+                // The width changed live while dragging, so it's put back and changed again as
+                // one undo step
+                self.spreadsheet.set_col_width(&cell, resize.from);
+                self.spreadsheet.resize_col(resize.col, resize.to);
+                self.infinite_table_state.col_resize = None;
+                self.resizing_col = None;
+            } else {
+                self.spreadsheet.set_col_width(&cell, resize.to);
+                self.resizing_col = Some(resize.col);
+            }
+        }
+        if let Some(row) = self.infinite_table_state.outline_click.take() {
+            self.spreadsheet.toggle_row_group(row);
+            self.infinite_table_state.formula_cache.clear();
+        }
         self.paste_button_state.handle_event(event);
         if self.paste_button_state.is_pressed {
             // TODO: self.
@@ -219,7 +615,77 @@ impl App {
 
         match event {
             Event::Key(key_event) if key_event.kind == KeyEventKind::Press => {
+                // The resize guide only stays up while resizing keeps going
+                self.resizing_col = None;
+                let shift = key_event.modifiers.contains(KeyModifiers::SHIFT);
+                let ctrl = key_event.modifiers.contains(KeyModifiers::CONTROL);
+                let alt = key_event.modifiers.contains(KeyModifiers::ALT);
                 match key_event.code {
+                    // Searching, then cycling through the matches while a search is going
+                    KeyCode::Char('f') if ctrl => self.open_command_bar(CommandMode::Find),
+                    KeyCode::Char('h') if ctrl => self.open_command_bar(
+                        // Replacing needs something to replace, so that's asked for first
+                        if self.search_term.is_some() {
+                            CommandMode::Replace
+                        } else {
+                            CommandMode::Find
+                        },
+                    ),
+                    KeyCode::Char(':') if !ctrl && !alt => {
+                        self.open_command_bar(CommandMode::Command)
+                    }
+                    KeyCode::Char('n') if self.search_term.is_some() && !ctrl && !alt => {
+                        self.find_next(true)
+                    }
+                    KeyCode::Char('N') if self.search_term.is_some() && !ctrl && !alt => {
+                        self.find_next(false)
+                    }
+                    KeyCode::Esc if self.search_term.is_some() => {
+                        self.search_term = None;
+                        self.search_index = None;
+                        self.search_status = None;
+                    }
+
+                    // Picking from the active cell's list of values, with Excel's shortcut
+                    KeyCode::Down
+                        if alt
+                            && self
+                                .spreadsheet
+                                .get_validation(&self.infinite_table_state.active_cell)
+                                .is_some() =>
+                    {
+                        self.open_value_list()
+                    }
+
+                    // Jumping to the edges of data
+                    KeyCode::Right if ctrl => self.jump_active_cell(1, 0, shift),
+                    KeyCode::Left if ctrl => self.jump_active_cell(-1, 0, shift),
+                    KeyCode::Down if ctrl => self.jump_active_cell(0, 1, shift),
+                    KeyCode::Up if ctrl => self.jump_active_cell(0, -1, shift),
+
+                    // Jumping to the start and end of the data
+                    KeyCode::Home if ctrl => self
+                        .infinite_table_state
+                        .move_active_cell_to(&SpreadsheetCell { row: 0, col: 0 }, shift),
+                    KeyCode::End if ctrl => {
+                        if let Some([_, end]) = self.spreadsheet.used_range() {
+                            self.infinite_table_state.move_active_cell_to(&end, shift);
+                        }
+                    }
+
+                    // Jumping to the start and end of the row
+                    KeyCode::Home => {
+                        let row = self.moving_cell(shift).row;
+                        self.infinite_table_state
+                            .move_active_cell_to(&SpreadsheetCell { row, col: 0 }, shift);
+                    }
+                    KeyCode::End => {
+                        let row = self.moving_cell(shift).row;
+                        let col = self.spreadsheet.last_used_col(row);
+                        self.infinite_table_state
+                            .move_active_cell_to(&SpreadsheetCell { row, col }, shift);
+                    }
+
                     // Cell movement
                     KeyCode::Right => {
                         self.infinite_table_state.move_active_cell(
@@ -257,14 +723,17 @@ impl App {
                             && self.infinite_table_state.active_cell.row > 0
                         {
                             self.infinite_table_state.active_cell.row -= 1
-                        } else if self.infinite_table_state.active_cell.row < SPREADSHEET_MAX_ROWS {
+                        } else if self.infinite_table_state.active_cell.row + 1
+                            < self.spreadsheet.max_rows()
+                        {
                             self.infinite_table_state.active_cell.row += 1
                         }
                     }
-                    KeyCode::Tab => {
-                        if self.infinite_table_state.active_cell.col < SPREADSHEET_MAX_COLS {
-                            self.infinite_table_state.active_cell.col += 1
-                        }
+                    KeyCode::Tab
+                        if self.infinite_table_state.active_cell.col + 1
+                            < self.spreadsheet.max_cols() =>
+                    {
+                        self.infinite_table_state.active_cell.col += 1
                     }
                     KeyCode::BackTab => {
                         if self.infinite_table_state.active_cell.col > 0 {
@@ -273,21 +742,16 @@ impl App {
                     }
 
                     // Resizing (temporary)
-                    KeyCode::Char('+') => {
-                        self.spreadsheet.set_col_width(
-                            &self.infinite_table_state.active_cell,
-                            self.spreadsheet
-                                .get_col_width(&self.infinite_table_state.active_cell)
-                                + 1,
-                        );
-                    }
-                    KeyCode::Char('-') => {
-                        self.spreadsheet.set_col_width(
-                            &self.infinite_table_state.active_cell,
-                            self.spreadsheet
-                                .get_col_width(&self.infinite_table_state.active_cell)
-                                - 1,
-                        );
+                    KeyCode::Char(c @ ('+' | '-')) => {
+                        let active_cell = &self.infinite_table_state.active_cell;
+                        let width = self.spreadsheet.get_col_width(active_cell);
+                        let width = if c == '+' {
+                            width.saturating_add(1)
+                        } else {
+                            width.saturating_sub(1)
+                        };
+                        self.spreadsheet.resize_col(active_cell.col, width);
+                        self.resizing_col = Some(active_cell.col);
                     }
 
                     // Undo/Redo
@@ -309,104 +773,479 @@ impl App {
                         }
                     }
 
+                    // Sorting by the active column, below the header if there is one
+                    KeyCode::Char('s') if alt => {
+                        self.spreadsheet
+                            .sort_by_column(self.infinite_table_state.active_cell.col, false);
+                        self.infinite_table_state.formula_cache.clear();
+                    }
+                    KeyCode::Char('S') if alt => {
+                        self.spreadsheet
+                            .sort_by_column(self.infinite_table_state.active_cell.col, true);
+                        self.infinite_table_state.formula_cache.clear();
+                    }
+                    KeyCode::Char('h') if alt => {
+                        self.spreadsheet
+                            .set_has_header(!self.spreadsheet.has_header());
+                    }
+
+                    // Number formats for the selection, with Excel's Ctrl+Shift shortcuts
+                    KeyCode::Char(c @ ('~' | '%' | '$' | '^')) if ctrl => {
+                        let format = match c {
+                            '%' => CellFormat::Percent,
+                            '$' => CellFormat::Currency('$'),
+                            '^' => CellFormat::Scientific(2),
+                            _ => CellFormat::General,
+                        };
+                        for range in self.infinite_table_state.selections() {
+                            self.spreadsheet.format_range(&range, format);
+                        }
+                    }
+
+                    // Colors, picked from a palette shown in the status bar
+                    KeyCode::Char('f') if alt => self.color_picker = Some(ColorTarget::Text),
+                    KeyCode::Char('b') if alt => self.color_picker = Some(ColorTarget::Background),
+
+                    // Filling the top row of the selection down through the rest of it
+                    KeyCode::Char('d') if ctrl => {
+                        for range in self.infinite_table_state.selections() {
+                            self.spreadsheet.fill_down(&range);
+                        }
+                        self.infinite_table_state.formula_cache.clear();
+                    }
+
+                    // Today's date and the current time, like Excel's Ctrl+; and Ctrl+Shift+;
+                    KeyCode::Char(c @ (';' | ':')) if ctrl => {
+                        let entry = if c == ';' && !shift {
+                            formats::today()
+                        } else {
+                            formats::current_time()
+                        };
+                        self.spreadsheet
+                            .enter_cell(&self.infinite_table_state.active_cell, &entry);
+                        self.infinite_table_state.formula_cache.clear();
+                    }
+
+                    // Text styles, toggled for the whole selection based on the active cell
+                    KeyCode::Char(c @ ('b' | 'i' | 'u')) if ctrl => {
+                        let style = self
+                            .spreadsheet
+                            .get_text_style(&self.infinite_table_state.active_cell);
+                        let change = |s: &mut TextStyle| match c {
+                            'b' => s.bold = !style.bold,
+                            'i' => s.italic = !style.italic,
+                            _ => s.underline = !style.underline,
+                        };
+                        for range in self.infinite_table_state.selections() {
+                            self.spreadsheet.style_range(&range, change);
+                        }
+                    }
+
                     // Copy/Paste
                     KeyCode::Char('c') if key_event.modifiers.contains(KeyModifiers::CONTROL) => {
-                        // TODO: Once selections are added, this needs multiple changes.
-                        // TODO: Copying and pasting of formulas, not just their results.
-                        let text = self
-                            .spreadsheet
-                            .select_matrix(
-                                &self.infinite_table_state.active_cell,
-                                &self.infinite_table_state.selection_end,
-                            )
+                        let selections = self.infinite_table_state.selections();
+                        let Some(mat) = self.spreadsheet.select_ranges(&selections) else {
+                            // The ranges don't line up, so there's no sensible way to copy them
+                            return;
+                        };
+                        let selection = self.infinite_table_state.selection();
+                        let text = mat
                             .iter()
-                            .map(|r| r.join("\t"))
+                            .map(|r| self.config.copy_delimiter.join(r))
                             .collect::<Vec<String>>()
                             .join("\n");
 
+                        // Other apps only ever get the values, but pasting back in here can use
+                        // the formulas as long as the clipboard hasn't changed since.
+                        self.formula_clipboard =
+                            if self.config.copy_formulas && selections.len() == 1 {
+                                Some(FormulaClipboard {
+                                    origin: selection[0].clone(),
+                                    text: text.clone(),
+                                    formulas: self
+                                        .spreadsheet
+                                        .select_raw_matrix(&selection[0], &selection[1]),
+                                })
+                            } else {
+                                None
+                            };
+
                         let mut clipboard = ClipboardContext::new().unwrap();
                         clipboard.set_contents(text).unwrap();
                     }
                     KeyCode::Char('v') if key_event.modifiers.contains(KeyModifiers::CONTROL) => {
-                        // TODO: Once selections are added, this needs multiple changes.
-                        // TODO: Copying and pasting of formulas, not just their results.
-
                         let mut clipboard = ClipboardContext::new().unwrap();
 
                         if let Ok(text) = clipboard.get_contents() {
-                            let mut mat: Vec<Vec<String>> = text
-                                .to_string()
-                                .split("\n")
-                                .map(|r| r.split("\t").map(|c| c.to_string()).collect())
-                                .collect();
-                            let selection = self.infinite_table_state.selection();
-                            if mat.len() == 1 && mat[0].len() == 1 {
-                                // Handle the case where there is a single item in clipboard, where
-                                // it must be pasted to every cell in the selection.
-                                let rows = selection[1].row - selection[0].row + 1;
-                                let cols = selection[1].col - selection[0].col + 1;
-                                let value = mat[0][0].clone();
-                                mat = vec![vec![value; cols]; rows];
-                            }
-                            self.spreadsheet.replace_matrix(&selection[0], mat);
+                            self.paste_text(&text);
                         }
-
-                        self.infinite_table_state.formula_cache.clear()
                     }
 
                     // Editing
+                    KeyCode::F(2) | KeyCode::Char(_)
+                        if self
+                            .spreadsheet
+                            .is_spilled(&self.infinite_table_state.active_cell) =>
+                    {
+                        // Spilled cells can only be changed through the formula they spill from
+                    }
                     KeyCode::F(2) => {
                         self.focused_area = AppArea::Editor;
                         self.formula_editor_state
-                            .set_cursor(self.formula_editor_state.value().len());
+                            .set_cursor(self.formula_editor_state.char_count());
                     }
                     KeyCode::Char(c) => {
                         self.focused_area = AppArea::Editor;
                         self.formula_editor_state.set_value(c.to_string());
                         self.formula_editor_state
-                            .set_cursor(self.formula_editor_state.value().len());
+                            .set_cursor(self.formula_editor_state.char_count());
                     }
                     KeyCode::Backspace | KeyCode::Delete => {
-                        let selection = self.infinite_table_state.selection();
-                        let rows = selection[1].row - selection[0].row + 1;
-                        let cols = selection[1].col - selection[0].col + 1;
-                        let mat = vec![vec![String::new(); cols]; rows];
-                        self.spreadsheet.replace_matrix(&selection[0], mat);
+                        self.spreadsheet
+                            .clear_ranges(&self.infinite_table_state.selections());
 
                         self.infinite_table_state.formula_cache.clear();
                     }
 
                     // Miscellanous
                     KeyCode::F(9) => {
+                        self.spreadsheet.recalculate();
                         self.infinite_table_state.formula_cache.clear();
+                        self.notify("Recalculated");
                     }
                     _ => (),
                 }
             }
             Event::Paste(text) => {
                 if !text.is_empty() {
-                    let mut mat: Vec<Vec<String>> = text
-                        .to_string()
-                        .split("\n")
-                        .map(|r| r.split("\t").map(|c| c.to_string()).collect())
-                        .collect();
-                    let selection = self.infinite_table_state.selection();
-                    if mat.len() == 1 && mat[0].len() == 1 {
-                        // Handle the case where there is a single item in clipboard, where
-                        // it must be pasted to every cell in the selection.
-                        let rows = selection[1].row - selection[0].row + 1;
-                        let cols = selection[1].col - selection[0].col + 1;
-                        let value = mat[0][0].clone();
-                        mat = vec![vec![value; cols]; rows];
-                    }
-                    self.spreadsheet.replace_matrix(&selection[0], mat);
+                    self.paste_text(text);
                 }
             }
             _ => (),
         }
     }
 
+    fn paste_text(&mut self, text: &str) {
+        let selection = self.infinite_table_state.selection();
+        let formula_origin = match &self.formula_clipboard {
+            Some(copied) if copied.text == text => Some(copied.origin.clone()),
+            _ => None,
+        };
+        let mut mat: Vec<Vec<String>> = match &self.formula_clipboard {
+            Some(copied) if formula_origin.is_some() => copied.formulas.clone(),
+            _ => self.config.copy_delimiter.split(text),
+        };
+
+        log::debug!(
+            "Pasting {} row(s) at {:?} (formulas: {})",
+            mat.len(),
+            selection[0],
+            formula_origin.is_some()
+        );
+
+        let fill = mat.len() == 1 && mat[0].len() == 1;
+        if fill {
+            // Handle the case where there is a single item in clipboard, where
+            // it must be pasted to every cell in the selection.
+            let rows = selection[1].row - selection[0].row + 1;
+            let cols = selection[1].col - selection[0].col + 1;
+            let value = mat[0][0].clone();
+            mat = vec![vec![value; cols]; rows];
+        }
+
+        if let Some(origin) = formula_origin {
+            // Relative references move along with where the formula is pasted. A filled formula
+            // is the same everywhere, so it's only scanned once.
+            let filled = fill.then(|| ShiftableFormula::new(&mat[0][0]));
+            for (row, row_items) in mat.iter_mut().enumerate() {
+                for (col, value) in row_items.iter_mut().enumerate() {
+                    let rows = selection[0].row as i64 - origin.row as i64;
+                    let cols = selection[0].col as i64 - origin.col as i64;
+                    *value = match &filled {
+                        Some(formula) => formula.shift(rows + row as i64, cols + col as i64),
+                        None => shift_references(value, rows, cols),
+                    };
+                }
+            }
+        }
+
+        self.spreadsheet.replace_matrix(&selection[0], mat);
+        self.infinite_table_state.formula_cache.clear();
+    }
+
+    fn moving_cell(&self, group: bool) -> SpreadsheetCell {
+        // The cell that moves with the arrow keys, which is the end of the selection when extending it
+        if group {
+            self.infinite_table_state.selection_end.clone()
+        } else {
+            self.infinite_table_state.active_cell.clone()
+        }
+    }
+
+    fn jump_active_cell(&mut self, x: i32, y: i32, group: bool) {
+        let to = self
+            .spreadsheet
+            .find_data_edge(&self.moving_cell(group), x, y);
+        self.infinite_table_state.move_active_cell_to(&to, group);
+    }
+
+    fn open_command_bar(&mut self, mode: CommandMode) {
+        self.focused_area = AppArea::CommandBar;
+        self.command_mode = mode;
+        self.command_error = None;
+        self.search_input_state.set_value(match mode {
+            CommandMode::Find => self.search_term.clone().unwrap_or_default(),
+            CommandMode::Replace | CommandMode::Command => String::new(),
+        });
+        self.search_input_state
+            .set_cursor(self.search_input_state.char_count());
+    }
+
+    fn handle_command_bar_event(&mut self, event: &Event) {
+        let Event::Key(key_event) = event else {
+            self.search_input_state.handle_event(event);
+            return;
+        };
+        if key_event.kind != KeyEventKind::Press {
+            return;
+        }
+        let alt = key_event.modifiers.contains(KeyModifiers::ALT);
+        match key_event.code {
+            // Alt+R switches between plain text and regex searches
+            KeyCode::Char('r') if alt && self.command_mode == CommandMode::Find => {
+                self.search_regex = !self.search_regex;
+                self.command_error = None;
+            }
+            KeyCode::Enter => {
+                let value = self.search_input_state.value();
+                match self.command_mode {
+                    CommandMode::Find if value.is_empty() => self.focused_area = AppArea::Data,
+                    CommandMode::Find => match SearchPattern::new(&value, self.search_regex) {
+                        // An invalid pattern stays in the bar to be fixed
+                        Err(error) => self.command_error = Some(error),
+                        Ok(_) => {
+                            self.focused_area = AppArea::Data;
+                            self.search_term = Some(value);
+                            self.find_next(true);
+                        }
+                    },
+                    CommandMode::Replace => {
+                        self.focused_area = AppArea::Data;
+                        self.replace_all(&value);
+                    }
+                    CommandMode::Command => {
+                        match Command::parse(&value).and_then(|command| self.run_command(command)) {
+                            Err(error) => self.command_error = Some(error),
+                            Ok(()) => self.focused_area = AppArea::Data,
+                        }
+                    }
+                }
+            }
+            KeyCode::Esc => self.focused_area = AppArea::Data,
+            _ => {
+                self.command_error = None;
+                self.search_input_state.handle_event(event);
+            }
+        }
+    }
+
+    fn open_value_list(&mut self) {
+        // Starts on the cell's current value, if it's one of them
+        let active_cell = &self.infinite_table_state.active_cell;
+        let Some(validation) = self.spreadsheet.get_validation(active_cell) else {
+            return;
+        };
+        let current = self.spreadsheet.get_cell(active_cell);
+        let selected = validation
+            .allowed_values(&self.spreadsheet)
+            .iter()
+            .position(|value| value == current)
+            .unwrap_or(0);
+        self.value_list = Some(ListState::default().with_selected(Some(selected)));
+    }
+
+    fn pick_value(&mut self, idx: usize) {
+        let active_cell = self.infinite_table_state.active_cell.clone();
+        let Some(validation) = self.spreadsheet.get_validation(&active_cell) else {
+            return;
+        };
+        let values = validation.allowed_values(&self.spreadsheet);
+        // The selection can run past the end of the list before it's drawn again
+        if let Some(value) = values.get(idx).or(values.last()) {
+            self.spreadsheet.enter_cell(&active_cell, value);
+            self.infinite_table_state.formula_cache.clear();
+        }
+    }
+
+    fn run_command(&mut self, command: Command) -> std::result::Result<(), String> {
+        // Commands that can't apply to the selection give an error for the command bar
+        match command {
+            Command::ColWidth(width) => {
+                let col = self.infinite_table_state.active_cell.col;
+                self.spreadsheet.resize_col(col, width);
+                self.resizing_col = Some(col);
+            }
+            // Unhiding works on a selection around the hidden ones, like in Excel
+            Command::HideCols(hidden) => {
+                for [start, end] in self.infinite_table_state.selections() {
+                    for col in start.col..=end.col {
+                        self.spreadsheet.set_col_hidden(col, hidden);
+                    }
+                }
+            }
+            Command::HideRows(hidden) => {
+                for [start, end] in self.infinite_table_state.selections() {
+                    for row in start.row..=end.row {
+                        self.spreadsheet.set_row_hidden(row, hidden);
+                    }
+                }
+                // SUBTOTAL can leave out hidden rows
+                self.infinite_table_state.formula_cache.clear();
+            }
+            Command::Group => {
+                for [start, end] in self.infinite_table_state.selections() {
+                    self.spreadsheet.group_rows(start.row, end.row);
+                }
+            }
+            Command::Ungroup => {
+                let mut ungrouped = false;
+                for [start, end] in self.infinite_table_state.selections() {
+                    ungrouped |= self.spreadsheet.ungroup_rows(start.row, end.row);
+                }
+                if !ungrouped {
+                    return Err("These rows aren't grouped".to_string());
+                }
+                self.infinite_table_state.formula_cache.clear();
+            }
+            Command::Format(format) => {
+                for range in self.infinite_table_state.selections() {
+                    self.spreadsheet.format_range(&range, format);
+                }
+            }
+            Command::Filter(criteria) => {
+                let col = self.infinite_table_state.active_cell.col;
+                if criteria.is_none() && !self.spreadsheet.filters().contains_key(&col) {
+                    return Err("This column isn't filtered".to_string());
+                }
+                self.spreadsheet.set_filter(col, criteria);
+                self.infinite_table_state.formula_cache.clear();
+            }
+            Command::ClearFilters => {
+                if self.spreadsheet.filters().is_empty() {
+                    return Err("No filters to clear".to_string());
+                }
+                self.spreadsheet.clear_filters();
+                self.infinite_table_state.formula_cache.clear();
+            }
+            Command::Validate(validation) => {
+                for range in self.infinite_table_state.selections() {
+                    self.spreadsheet.validate_range(&range, validation.clone());
+                }
+            }
+            Command::Collapse | Command::Expand => {
+                let collapsed = command == Command::Collapse;
+                let row = self.infinite_table_state.active_cell.row;
+                if !self.spreadsheet.set_group_collapsed(row, collapsed) {
+                    return Err(format!(
+                        "No group to {} here",
+                        if collapsed { "collapse" } else { "expand" }
+                    ));
+                }
+                self.infinite_table_state.formula_cache.clear();
+            }
+        }
+        Ok(())
+    }
+
+    fn search_pattern(&self) -> Option<SearchPattern> {
+        // Terms are checked when entered, so this only fails without a search going
+        SearchPattern::new(self.search_term.as_ref()?, self.search_regex).ok()
+    }
+
+    fn replace_all(&mut self, replacement: &str) {
+        let Some(pattern) = self.search_pattern() else {
+            return;
+        };
+        let count = self.spreadsheet.replace_matches(&pattern, replacement);
+        self.infinite_table_state.formula_cache.clear();
+        self.search_index = None;
+        self.search_status = None;
+        self.notify(format!(
+            "Replaced in {} cell{}",
+            count,
+            if count == 1 { "" } else { "s" }
+        ));
+    }
+
+    fn find_next(&mut self, forward: bool) {
+        // Moves to the next (or previous) match after the active cell in reading order, wrapping
+        // around the ends of the sheet. The matches are found again each time, since the sheet
+        // may have been edited or the active cell moved since the last one.
+        let Some(pattern) = self.search_pattern() else {
+            return;
+        };
+        let matches = self
+            .spreadsheet
+            .find_matches(&pattern, self.config.search_values);
+        let active = &self.infinite_table_state.active_cell;
+        let position = |cell: &SpreadsheetCell| (cell.row, cell.col);
+        let found = if forward {
+            matches
+                .iter()
+                .position(|cell| position(cell) > position(active))
+        } else {
+            matches
+                .iter()
+                .rposition(|cell| position(cell) < position(active))
+        };
+        let (idx, wrapped) = match found {
+            _ if matches.is_empty() => {
+                self.search_index = None;
+                self.search_status = Some(format!(
+                    "No matches for \"{}\"",
+                    self.search_term.as_deref().unwrap_or_default()
+                ));
+                return;
+            }
+            Some(idx) => (idx, false),
+            None if forward => (0, true),
+            None => (matches.len() - 1, true),
+        };
+
+        self.infinite_table_state
+            .move_active_cell_to(&matches[idx], false);
+        self.search_index = Some(idx);
+        self.search_status = Some(format!(
+            "Match {} of {}{}",
+            idx + 1,
+            matches.len(),
+            match (wrapped, forward) {
+                (false, _) => "",
+                (true, true) => ", wrapped to the top",
+                (true, false) => ", wrapped to the bottom",
+            }
+        ));
+    }
+
     fn handle_editor_event(&mut self, event: &Event) {
+        if matches!(event, Event::Key(_)) {
+            self.validation_error = None;
+        }
+        // Esc closes the suggestions popup first, and only leaves the editor once it's gone
+        if let Event::Key(key_event) = event {
+            if key_event.code == KeyCode::Esc && self.formula_suggestions_state.visible {
+                self.formula_suggestions_state.dismiss();
+                return;
+            }
+            // Anything but Enter declines a pending balance, with Esc going back to editing
+            if key_event.code != KeyCode::Enter
+                && self.pending_balance.take().is_some()
+                && key_event.code == KeyCode::Esc
+            {
+                return;
+            }
+        }
+
         self.formula_editor_state.handle_event(&event);
         self.formula_suggestions_state.handle_event(&event);
         self.formula_editor_state = self.formula_suggestions_state.text_input_state.clone();
@@ -418,16 +1257,41 @@ impl App {
                     //     return;
                     // }
 
-                    self.focused_area = AppArea::Data;
-
-                    let value = if self.formula_editor_state.value().starts_with("=") {
-                        balance_parens(&self.formula_editor_state.value())
+                    let typed = self.formula_editor_state.value();
+                    let value = if typed.starts_with("=") {
+                        balance_parens(&typed)
                     } else {
-                        self.formula_editor_state.value()
-                    }; // TODO: Add a popup to confirm auto-balancing
+                        typed.clone()
+                    };
+                    // The first Enter shows what auto-balancing would commit, the second commits
+                    if self.config.confirm_balance
+                        && value != typed
+                        && self.pending_balance.as_ref() != Some(&value)
+                    {
+                        self.pending_balance = Some(value);
+                        return;
+                    }
+                    self.pending_balance = None;
+                    // Values the cell's rule doesn't allow are kept in the editor to be fixed
+                    let value = match self
+                        .spreadsheet
+                        .check_entry(&self.infinite_table_state.active_cell, &value)
+                    {
+                        Ok(value) => value,
+                        Err(error) => {
+                            self.validation_error = Some(error);
+                            return;
+                        }
+                    };
+                    self.focused_area = AppArea::Data;
 
+                    log::debug!(
+                        "Committing {:?} to {:?}",
+                        value,
+                        self.infinite_table_state.active_cell
+                    );
                     self.spreadsheet
-                        .set_cell(&self.infinite_table_state.active_cell, &value);
+                        .enter_cell(&self.infinite_table_state.active_cell, &value);
                     self.infinite_table_state.formula_cache.clear();
 
                     if self
@@ -455,3 +1319,30 @@ impl App {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ratatui::backend::TestBackend;
+
+    fn status_line(app: &mut App) -> String {
+        let mut terminal = Terminal::new(TestBackend::new(80, 20)).unwrap();
+        let frame = terminal.draw(|frame| app.render_frame(frame)).unwrap();
+        let area = frame.buffer.area;
+        (0..area.width)
+            .map(|x| frame.buffer[(x, area.height - 1)].symbol())
+            .collect()
+    }
+
+    #[test]
+    fn notifications_show_until_they_expire() {
+        let mut app = App::new(Config::default());
+        app.notify("Saved data.csv");
+        assert!(status_line(&mut app).starts_with("Saved data.csv · Undo"));
+
+        // Waiting out the notification is the same as it having expired already
+        app.notification.as_mut().unwrap().1 = Instant::now();
+        assert!(status_line(&mut app).starts_with("Undo"));
+        assert!(app.notification.is_none());
+    }
+}
@@ -0,0 +1,147 @@
+use crate::formats::{CellFormat, CURRENCY_SYMBOLS};
+use crate::spreadsheet::MIN_COL_WIDTH;
+use crate::validation::Validation;
+
+// Commands typed into the command bar after a colon, ie. `:colwidth 20`
+
+// Excel's widest column, which is also well past any terminal
+pub const MAX_COL_WIDTH: u16 = 255;
+
+// Past this, decimals are only rounding noise for a 64-bit float
+pub const MAX_DECIMALS: u8 = 15;
+// Digits below the line in fractions, like Excel's # ?/? up to # ???/???
+pub const MAX_FRACTION_DIGITS: u8 = 3;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Command {
+    // Sets the active column to an exact width
+    ColWidth(u16),
+    // Hides the selected columns or rows, or with false shows them again
+    HideCols(bool),
+    HideRows(bool),
+    // Outlines the selected rows as a group, which can then be collapsed and expanded around the
+    // active cell
+    Group,
+    Ungroup,
+    Collapse,
+    Expand,
+    // Sets (or with None, removes) the rule for what can be entered in the selected cells
+    Validate(Option<Validation>),
+    // Sets the number format of the selected cells
+    Format(CellFormat),
+    // Filters the active column's rows by a criteria (ie. >5, <>apple or *app*), or with None
+    // removes its filter, and clears every filter
+    Filter(Option<String>),
+    ClearFilters,
+}
+
+impl Command {
+    pub fn parse(input: &str) -> Result<Command, String> {
+        // The colon that opened the bar may be typed again out of habit
+        let input = input.trim().trim_start_matches(':').trim_start();
+        let mut words = input.split_whitespace();
+        let Some(name) = words.next() else {
+            return Err("Type a command, ie. colwidth 20".to_string());
+        };
+        let args: Vec<&str> = words.collect();
+        match name.to_lowercase().as_str() {
+            "colwidth" => {
+                let [width] = args[..] else {
+                    return Err("Usage: colwidth WIDTH".to_string());
+                };
+                width
+                    .parse::<u16>()
+                    .ok()
+                    .filter(|width| (MIN_COL_WIDTH..=MAX_COL_WIDTH).contains(width))
+                    .map(Command::ColWidth)
+                    .ok_or(format!(
+                        "Width must be from {} to {}",
+                        MIN_COL_WIDTH, MAX_COL_WIDTH
+                    ))
+            }
+            name @ ("hide" | "unhide") => {
+                let hidden = name == "hide";
+                match args[..] {
+                    ["cols" | "columns"] => Ok(Command::HideCols(hidden)),
+                    ["rows"] => Ok(Command::HideRows(hidden)),
+                    _ => Err(format!("Usage: {} cols|rows", name)),
+                }
+            }
+            name @ ("group" | "ungroup" | "collapse" | "expand") => {
+                if !args.is_empty() {
+                    return Err(format!("{} takes no arguments", name));
+                }
+                Ok(match name {
+                    "group" => Command::Group,
+                    "ungroup" => Command::Ungroup,
+                    "collapse" => Command::Collapse,
+                    _ => Command::Expand,
+                })
+            }
+            // Lists keep their spacing, so values can have spaces in them
+            "validate" => match input[name.len()..].trim().split_once(char::is_whitespace) {
+                Some((kind, source)) if kind.eq_ignore_ascii_case("list") => {
+                    Validation::parse_list(source).map(|list| Command::Validate(Some(list)))
+                }
+                None if args == ["none"] => Ok(Command::Validate(None)),
+                _ => Err("Usage: validate list VALUES|RANGE, or validate none".to_string()),
+            },
+            "format" => {
+                let usage =
+                    "Usage: format general|percent|currency [SYMBOL]|date|time|sci [DECIMALS]|eng [DECIMALS]|fraction [DIGITS]";
+                let decimals = |decimals: Option<&&str>| match decimals {
+                    None => Ok(2),
+                    Some(decimals) => decimals
+                        .parse::<u8>()
+                        .ok()
+                        .filter(|decimals| *decimals <= MAX_DECIMALS)
+                        .ok_or(format!("Decimals must be from 0 to {}", MAX_DECIMALS)),
+                };
+                match args[..] {
+                    ["general"] => Ok(Command::Format(CellFormat::General)),
+                    ["percent"] => Ok(Command::Format(CellFormat::Percent)),
+                    ["date"] => Ok(Command::Format(CellFormat::Date)),
+                    ["time"] => Ok(Command::Format(CellFormat::Time)),
+                    ["currency"] => Ok(Command::Format(CellFormat::Currency('$'))),
+                    ["currency", symbol] => match symbol.chars().collect::<Vec<char>>()[..] {
+                        [symbol] if CURRENCY_SYMBOLS.contains(&symbol) => {
+                            Ok(Command::Format(CellFormat::Currency(symbol)))
+                        }
+                        _ => Err(format!(
+                            "Currency symbols are {}",
+                            CURRENCY_SYMBOLS.iter().collect::<String>()
+                        )),
+                    },
+                    ["sci" | "scientific", ..] if args.len() <= 2 => {
+                        decimals(args.get(1)).map(|d| Command::Format(CellFormat::Scientific(d)))
+                    }
+                    ["eng" | "engineering", ..] if args.len() <= 2 => {
+                        decimals(args.get(1)).map(|d| Command::Format(CellFormat::Engineering(d)))
+                    }
+                    ["fraction", ..] if args.len() <= 2 => args
+                        .get(1)
+                        .map_or(Some(1), |digits| digits.parse::<u8>().ok())
+                        .filter(|digits| (1..=MAX_FRACTION_DIGITS).contains(digits))
+                        .map(|digits| Command::Format(CellFormat::Fraction(digits)))
+                        .ok_or(format!("Digits must be from 1 to {}", MAX_FRACTION_DIGITS)),
+                    _ => Err(usage.to_string()),
+                }
+            }
+            // Criteria keep their spacing too, to match text with spaces in it
+            "filter" => match input[name.len()..].trim() {
+                "" => Err("Usage: filter CRITERIA (ie. >5 or *apple*), or filter none".to_string()),
+                "none" => Ok(Command::Filter(None)),
+                criteria => Ok(Command::Filter(Some(criteria.to_string()))),
+            },
+            "unfilter" => {
+                if !args.is_empty() {
+                    return Err("unfilter takes no arguments".to_string());
+                }
+                Ok(Command::ClearFilters)
+            }
+            // Every row is one line tall for now
+            "rowheight" => Err("Row heights aren't supported yet".to_string()),
+            _ => Err(format!("Unknown command: {}", name)),
+        }
+    }
+}
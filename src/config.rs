@@ -1,4 +1,187 @@
-#[derive(Debug)]
+use clap::ValueEnum;
+
+use crate::spreadsheet::{parse_csv, quote_csv_cell};
+
+#[derive(Debug, Default)]
 pub struct Config {
     pub nerd_font: bool,
+    pub default_col_width: u16,
+    pub copy_formulas: bool,
+    pub copy_delimiter: CopyDelimiter,
+    pub confirm_balance: bool, // Ask before adding missing parentheses to a formula
+    pub debug_overlay: bool,   // Allow toggling performance stats with F12
+    pub search_values: bool,   // Search what formulas give rather than what was typed
+    pub zebra: bool,           // Shade every other row, to follow rows across wide sheets
+    pub locale: Locale,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, ValueEnum)]
+pub enum CopyDelimiter {
+    // Tab-separated values are what Excel and most spreadsheets put on the clipboard, with cells
+    // holding tabs or line breaks quoted like in CSV
+    #[default]
+    Tab,
+    // Comma-separated values, quoted where needed, for tools that expect CSV
+    Comma,
+}
+
+impl CopyDelimiter {
+    pub fn delimiter(&self) -> char {
+        match self {
+            CopyDelimiter::Tab => '\t',
+            CopyDelimiter::Comma => ',',
+        }
+    }
+
+    pub fn join(&self, cells: &[String]) -> String {
+        cells
+            .iter()
+            .map(|c| quote_csv_cell(c, self.delimiter()))
+            .collect::<Vec<String>>()
+            .join(&self.delimiter().to_string())
+    }
+
+    pub fn split(&self, text: &str) -> Vec<Vec<String>> {
+        // The reverse of join, for pasting rows copied with the same delimiter
+        parse_csv(text, self.delimiter())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Locale {
+    pub decimal_separator: char,
+    pub thousands_separator: char,
+    pub csv_delimiter: char,
+}
+
+impl Default for Locale {
+    fn default() -> Self {
+        Self {
+            decimal_separator: '.',
+            thousands_separator: ',',
+            csv_delimiter: ',',
+        }
+    }
+}
+
+impl Locale {
+    pub fn new(
+        decimal_separator: char,
+        thousands_separator: char,
+        csv_delimiter: char,
+    ) -> Result<Locale, String> {
+        // A decimal separator that's also used for anything else makes numbers ambiguous
+        if decimal_separator == thousands_separator {
+            return Err(format!(
+                "The decimal and thousands separators can't both be '{}'",
+                decimal_separator
+            ));
+        }
+        if decimal_separator == csv_delimiter {
+            return Err(format!(
+                "'{}' can't be both the decimal separator and the CSV delimiter",
+                decimal_separator
+            ));
+        }
+        Ok(Locale {
+            decimal_separator,
+            thousands_separator,
+            csv_delimiter,
+        })
+    }
+
+    pub fn parse_number(&self, text: &str) -> Option<f64> {
+        // Parses numbers written in this locale (ie. 1.234,56 or 1,234.56), where thousands
+        // separators are only allowed between proper groups of three digits.
+        let (negative, text) = match text.strip_prefix('-') {
+            Some(rest) => (true, rest),
+            None => (false, text),
+        };
+        let (integer, fraction) = match text.split_once(self.decimal_separator) {
+            Some((integer, fraction)) => (integer, fraction),
+            None => (text, ""),
+        };
+
+        let groups: Vec<&str> = integer.split(self.thousands_separator).collect();
+        if groups.len() > 1
+            && (groups[0].is_empty()
+                || groups[0].len() > 3
+                || groups[1..].iter().any(|g| g.len() != 3))
+        {
+            return None;
+        }
+        let integer = groups.concat();
+        if (integer.is_empty() && fraction.is_empty())
+            || !integer
+                .chars()
+                .chain(fraction.chars())
+                .all(|c| c.is_ascii_digit())
+        {
+            return None;
+        }
+
+        let number = format!("{}.{}", integer, fraction).parse::<f64>().ok()?;
+        Some(if negative { -number } else { number })
+    }
+
+    pub fn format_number(&self, number: &str) -> String {
+        // Numbers are always formatted with a period internally
+        number.replace('.', &self.decimal_separator.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn european_numbers() {
+        let locale = Locale::new(',', '.', ';').unwrap();
+        assert_eq!(locale.parse_number("1.234,56"), Some(1234.56));
+        assert_eq!(locale.parse_number("-0,5"), Some(-0.5));
+        assert_eq!(locale.parse_number("1234"), Some(1234.0));
+        assert_eq!(locale.format_number("1234.56"), "1234,56");
+        // Thousands separators only go between groups of three
+        assert_eq!(locale.parse_number("1.23,4"), None);
+        assert_eq!(locale.parse_number("1,234.56"), None);
+    }
+
+    #[test]
+    fn default_numbers() {
+        let locale = Locale::default();
+        assert_eq!(locale.parse_number("1,234.56"), Some(1234.56));
+        assert_eq!(locale.parse_number("12,34"), None);
+        assert_eq!(locale.parse_number(","), None);
+        assert_eq!(locale.parse_number(""), None);
+    }
+
+    #[test]
+    fn comma_delimited_copies_quote_commas() {
+        let cells = [String::from("1,5"), String::from("plain")];
+        assert_eq!(CopyDelimiter::Comma.join(&cells), "\"1,5\",plain");
+        assert_eq!(CopyDelimiter::Tab.join(&cells), "1,5\tplain");
+    }
+
+    #[test]
+    fn copies_paste_back_as_they_were() {
+        let rows = vec![
+            vec![String::from("a,b"), String::from("tab\there")],
+            vec![String::from("say \"hi\""), String::from("two\nlines")],
+        ];
+        for delimiter in [CopyDelimiter::Tab, CopyDelimiter::Comma] {
+            let text = rows
+                .iter()
+                .map(|r| delimiter.join(r))
+                .collect::<Vec<String>>()
+                .join("\n");
+            assert_eq!(delimiter.split(&text), rows);
+        }
+    }
+
+    #[test]
+    fn separators_must_differ() {
+        assert!(Locale::new(',', ',', ';').is_err());
+        assert!(Locale::new(',', '.', ',').is_err());
+        assert!(Locale::new('.', ',', ',').is_ok());
+    }
 }
@@ -0,0 +1,170 @@
+use std::collections::{HashMap, HashSet};
+
+use rayon::prelude::*;
+
+use crate::formula_functions::{ARRAY_FUNCS, VOLATILE_FUNCS};
+use crate::formulas::{parse_formula, Token, TokenType};
+use crate::spreadsheet::{
+    Spreadsheet, SpreadsheetCell, SPREADSHEET_MAX_COLS, SPREADSHEET_MAX_ROWS,
+};
+
+#[derive(Debug, Default)]
+pub struct DependencyGraph {
+    // Formula cells in an order they can be evaluated in, where the cells of each level only
+    // reference formulas from earlier levels and so can be evaluated at the same time
+    pub levels: Vec<Vec<SpreadsheetCell>>,
+    // Formulas in a circular reference, or referencing one, which have no such order
+    pub unordered: Vec<SpreadsheetCell>,
+}
+
+impl DependencyGraph {
+    pub fn new(spreadsheet: &Spreadsheet) -> DependencyGraph {
+        let formulas: Vec<SpreadsheetCell> = spreadsheet
+            .iter_used_cells()
+            .filter(|(_, value)| value.starts_with("="))
+            .map(|(cell, _)| cell)
+            .collect();
+        let indices: HashMap<&SpreadsheetCell, usize> = formulas
+            .iter()
+            .enumerate()
+            .map(|(idx, cell)| (cell, idx))
+            .collect();
+
+        // Each formula's inputs are the other formulas it references. Parsing for them costs
+        // about as much as evaluating, so it's spread across threads too.
+        let ranges: Vec<Vec<[SpreadsheetCell; 2]>> = formulas
+            .par_iter()
+            .map(|cell| referenced_ranges(&spreadsheet.get_cell(cell)[1..]))
+            .collect();
+        let mut dependents: Vec<Vec<usize>> = vec![Vec::new(); formulas.len()];
+        let mut waiting_on: Vec<usize> = vec![0; formulas.len()];
+        for (idx, ranges) in ranges.into_iter().enumerate() {
+            let mut inputs: HashSet<usize> = HashSet::new();
+            for [start, end] in ranges {
+                let area = (end.row - start.row + 1) * (end.col - start.col + 1);
+                if area <= formulas.len() {
+                    for row in start.row..=end.row {
+                        for col in start.col..=end.col {
+                            inputs.extend(indices.get(&SpreadsheetCell { row, col }));
+                        }
+                    }
+                } else {
+                    // Big ranges (ie. whole columns) are cheaper to check against every formula
+                    inputs.extend(formulas.iter().enumerate().filter_map(|(input, c)| {
+                        let inside = (start.row..=end.row).contains(&c.row)
+                            && (start.col..=end.col).contains(&c.col);
+                        inside.then_some(input)
+                    }));
+                }
+            }
+            waiting_on[idx] = inputs.len();
+            for input in inputs {
+                dependents[input].push(idx);
+            }
+        }
+
+        // Kahn's algorithm, taking every formula that's ready at once as a level
+        let mut levels: Vec<Vec<usize>> = Vec::new();
+        let mut ready: Vec<usize> = (0..formulas.len())
+            .filter(|idx| waiting_on[*idx] == 0)
+            .collect();
+        while !ready.is_empty() {
+            let mut next: Vec<usize> = Vec::new();
+            for idx in &ready {
+                for dependent in &dependents[*idx] {
+                    waiting_on[*dependent] -= 1;
+                    if waiting_on[*dependent] == 0 {
+                        next.push(*dependent);
+                    }
+                }
+            }
+            levels.push(ready);
+            ready = next;
+        }
+
+        DependencyGraph {
+            levels: levels
+                .into_iter()
+                .map(|level| level.into_iter().map(|idx| formulas[idx].clone()).collect())
+                .collect(),
+            unordered: (0..formulas.len())
+                .filter(|idx| waiting_on[*idx] > 0)
+                .map(|idx| formulas[idx].clone())
+                .collect(),
+        }
+    }
+}
+
+pub fn referenced_ranges(formula: &str) -> Vec<[SpreadsheetCell; 2]> {
+    // The ranges a formula (without its =) reads, with single cells as one cell ranges. Ranges
+    // are kept as their corners, rather than every cell in them like when evaluating.
+    match parse_formula(formula) {
+        Ok(parsed) => ranges_in(&parsed),
+        Err(_) => Vec::new(),
+    }
+}
+
+pub fn value_inputs(formula: &str) -> Vec<[SpreadsheetCell; 2]> {
+    // What a formula's value depends on, so that it's only dropped when some of that changes.
+    // Volatile functions depend on the whole sheet, since any edit changes them.
+    let Ok(parsed) = parse_formula(formula) else {
+        return Vec::new();
+    };
+    let mut inputs = ranges_in(&parsed);
+    if parsed.iter().any(|t| {
+        t.token_type == TokenType::Function
+            && VOLATILE_FUNCS.contains(&t.content.to_uppercase().as_str())
+    }) {
+        inputs.push([
+            SpreadsheetCell { row: 0, col: 0 },
+            SpreadsheetCell {
+                row: SPREADSHEET_MAX_ROWS - 1,
+                col: SPREADSHEET_MAX_COLS - 1,
+            },
+        ]);
+    }
+    inputs
+}
+
+pub fn can_spill(formula: &str) -> bool {
+    // Whether a formula (without its =) calls anything returning an array, which it could spill
+    parse_formula(formula).is_ok_and(|parsed| {
+        parsed.iter().any(|t| {
+            t.token_type == TokenType::Function
+                && ARRAY_FUNCS.contains(&t.content.to_uppercase().as_str())
+        })
+    })
+}
+
+fn ranges_in(parsed: &[Token]) -> Vec<[SpreadsheetCell; 2]> {
+    let reference_at = |idx: usize| {
+        parsed
+            .get(idx)
+            .filter(|t| t.token_type == TokenType::Reference)
+            .and_then(|t| t.reference_set.as_ref()?.first().cloned())
+    };
+
+    let mut ranges: Vec<[SpreadsheetCell; 2]> = Vec::new();
+    let mut idx = 0;
+    while idx < parsed.len() {
+        let Some(start) = reference_at(idx) else {
+            idx += 1;
+            continue;
+        };
+        let is_range = parsed
+            .get(idx + 1)
+            .is_some_and(|t| t.token_type == TokenType::Operator && t.content == ":");
+        // Whole columns and rows (ie. A:A) are kept whole, since the data they cover can grow
+        match reference_at(idx + 2).filter(|_| is_range) {
+            Some(end) => {
+                ranges.push(start.bounds(&end));
+                idx += 3;
+            }
+            None => {
+                ranges.push(start.bounds(&start));
+                idx += 1;
+            }
+        }
+    }
+    ranges
+}
@@ -0,0 +1,661 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::config::Locale;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum CellFormat {
+    #[default]
+    General,
+    // Numbers shown multiplied by 100 with a percent sign, ie. 0.05 as 5%
+    Percent,
+    // Numbers shown after a currency symbol, ie. 5 as $5.00
+    Currency(char),
+    // Excel date serials (days since 1899-12-30) shown as dates, ie. 46311 as 2026-10-16
+    Date,
+    // Fractions of a day shown as times, ie. 0.5 as 12:00:00
+    Time,
+    // Numbers in scientific notation with this many decimals, ie. 12345 as 1.23E+04
+    Scientific(u8),
+    // Scientific notation with exponents that are multiples of three, ie. 12345 as 12.35E+03
+    Engineering(u8),
+    // Numbers as a whole part and a fraction with up to this many digits below the line, ie. 2.25
+    // as 2 1/4
+    Fraction(u8),
+}
+
+// The date serial of 1970-01-01, which the date math below counts days from
+const UNIX_EPOCH_SERIAL: i64 = 25569;
+
+pub const CURRENCY_SYMBOLS: [char; 4] = ['$', '€', '£', '¥'];
+
+impl CellFormat {
+    pub fn parse_entry(input: &str, locale: &Locale) -> Option<(String, CellFormat)> {
+        // Recognizes typed numbers that imply a format, like Excel turning 5% into 0.05 shown as a
+        // percentage. Gives the value to store (in the locale) and the format to show it with.
+        let input = input.trim();
+        if let Some(number) = input.strip_suffix('%') {
+            let number = locale.parse_number(number.trim_end())?;
+            return Some((
+                locale.format_number(&(number / 100.0).to_string()),
+                CellFormat::Percent,
+            ));
+        }
+
+        if let Some(serial) = parse_date(input) {
+            return Some((serial.to_string(), CellFormat::Date));
+        }
+        if let Some(serial) = parse_time(input) {
+            return Some((locale.format_number(&serial.to_string()), CellFormat::Time));
+        }
+
+        // Currency symbols go before the number, but after a minus sign if there is one
+        let (negative, rest) = match input.strip_prefix('-') {
+            Some(rest) => (true, rest),
+            None => (false, input),
+        };
+        let symbol = rest
+            .chars()
+            .next()
+            .filter(|c| CURRENCY_SYMBOLS.contains(c))?;
+        let number = locale.parse_number(rest[symbol.len_utf8()..].trim_start())?;
+        let number = if negative { -number } else { number };
+        Some((
+            locale.format_number(&number.to_string()),
+            CellFormat::Currency(symbol),
+        ))
+    }
+
+    pub fn to_entry(&self, value: &str, locale: &Locale) -> String {
+        // The reverse of parse_entry, giving back what would be typed to enter a value
+        match (self, locale.parse_number(value)) {
+            (CellFormat::Percent, Some(number)) => {
+                locale.format_number(&format!("{}%", scale_percent(number)))
+            }
+            (CellFormat::Currency(_), Some(number)) => {
+                self.decorate(&locale.format_number(&number.to_string()))
+            }
+            (CellFormat::Date | CellFormat::Time, Some(number)) => {
+                self.to_date_time(number).unwrap_or(value.to_string())
+            }
+            _ => value.to_string(),
+        }
+    }
+
+    pub fn scale(&self, number: f64) -> f64 {
+        // The number as it's displayed, before decorating it
+        match self {
+            CellFormat::Percent => scale_percent(number),
+            _ => number,
+        }
+    }
+
+    pub fn to_date_time(&self, number: f64) -> Option<String> {
+        // Dates and times are shown in place of the number, rather than decorating it
+        match self {
+            CellFormat::Date => {
+                let (year, month, day) = civil_from_days(number.floor() as i64 - UNIX_EPOCH_SERIAL);
+                Some(format!("{:04}-{:02}-{:02}", year, month, day))
+            }
+            CellFormat::Time => {
+                let seconds = (number.fract().abs() * 86400.0).round() as i64 % 86400;
+                Some(format!(
+                    "{:02}:{:02}:{:02}",
+                    seconds / 3600,
+                    seconds / 60 % 60,
+                    seconds % 60
+                ))
+            }
+            _ => None,
+        }
+    }
+
+    pub fn to_exponent(&self, number: f64) -> Option<String> {
+        // Scientific formats are shown in place of the number too, since they pick their own
+        // rounding. Exponents have a sign and at least two digits, as in Excel.
+        let (decimals, step) = match *self {
+            CellFormat::Scientific(decimals) => (decimals as usize, 1),
+            CellFormat::Engineering(decimals) => (decimals as usize, 3),
+            _ => return None,
+        };
+        let mut exponent = match number {
+            0.0 => 0,
+            // Rounding in Rust's own notation first, so 9.999 to two decimals is 1.00E+01
+            _ => {
+                format!("{:.*e}", decimals, number)
+                    .split_once('e')
+                    .and_then(|(_, exponent)| exponent.parse::<i32>().ok())?
+                    .div_euclid(step)
+                    * step
+            }
+        };
+        let mut mantissa = format!("{:.*}", decimals, number / 10f64.powi(exponent));
+        // Rounding can carry into another digit, ie. 999.99 to one decimal as 1000.0E+00
+        if mantissa.trim_start_matches('-').split('.').next()?.len() > step as usize {
+            exponent += step;
+            mantissa = format!("{:.*}", decimals, number / 10f64.powi(exponent));
+        }
+        let sign = if exponent < 0 { '-' } else { '+' };
+        Some(format!("{}E{}{:02}", mantissa, sign, exponent.abs()))
+    }
+
+    pub fn to_fraction(&self, number: f64) -> Option<String> {
+        // The closest fraction with a small enough denominator, preferring the smallest one. Like
+        // in Excel, fractions that round to nothing or to one are shown as whole numbers.
+        let CellFormat::Fraction(digits) = *self else {
+            return None;
+        };
+        let max_denominator = 10u32.pow(digits as u32) - 1;
+        let mut whole = number.abs().trunc();
+        let fraction = number.abs().fract();
+        let (mut numerator, mut denominator) = (0.0, 1.0);
+        for candidate in 1..=max_denominator {
+            let candidate = candidate as f64;
+            let closest = (fraction * candidate).round();
+            if (fraction - closest / candidate).abs()
+                < (fraction - numerator / denominator).abs() - f64::EPSILON
+            {
+                (numerator, denominator) = (closest, candidate);
+            }
+        }
+        if numerator == denominator {
+            (whole, numerator) = (whole + 1.0, 0.0);
+        }
+
+        let sign = if number < 0.0 && (whole > 0.0 || numerator > 0.0) {
+            "-"
+        } else {
+            ""
+        };
+        Some(match (whole, numerator) {
+            (_, 0.0) => format!("{}{}", sign, whole),
+            (0.0, _) => format!("{}{}/{}", sign, numerator, denominator),
+            _ => format!("{}{} {}/{}", sign, whole, numerator, denominator),
+        })
+    }
+
+    pub fn decorate(&self, number: &str) -> String {
+        // Adds the format's symbols to an already scaled and formatted number
+        match self {
+            CellFormat::General
+            | CellFormat::Date
+            | CellFormat::Time
+            | CellFormat::Scientific(_)
+            | CellFormat::Engineering(_)
+            | CellFormat::Fraction(_) => number.to_string(),
+            CellFormat::Percent => format!("{}%", number),
+            CellFormat::Currency(symbol) => match number.strip_prefix('-') {
+                Some(number) => format!("-{}{}", symbol, number),
+                None => format!("{}{}", symbol, number),
+            },
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct TextStyle {
+    pub bold: bool,
+    pub italic: bool,
+    pub underline: bool,
+}
+
+impl TextStyle {
+    pub fn to_flags(&self) -> String {
+        // A compact form for saving, with a letter for each style that's on (ie. "bu")
+        [(self.bold, 'b'), (self.italic, 'i'), (self.underline, 'u')]
+            .iter()
+            .filter(|(on, _)| *on)
+            .map(|(_, flag)| *flag)
+            .collect()
+    }
+
+    pub fn from_flags(flags: &str) -> TextStyle {
+        TextStyle {
+            bold: flags.contains('b'),
+            italic: flags.contains('i'),
+            underline: flags.contains('u'),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CellColor {
+    Red,
+    Green,
+    Yellow,
+    Blue,
+    Magenta,
+    Cyan,
+    White,
+    Black,
+}
+
+impl CellColor {
+    // The colors offered when picking one, which every terminal can show
+    pub const PALETTE: [CellColor; 8] = [
+        CellColor::Red,
+        CellColor::Green,
+        CellColor::Yellow,
+        CellColor::Blue,
+        CellColor::Magenta,
+        CellColor::Cyan,
+        CellColor::White,
+        CellColor::Black,
+    ];
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct CellColors {
+    pub fg: Option<CellColor>,
+    pub bg: Option<CellColor>,
+}
+
+// Month and weekday names for mmm, mmmm, ddd and dddd in format codes
+const MONTHS: [&str; 12] = [
+    "January",
+    "February",
+    "March",
+    "April",
+    "May",
+    "June",
+    "July",
+    "August",
+    "September",
+    "October",
+    "November",
+    "December",
+];
+const WEEKDAYS: [&str; 7] = [
+    "Sunday",
+    "Monday",
+    "Tuesday",
+    "Wednesday",
+    "Thursday",
+    "Friday",
+    "Saturday",
+];
+
+#[derive(Debug, Clone, PartialEq)]
+enum CodePart {
+    Literal(String),
+    Code(char), // Digit placeholders, separators, % and date letters
+}
+
+pub fn format_with_code(number: f64, code: &str, locale: &Locale) -> String {
+    // Formats a number with a subset of Excel's format codes, for TEXT. A code is either a date
+    // or time (ie. yyyy-mm-dd or h:mm) or a number with any text around it (ie. #,##0.00, 0.0%
+    // or $0), and can have separate sections for negative numbers and zero (ie. 0;(0);-).
+    let sections = split_sections(code);
+    let (section, number, signed) = match sections[..] {
+        [_, negative, ..] if number < 0.0 => (negative, -number, false),
+        [_, _, zero] if number == 0.0 => (zero, number, true),
+        _ => (sections[0], number, true),
+    };
+    if section.eq_ignore_ascii_case("general") || section == "@" {
+        return locale.format_number(&number.to_string());
+    }
+    let parts = code_parts(section);
+    if parts
+        .iter()
+        .any(|part| matches!(part, CodePart::Code('y' | 'm' | 'd' | 'h' | 's')))
+    {
+        format_date_code(number, &parts)
+    } else {
+        format_number_code(number, &parts, locale, signed)
+    }
+}
+
+fn split_sections(code: &str) -> Vec<&str> {
+    // Splits at semicolons that aren't quoted
+    let mut sections: Vec<&str> = Vec::new();
+    let mut start = 0;
+    let mut in_quotes = false;
+    for (idx, c) in code.char_indices() {
+        match c {
+            '"' => in_quotes = !in_quotes,
+            ';' if !in_quotes => {
+                sections.push(&code[start..idx]);
+                start = idx + 1;
+            }
+            _ => (),
+        }
+    }
+    sections.push(&code[start..]);
+    sections
+}
+
+fn code_parts(section: &str) -> Vec<CodePart> {
+    // Quoted and escaped text is always literal, like anything that isn't part of a code
+    let mut parts: Vec<CodePart> = Vec::new();
+    let mut chars = section.chars();
+    while let Some(c) = chars.next() {
+        parts.push(match c {
+            '"' => CodePart::Literal(chars.by_ref().take_while(|c| *c != '"').collect()),
+            '\\' => CodePart::Literal(chars.next().map(String::from).unwrap_or_default()),
+            // Padding the width of the next character, which is close enough to a space
+            '_' => {
+                chars.next();
+                CodePart::Literal(String::from(" "))
+            }
+            '0' | '#' | '?' | ',' | '.' | '%' => CodePart::Code(c),
+            'y' | 'Y' | 'm' | 'M' | 'd' | 'D' | 'h' | 'H' | 's' | 'S' => {
+                CodePart::Code(c.to_ascii_lowercase())
+            }
+            c => CodePart::Literal(c.to_string()),
+        });
+    }
+    parts
+}
+
+fn format_number_code(number: f64, parts: &[CodePart], locale: &Locale, signed: bool) -> String {
+    let is_digit = |part: &CodePart| matches!(part, CodePart::Code('0' | '#' | '?'));
+    let percents = parts
+        .iter()
+        .filter(|part| **part == CodePart::Code('%'))
+        .count();
+    let mut number = number * 100f64.powi(percents as i32);
+    let literal = |part: &CodePart| match part {
+        CodePart::Literal(text) => text.clone(),
+        CodePart::Code(c) => c.to_string(),
+    };
+    // Codes without any digits (ie. - for zero) are just their text
+    let (Some(first), Some(last)) = (
+        parts.iter().position(is_digit),
+        parts.iter().rposition(is_digit),
+    ) else {
+        return parts.iter().map(literal).collect();
+    };
+    // Commas right after the digits scale by a thousand each, ie. 0, for thousands
+    let mut suffix = last + 1;
+    while parts.get(suffix) == Some(&CodePart::Code(',')) {
+        number /= 1000.0;
+        suffix += 1;
+    }
+    let digits = &parts[first..=last];
+    let point = digits.iter().position(|part| *part == CodePart::Code('.'));
+    let (integer_code, fraction_code) = match point {
+        Some(point) => (&digits[..point], &digits[point + 1..]),
+        None => (digits, &digits[digits.len()..]),
+    };
+    let count = |code: &[CodePart], placeholders: &str| {
+        code.iter()
+            .filter(|part| matches!(part, CodePart::Code(c) if placeholders.contains(*c)))
+            .count()
+    };
+
+    // Decimals are rounded to every placeholder, then 0 and ? keep trailing zeros where # doesn't.
+    // Halves round away from zero like in Excel, where formatting alone would round them to even.
+    let decimals = count(fraction_code, "0#?");
+    let rounding_scalar = 10f64.powi(decimals as i32);
+    let rounded = (number.abs() * rounding_scalar).round() / rounding_scalar;
+    let rounded = format!("{:.*}", decimals, rounded);
+    let (integer, fraction) = rounded.split_once('.').unwrap_or((&rounded, ""));
+    let mut fraction = fraction.to_string();
+    while fraction.len() > count(fraction_code, "0?") && fraction.ends_with('0') {
+        fraction.pop();
+    }
+    let min_digits = count(integer_code, "0?");
+    let integer = match integer.trim_start_matches('0') {
+        "" => "0".repeat(min_digits),
+        integer => format!("{:0>1$}", integer, min_digits),
+    };
+    let integer = if integer_code.contains(&CodePart::Code(',')) {
+        group_thousands(&integer, locale.thousands_separator)
+    } else {
+        integer
+    };
+
+    let is_zero = rounded.chars().all(|c| c == '0' || c == '.');
+    let mut formatted = String::new();
+    if signed && number < 0.0 && !is_zero {
+        formatted.push('-');
+    }
+    formatted.extend(parts[..first].iter().map(literal));
+    formatted += &integer;
+    if point.is_some() {
+        formatted.push(locale.decimal_separator);
+        formatted += &fraction;
+    }
+    formatted.extend(parts[suffix..].iter().map(literal));
+    formatted
+}
+
+fn group_thousands(integer: &str, separator: char) -> String {
+    let mut grouped = String::new();
+    for (idx, digit) in integer.chars().enumerate() {
+        if idx > 0 && (integer.len() - idx).is_multiple_of(3) {
+            grouped.push(separator);
+        }
+        grouped.push(digit);
+    }
+    grouped
+}
+
+fn format_date_code(number: f64, parts: &[CodePart]) -> String {
+    let seconds = (number * 86400.0).round() as i64;
+    let days = seconds.div_euclid(86400);
+    let (year, month, day) = civil_from_days(days - UNIX_EPOCH_SERIAL);
+    let time = seconds.rem_euclid(86400);
+    let (hours, minutes, seconds) = (time / 3600, time / 60 % 60, time % 60);
+    // Serial 1 (1900-01-01) was a Sunday
+    let weekday = (days - 1).rem_euclid(7) as usize;
+
+    // Runs of the same letter are one code, ie. yyyy or mm, and anything else is shown as is
+    let mut runs: Vec<(char, usize)> = Vec::new();
+    let mut texts: Vec<String> = vec![String::new()];
+    for part in parts {
+        match part {
+            CodePart::Code(c) if "ymdhs".contains(*c) => match runs.last_mut() {
+                Some((last, len)) if last == c && texts.last().is_some_and(String::is_empty) => {
+                    *len += 1
+                }
+                _ => {
+                    runs.push((*c, 1));
+                    texts.push(String::new());
+                }
+            },
+            CodePart::Code(c) => texts.last_mut().unwrap().push(*c),
+            CodePart::Literal(text) => *texts.last_mut().unwrap() += text,
+        }
+    }
+
+    let mut formatted = texts[0].clone();
+    for (idx, (c, len)) in runs.iter().enumerate() {
+        // An m is minutes right after an h or before an s, and otherwise the month
+        let minute = *c == 'm'
+            && (idx > 0 && runs[idx - 1].0 == 'h'
+                || runs.get(idx + 1).is_some_and(|run| run.0 == 's'));
+        formatted += &match (c, len) {
+            ('y', 1 | 2) => format!("{:02}", year.rem_euclid(100)),
+            ('y', _) => format!("{:04}", year),
+            ('m', 1) if minute => minutes.to_string(),
+            ('m', _) if minute => format!("{:02}", minutes),
+            ('m', 1) => month.to_string(),
+            ('m', 2) => format!("{:02}", month),
+            ('m', 3) => MONTHS[month as usize - 1][..3].to_string(),
+            ('m', _) => MONTHS[month as usize - 1].to_string(),
+            ('d', 1) => day.to_string(),
+            ('d', 2) => format!("{:02}", day),
+            ('d', 3) => WEEKDAYS[weekday][..3].to_string(),
+            ('d', _) => WEEKDAYS[weekday].to_string(),
+            ('h', 1) => hours.to_string(),
+            ('h', _) => format!("{:02}", hours),
+            ('s', 1) => seconds.to_string(),
+            _ => format!("{:02}", seconds),
+        };
+        formatted += &texts[idx + 1];
+    }
+    formatted
+}
+
+pub fn today() -> String {
+    // Today's date, as it would be typed. This is in UTC, since there's no time zone database.
+    let days = now_seconds().div_euclid(86400) as f64;
+    CellFormat::Date
+        .to_date_time(days + UNIX_EPOCH_SERIAL as f64)
+        .unwrap_or_default()
+}
+
+pub fn current_time() -> String {
+    // The current time (in UTC, like today), as it would be typed
+    let seconds = now_seconds().rem_euclid(86400) as f64;
+    CellFormat::Time
+        .to_date_time(seconds / 86400.0)
+        .unwrap_or_default()
+}
+
+fn now_seconds() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs() as i64)
+}
+
+fn parse_date(input: &str) -> Option<i64> {
+    // ISO dates (ie. 2026-10-16) as date serials
+    let mut parts = input.split('-');
+    let (year, month, day) = (parts.next()?, parts.next()?, parts.next()?);
+    if parts.next().is_some()
+        || year.len() != 4
+        || !(1..=2).contains(&month.len())
+        || !(1..=2).contains(&day.len())
+    {
+        return None;
+    }
+    let (year, month, day) = (
+        year.parse::<i64>().ok()?,
+        month.parse::<i64>().ok()?,
+        day.parse::<i64>().ok()?,
+    );
+    if !(1..=12).contains(&month) || day < 1 {
+        return None;
+    }
+    // Dates past the end of their month (ie. 2026-02-30) would otherwise roll over
+    let days = days_from_civil(year, month, day);
+    if civil_from_days(days) != (year, month, day) {
+        return None;
+    }
+    Some(days + UNIX_EPOCH_SERIAL)
+}
+
+fn parse_time(input: &str) -> Option<f64> {
+    // 24-hour times with or without seconds (ie. 14:05 or 14:05:09) as fractions of a day
+    let parts: Vec<u32> = input
+        .split(':')
+        .map(|p| (p.len() <= 2).then(|| p.parse().ok()).flatten())
+        .collect::<Option<_>>()?;
+    let (hours, minutes, seconds) = match parts[..] {
+        [hours, minutes] => (hours, minutes, 0),
+        [hours, minutes, seconds] => (hours, minutes, seconds),
+        _ => return None,
+    };
+    if hours >= 24 || minutes >= 60 || seconds >= 60 {
+        return None;
+    }
+    Some((hours * 3600 + minutes * 60 + seconds) as f64 / 86400.0)
+}
+
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    // Days since 1970-01-01 of a date in the proleptic Gregorian calendar. This and its inverse
+    // are Howard Hinnant's algorithms, which work in 400 year eras starting in March.
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146097 + day_of_era - 719468
+}
+
+fn civil_from_days(days: i64) -> (i64, i64, i64) {
+    let days = days + 719468;
+    let era = days.div_euclid(146097);
+    let day_of_era = days - era * 146097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_index + 2) / 5 + 1;
+    let month = if month_index < 10 {
+        month_index + 3
+    } else {
+        month_index - 9
+    };
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year, month, day)
+}
+
+fn scale_percent(number: f64) -> f64 {
+    // Multiplying by 100 can leave float artifacts (ie. 0.07 * 100 = 7.000000000000001), which
+    // are rounded away since they'd show up when editing the cell.
+    ((number * 100.0) * 1e9).round() / 1e9
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::formulas::eval_formula;
+    use crate::spreadsheet::Spreadsheet;
+
+    #[test]
+    fn format_codes_group_and_round() {
+        let locale = Locale::default();
+        assert_eq!(format_with_code(1234.5, "#,##0.00", &locale), "1,234.50");
+        assert_eq!(format_with_code(-1234.5, "#,##0", &locale), "-1,235");
+        assert_eq!(format_with_code(0.5, "0.00", &locale), "0.50");
+        assert_eq!(format_with_code(2.5, "0", &locale), "3");
+        assert_eq!(format_with_code(0.125, "0.00", &locale), "0.13");
+        // Written in the sheet's own separators
+        let locale = Locale::new(',', '.', ';').unwrap();
+        assert_eq!(format_with_code(1234.5, "#,##0.00", &locale), "1.234,50");
+
+        let text = eval_formula("TEXT(1234.5,\"#,##0.00\")", &Spreadsheet::new(), None);
+        assert_eq!(text.unwrap().content, "1,234.50");
+    }
+
+    #[test]
+    fn percent_codes_scale_by_a_hundred() {
+        let locale = Locale::default();
+        assert_eq!(format_with_code(0.256, "0.0%", &locale), "25.6%");
+        assert_eq!(format_with_code(0.5, "0%", &locale), "50%");
+        assert_eq!(format_with_code(-0.125, "0.00%", &locale), "-12.50%");
+        assert_eq!(format_with_code(12.5, "#,##0%", &locale), "1,250%");
+    }
+
+    #[test]
+    fn exponents_round_and_carry() {
+        let scientific = CellFormat::Scientific(2);
+        assert_eq!(scientific.to_exponent(123456789.0).unwrap(), "1.23E+08");
+        assert_eq!(scientific.to_exponent(0.000123).unwrap(), "1.23E-04");
+        assert_eq!(scientific.to_exponent(-4.5e300).unwrap(), "-4.50E+300");
+        assert_eq!(scientific.to_exponent(0.0).unwrap(), "0.00E+00");
+        // Rounding up to the next power of ten moves the exponent along with it
+        assert_eq!(
+            CellFormat::Scientific(1).to_exponent(999.99).unwrap(),
+            "1.0E+03"
+        );
+        assert_eq!(
+            CellFormat::Engineering(1).to_exponent(999999.0).unwrap(),
+            "1.0E+06"
+        );
+        assert_eq!(
+            CellFormat::Engineering(2).to_exponent(0.0125).unwrap(),
+            "12.50E-03"
+        );
+        assert_eq!(CellFormat::General.to_exponent(1.0), None);
+    }
+
+    #[test]
+    fn fractions_find_the_closest_denominator() {
+        let fraction = |number| CellFormat::Fraction(1).to_fraction(number).unwrap();
+        assert_eq!(fraction(0.5), "1/2");
+        assert_eq!(fraction(2.25), "2 1/4");
+        assert_eq!(fraction(-0.5), "-1/2");
+        assert_eq!(fraction(-2.25), "-2 1/4");
+        assert_eq!(fraction(3.0), "3");
+        assert_eq!(fraction(0.0), "0");
+        // Fractions too small to show round down to the whole number, or up to the next one
+        assert_eq!(fraction(5.01), "5");
+        assert_eq!(fraction(4.99), "5");
+        assert_eq!(fraction(-0.01), "0");
+        assert_eq!(fraction(0.333), "1/3");
+        assert_eq!(CellFormat::Fraction(2).to_fraction(0.3125).unwrap(), "5/16");
+    }
+}
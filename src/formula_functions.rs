@@ -1,8 +1,9 @@
-use std::{collections::HashMap, f32::consts::PI, sync::OnceLock};
+use std::{collections::HashMap, f64::consts::PI, sync::OnceLock};
 
 use crate::{
+    formats::{format_with_code, CellFormat},
     formulas::{Token, TokenType},
-    spreadsheet::Spreadsheet,
+    spreadsheet::{is_subtotal, Spreadsheet},
 };
 
 pub fn get_funcs() -> &'static HashMap<&'static str, &'static (dyn FormulaFunction + Sync)> {
@@ -11,15 +12,46 @@ pub fn get_funcs() -> &'static HashMap<&'static str, &'static (dyn FormulaFuncti
         let mut m: HashMap<&str, &(dyn FormulaFunction + Sync)> = HashMap::new();
         m.insert("SUM", &Sum {});
         m.insert("SQRT", &Sqrt {});
+        m.insert("CEILING.MATH", &CeilingMath {});
         m.insert("IF", &If {});
         m.insert("PI", &Pi {});
         m.insert("RAND", &Rand {});
         m.insert("AVERAGE", &Average {});
         m.insert("MEDIAN", &Median {});
+        m.insert("MIN", &Min {});
+        m.insert("MAX", &Max {});
+        m.insert("LARGE", &Large {});
+        m.insert("SMALL", &Small {});
+        m.insert("RANK", &Rank {});
+        m.insert("COUNT", &Count {});
+        m.insert("COUNTA", &CountA {});
+        m.insert("COUNTBLANK", &CountBlank {});
+        m.insert("COUNTIF", &CountIf {});
+        m.insert("COUNTIFS", &CountIfs {});
+        m.insert("SEQUENCE", &Sequence {});
+        m.insert("TRANSPOSE", &Transpose {});
+        m.insert("TEXT", &Text {});
+        m.insert("VALUE", &Value {});
+        m.insert("N", &N {});
+        m.insert("CHAR", &Char {});
+        m.insert("CODE", &Code {});
+        m.insert("REPT", &Rept {});
+        m.insert("ISEVEN", &IsEven {});
+        m.insert("ISODD", &IsOdd {});
+        m.insert("EVEN", &Even {});
+        m.insert("ODD", &Odd {});
+        m.insert("SUBTOTAL", &Subtotal {});
         m
     })
 }
 
+// Functions with a different result every time, which are evaluated again after every edit like
+// in Excel
+pub const VOLATILE_FUNCS: [&str; 1] = ["RAND"];
+
+// Functions returning arrays, so the only ones whose formulas can spill
+pub const ARRAY_FUNCS: [&str; 2] = ["SEQUENCE", "TRANSPOSE"];
+
 pub fn get_func(name: &str) -> Option<&&(dyn FormulaFunction + Sync)> {
     return get_funcs().get(name);
 }
@@ -28,54 +60,88 @@ pub trait FormulaFunction {
     fn call(&self, args: &[Token], spreadsheet: &Spreadsheet) -> Result<Vec<Token>, ()>;
 }
 
+fn collect_nums(args: &[Token], spreadsheet: &Spreadsheet) -> Vec<f64> {
+    // Flattens literal arguments and referenced ranges into a list of their numeric values
+    let mut nums: Vec<f64> = Vec::new();
+    for arg in args {
+        // Ranges are checked first, since a range would otherwise be read as its first cell
+        if let Some(ref_set) = &arg.reference_set {
+            // Cells whose formulas can't be evaluated (ie. =SUM(A1,) are skipped like text
+            nums.extend(
+                ref_set
+                    .iter()
+                    .filter_map(|r| spreadsheet.get_cell_value(&r.get_cell()).ok())
+                    .filter(|t| t.is_number(spreadsheet))
+                    .map(|t| t.as_f64(spreadsheet)),
+            );
+        } else if let Some(values) = &arg.array {
+            nums.extend(
+                values
+                    .iter()
+                    .flatten()
+                    .filter(|t| t.is_number(spreadsheet))
+                    .map(|t| t.as_f64(spreadsheet)),
+            );
+        } else if arg.is_number(spreadsheet) {
+            nums.push(arg.as_f64(spreadsheet));
+        }
+    }
+    nums
+}
+
 struct Sum;
 impl FormulaFunction for Sum {
     fn call(&self, args: &[Token], spreadsheet: &Spreadsheet) -> Result<Vec<Token>, ()> {
-        let mut nums: Vec<f32> = Vec::new();
-        for arg in args {
-            if arg.is_number(spreadsheet) {
-                nums.push(arg.as_f32(spreadsheet));
-            }
-            if let Some(ref_set) = &arg.reference_set {
-                let mut referenced_nums: Vec<f32> = ref_set
-                    .iter()
-                    .filter(|r| {
-                        spreadsheet
-                            .get_cell_value(&r.get_cell())
-                            .unwrap()
-                            .is_number(spreadsheet)
-                    })
-                    .map(|r| {
-                        spreadsheet
-                            .get_cell_value(&r.get_cell())
-                            .unwrap()
-                            .as_f32(spreadsheet)
-                    })
-                    .collect();
-
-                nums.append(&mut referenced_nums);
-            }
-        }
+        let nums = collect_nums(args, spreadsheet);
+        // Adding zero turns the -0 that summing nothing gives into 0
         Ok(vec![Token::new(
             TokenType::Number,
-            nums.iter().sum::<f32>().to_string(),
+            (nums.iter().sum::<f64>() + 0.0).to_string(),
         )])
     }
 }
 
 struct Sqrt;
 impl FormulaFunction for Sqrt {
-    fn call(&self, args: &[Token], spreadsheet: &Spreadsheet) -> Result<Vec<Token>, ()> {
+    fn call(&self, args: &[Token], _spreadsheet: &Spreadsheet) -> Result<Vec<Token>, ()> {
         if args.len() == 1 && args[0].token_type == TokenType::Number {
             return Ok(vec![Token::new(
                 TokenType::Number,
-                args[0].content.parse::<f32>().unwrap().sqrt().to_string(),
+                args[0].content.parse::<f64>().unwrap().sqrt().to_string(),
             )]);
         }
         return Err(());
     }
 }
 
+struct CeilingMath;
+impl FormulaFunction for CeilingMath {
+    fn call(&self, args: &[Token], spreadsheet: &Spreadsheet) -> Result<Vec<Token>, ()> {
+        // CEILING.MATH(number, [significance], [mode])
+        if args.is_empty() || args.len() > 3 || !args.iter().all(|a| a.is_number(spreadsheet)) {
+            return Err(());
+        }
+        let number = args[0].as_f64(spreadsheet);
+        let significance = args.get(1).map_or(1.0, |a| a.as_f64(spreadsheet).abs());
+        let away_from_zero = args.get(2).is_some_and(|a| a.as_f64(spreadsheet) != 0.0);
+
+        if significance == 0.0 {
+            return Ok(vec![Token::new(TokenType::Number, String::from("0"))]);
+        }
+        let multiples = number / significance;
+        // Negative numbers round towards zero unless a nonzero mode is given
+        let rounded = if number < 0.0 && away_from_zero {
+            multiples.floor()
+        } else {
+            multiples.ceil()
+        };
+        Ok(vec![Token::new(
+            TokenType::Number,
+            (rounded * significance).to_string(),
+        )])
+    }
+}
+
 struct If;
 impl FormulaFunction for If {
     fn call(&self, args: &[Token], spreadsheet: &Spreadsheet) -> Result<Vec<Token>, ()> {
@@ -89,7 +155,7 @@ impl FormulaFunction for If {
             return Err(());
         }
 
-        if condition.as_f32(spreadsheet) == 1.0 {
+        if condition.as_f64(spreadsheet) == 1.0 {
             return Ok(vec![args[1].clone()]);
         } else {
             return Ok(vec![args
@@ -102,7 +168,7 @@ impl FormulaFunction for If {
 
 struct Pi;
 impl FormulaFunction for Pi {
-    fn call(&self, args: &[Token], spreadsheet: &Spreadsheet) -> Result<Vec<Token>, ()> {
+    fn call(&self, args: &[Token], _spreadsheet: &Spreadsheet) -> Result<Vec<Token>, ()> {
         if args.len() > 0 {
             return Err(());
         }
@@ -112,7 +178,7 @@ impl FormulaFunction for Pi {
 
 struct Rand;
 impl FormulaFunction for Rand {
-    fn call(&self, args: &[Token], spreadsheet: &Spreadsheet) -> Result<Vec<Token>, ()> {
+    fn call(&self, args: &[Token], _spreadsheet: &Spreadsheet) -> Result<Vec<Token>, ()> {
         if args.len() > 0 {
             return Err(());
         }
@@ -127,67 +193,44 @@ impl FormulaFunction for Rand {
 struct Average;
 impl FormulaFunction for Average {
     fn call(&self, args: &[Token], spreadsheet: &Spreadsheet) -> Result<Vec<Token>, ()> {
-        let mut nums: Vec<f32> = Vec::new();
-        for arg in args {
-            if arg.is_number(spreadsheet) {
-                nums.push(arg.as_f32(spreadsheet));
-            }
-            if let Some(ref_set) = &arg.reference_set {
-                let mut referenced_nums: Vec<f32> = ref_set
-                    .iter()
-                    .filter(|r| {
-                        spreadsheet
-                            .get_cell_value(&r.get_cell())
-                            .unwrap()
-                            .is_number(spreadsheet)
-                    })
-                    .map(|r| {
-                        spreadsheet
-                            .get_cell_value(&r.get_cell())
-                            .unwrap()
-                            .as_f32(spreadsheet)
-                    })
-                    .collect();
-
-                nums.append(&mut referenced_nums);
-            }
+        let nums = collect_nums(args, spreadsheet);
+        if nums.is_empty() {
+            return Ok(vec![Token::new(TokenType::Error, String::from("#DIV/0!"))]);
         }
         Ok(vec![Token::new(
             TokenType::Number,
-            (nums.iter().sum::<f32>() / nums.len() as f32).to_string(),
+            (nums.iter().sum::<f64>() / nums.len() as f64).to_string(),
         )])
     }
 }
 
+struct Min;
+impl FormulaFunction for Min {
+    fn call(&self, args: &[Token], spreadsheet: &Spreadsheet) -> Result<Vec<Token>, ()> {
+        // Like Excel, nothing to compare gives 0
+        let nums = collect_nums(args, spreadsheet);
+        let min = nums.into_iter().reduce(f64::min).unwrap_or(0.0);
+        Ok(vec![Token::new(TokenType::Number, min.to_string())])
+    }
+}
+
+struct Max;
+impl FormulaFunction for Max {
+    fn call(&self, args: &[Token], spreadsheet: &Spreadsheet) -> Result<Vec<Token>, ()> {
+        let nums = collect_nums(args, spreadsheet);
+        let max = nums.into_iter().reduce(f64::max).unwrap_or(0.0);
+        Ok(vec![Token::new(TokenType::Number, max.to_string())])
+    }
+}
+
 struct Median;
 impl FormulaFunction for Median {
     fn call(&self, args: &[Token], spreadsheet: &Spreadsheet) -> Result<Vec<Token>, ()> {
-        let mut nums: Vec<f32> = Vec::new();
-        for arg in args {
-            if arg.is_number(spreadsheet) {
-                nums.push(arg.as_f32(spreadsheet));
-            }
-            if let Some(ref_set) = &arg.reference_set {
-                let mut referenced_nums: Vec<f32> = ref_set
-                    .iter()
-                    .filter(|r| {
-                        spreadsheet
-                            .get_cell_value(&r.get_cell())
-                            .unwrap()
-                            .is_number(spreadsheet)
-                    })
-                    .map(|r| {
-                        spreadsheet
-                            .get_cell_value(&r.get_cell())
-                            .unwrap()
-                            .as_f32(spreadsheet)
-                    })
-                    .collect();
-
-                nums.append(&mut referenced_nums);
-            }
+        let mut nums = collect_nums(args, spreadsheet);
+        if nums.is_empty() {
+            return Ok(vec![Token::new(TokenType::Error, String::from("#NUM!"))]);
         }
-        nums.sort_by(|a, b| a.partial_cmp(b).unwrap());
+        nums.sort_by(f64::total_cmp);
         let middle = match nums.len() % 2 {
             1 => {
                 // Odd number of elements
@@ -195,13 +238,761 @@ impl FormulaFunction for Median {
             }
             0 => {
                 // Even number of elements
-                (nums[nums.len() / 2] + nums[nums.len() / 2 - 1]) / 2f32
+                (nums[nums.len() / 2] + nums[nums.len() / 2 - 1]) / 2f64
             }
             _ => {
                 // Never reached
-                0f32
+                0f64
             }
         };
         Ok(vec![Token::new(TokenType::Number, middle.to_string())])
     }
 }
+
+fn range_values(arg: &Token, spreadsheet: &Spreadsheet) -> Vec<Token> {
+    // Single cell references are already reduced to their values by the time they reach a
+    // function, so anything without a reference set is treated as a 1x1 range.
+    if let Some(cells) = arg.referenced_cells() {
+        cells
+            .iter()
+            .map(|c| spreadsheet.get_cell_value(c).unwrap_or_default())
+            .collect()
+    } else {
+        vec![arg.clone()]
+    }
+}
+
+fn arg_values(args: &[Token], spreadsheet: &Spreadsheet) -> Vec<Token> {
+    // Every value across the arguments, with ranges and arrays flattened into their values
+    args.iter()
+        .flat_map(|arg| match &arg.array {
+            Some(values) => values.iter().flatten().cloned().collect(),
+            None => range_values(arg, spreadsheet),
+        })
+        .collect()
+}
+
+fn range_shape(arg: &Token) -> (usize, usize) {
+    // Returns the (rows, cols) of the bounding box of a range
+    if let Some(cells) = arg.referenced_cells() {
+        if let (Some(min_row), Some(max_row), Some(min_col), Some(max_col)) = (
+            cells.iter().map(|c| c.row).min(),
+            cells.iter().map(|c| c.row).max(),
+            cells.iter().map(|c| c.col).min(),
+            cells.iter().map(|c| c.col).max(),
+        ) {
+            return (max_row - min_row + 1, max_col - min_col + 1);
+        }
+    }
+    (1, 1)
+}
+
+pub fn meets_criteria(value: &Token, criteria: &str) -> bool {
+    // Parses Excel's criteria syntax (">=5", "<>apple", "=3", "apple", "*app*", etc.) and tests
+    // the value against it. Numeric criteria only ever match numeric values, while text is
+    // compared case-insensitively, with * and ? as wildcards for equality, as Excel does.
+    let (operator, operand) = ["<>", ">=", "<=", "=", "<", ">"]
+        .iter()
+        .find_map(|op| criteria.strip_prefix(op).map(|rest| (*op, rest)))
+        .unwrap_or(("=", criteria));
+
+    if let Ok(target) = operand.parse::<f64>() {
+        let Ok(number) = value.content.parse::<f64>() else {
+            return operator == "<>";
+        };
+        match operator {
+            "=" => number == target,
+            "<>" => number != target,
+            "<" => number < target,
+            ">" => number > target,
+            "<=" => number <= target,
+            ">=" => number >= target,
+            _ => false,
+        }
+    } else {
+        let text = value.content.to_uppercase();
+        let operand = operand.to_uppercase();
+        match operator {
+            "=" => matches_wildcards(&text, &operand),
+            "<>" => !matches_wildcards(&text, &operand),
+            "<" => text < operand,
+            ">" => text > operand,
+            "<=" => text <= operand,
+            ">=" => text >= operand,
+            _ => false,
+        }
+    }
+}
+
+fn matches_wildcards(text: &str, pattern: &str) -> bool {
+    // Whether text matches a pattern where * is any run of characters and ? any one. A failed
+    // match goes back to the last *, letting it take one more character.
+    let (text, pattern): (Vec<char>, Vec<char>) =
+        (text.chars().collect(), pattern.chars().collect());
+    let (mut t, mut p) = (0, 0);
+    let mut last_star: Option<(usize, usize)> = None;
+    while t < text.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                last_star = Some((p, t));
+                p += 1;
+            }
+            Some(c) if *c == '?' || *c == text[t] => {
+                t += 1;
+                p += 1;
+            }
+            _ => match last_star {
+                Some((star, taken)) => {
+                    last_star = Some((star, taken + 1));
+                    p = star + 1;
+                    t = taken + 1;
+                }
+                None => return false,
+            },
+        }
+    }
+    pattern[p..].iter().all(|c| *c == '*')
+}
+
+struct Rank;
+impl FormulaFunction for Rank {
+    fn call(&self, args: &[Token], spreadsheet: &Spreadsheet) -> Result<Vec<Token>, ()> {
+        if args.len() < 2 || args.len() > 3 || !args[0].is_number(spreadsheet) {
+            return Err(());
+        }
+        let number = args[0].as_f64(spreadsheet);
+        let nums = collect_nums(&args[1..2], spreadsheet);
+        let ascending = args.get(2).is_some_and(|t| t.as_f64(spreadsheet) != 0.0);
+
+        if !nums.contains(&number) {
+            return Ok(vec![Token::new(TokenType::Error, String::from("#N/A"))]);
+        }
+
+        // Ties share the highest rank, so only strictly better values are counted
+        let rank = 1 + nums
+            .iter()
+            .filter(|n| {
+                if ascending {
+                    **n < number
+                } else {
+                    **n > number
+                }
+            })
+            .count();
+        Ok(vec![Token::new(TokenType::Number, rank.to_string())])
+    }
+}
+
+struct Count;
+impl FormulaFunction for Count {
+    fn call(&self, args: &[Token], spreadsheet: &Spreadsheet) -> Result<Vec<Token>, ()> {
+        // Only numbers count, not blanks, text or booleans
+        let numbers = arg_values(args, spreadsheet)
+            .iter()
+            .filter(|t| t.token_type == TokenType::Number)
+            .count();
+        Ok(vec![Token::new(TokenType::Number, numbers.to_string())])
+    }
+}
+
+struct CountA;
+impl FormulaFunction for CountA {
+    fn call(&self, args: &[Token], spreadsheet: &Spreadsheet) -> Result<Vec<Token>, ()> {
+        // Anything that isn't blank counts, errors included
+        let values = arg_values(args, spreadsheet)
+            .iter()
+            .filter(|t| !t.content.is_empty())
+            .count();
+        Ok(vec![Token::new(TokenType::Number, values.to_string())])
+    }
+}
+
+struct CountBlank;
+impl FormulaFunction for CountBlank {
+    fn call(&self, args: &[Token], spreadsheet: &Spreadsheet) -> Result<Vec<Token>, ()> {
+        if args.len() != 1 {
+            return Err(());
+        }
+        let blanks = range_values(&args[0], spreadsheet)
+            .iter()
+            .filter(|t| t.content.is_empty())
+            .count();
+        Ok(vec![Token::new(TokenType::Number, blanks.to_string())])
+    }
+}
+
+struct CountIf;
+impl FormulaFunction for CountIf {
+    fn call(&self, args: &[Token], spreadsheet: &Spreadsheet) -> Result<Vec<Token>, ()> {
+        if args.len() != 2 {
+            return Err(());
+        }
+        CountIfs.call(args, spreadsheet)
+    }
+}
+
+struct CountIfs;
+impl FormulaFunction for CountIfs {
+    fn call(&self, args: &[Token], spreadsheet: &Spreadsheet) -> Result<Vec<Token>, ()> {
+        if args.len() < 2 || !args.len().is_multiple_of(2) {
+            return Err(());
+        }
+
+        // Every range has to be the same shape, so that the cells line up with one another
+        let shape = range_shape(&args[0]);
+        if args.chunks(2).any(|pair| range_shape(&pair[0]) != shape) {
+            return Ok(vec![Token::new(TokenType::Error, String::from("#VALUE!"))]);
+        }
+
+        let mut matches = vec![true; shape.0 * shape.1];
+        for pair in args.chunks(2) {
+            let criteria = pair[1].as_string(spreadsheet);
+            for (matched, value) in matches.iter_mut().zip(range_values(&pair[0], spreadsheet)) {
+                *matched = *matched && meets_criteria(&value, &criteria);
+            }
+        }
+
+        Ok(vec![Token::new(
+            TokenType::Number,
+            matches.iter().filter(|m| **m).count().to_string(),
+        )])
+    }
+}
+
+fn nth_num(args: &[Token], spreadsheet: &Spreadsheet, largest: bool) -> Result<Vec<Token>, ()> {
+    // Shared implementation of LARGE and SMALL, where k is 1-indexed
+    if args.len() != 2 || !args[1].is_number(spreadsheet) {
+        return Err(());
+    }
+    let mut nums = collect_nums(&args[..1], spreadsheet);
+    nums.sort_by(f64::total_cmp);
+    if largest {
+        nums.reverse();
+    }
+
+    let k = args[1].as_f64(spreadsheet).trunc();
+    if k < 1.0 || k as usize > nums.len() {
+        return Ok(vec![Token::new(TokenType::Error, String::from("#NUM!"))]);
+    }
+    Ok(vec![Token::new(
+        TokenType::Number,
+        nums[k as usize - 1].to_string(),
+    )])
+}
+
+struct Large;
+impl FormulaFunction for Large {
+    fn call(&self, args: &[Token], spreadsheet: &Spreadsheet) -> Result<Vec<Token>, ()> {
+        nth_num(args, spreadsheet, true)
+    }
+}
+
+struct Small;
+impl FormulaFunction for Small {
+    fn call(&self, args: &[Token], spreadsheet: &Spreadsheet) -> Result<Vec<Token>, ()> {
+        nth_num(args, spreadsheet, false)
+    }
+}
+
+// As many numbers as fill a whole column, which keeps SEQUENCE from filling memory
+const MAX_SEQUENCE_LEN: usize = 2usize.pow(20);
+
+struct Sequence;
+impl FormulaFunction for Sequence {
+    fn call(&self, args: &[Token], spreadsheet: &Spreadsheet) -> Result<Vec<Token>, ()> {
+        // SEQUENCE(rows, [cols], [start], [step])
+        if args.is_empty() || args.len() > 4 || !args.iter().all(|a| a.is_number(spreadsheet)) {
+            return Err(());
+        }
+        let rows = args[0].as_f64(spreadsheet).trunc();
+        let cols = args.get(1).map_or(1.0, |a| a.as_f64(spreadsheet).trunc());
+        let start = args.get(2).map_or(1.0, |a| a.as_f64(spreadsheet));
+        let step = args.get(3).map_or(1.0, |a| a.as_f64(spreadsheet));
+
+        if rows < 1.0 || cols < 1.0 {
+            return Ok(vec![Token::new(TokenType::Error, String::from("#VALUE!"))]);
+        }
+        // Anything bigger than the sheet could never spill anyway
+        if rows > spreadsheet.max_rows() as f64 || cols > spreadsheet.max_cols() as f64 {
+            return Ok(vec![Token::new(TokenType::Error, String::from("#SPILL!"))]);
+        }
+        if rows * cols > MAX_SEQUENCE_LEN as f64 {
+            return Ok(vec![Token::new(TokenType::Error, String::from("#NUM!"))]);
+        }
+
+        let (rows, cols) = (rows as usize, cols as usize);
+        let values = (0..rows)
+            .map(|row| {
+                (0..cols)
+                    .map(|col| {
+                        let number = start + step * (row * cols + col) as f64;
+                        Token::new(TokenType::Number, number.to_string())
+                    })
+                    .collect()
+            })
+            .collect();
+        Ok(vec![Token::array(values)])
+    }
+}
+
+struct Transpose;
+impl FormulaFunction for Transpose {
+    fn call(&self, args: &[Token], spreadsheet: &Spreadsheet) -> Result<Vec<Token>, ()> {
+        if args.len() != 1 {
+            return Err(());
+        }
+        let values: Vec<Vec<Token>> = if let Some(values) = &args[0].array {
+            values.clone()
+        } else {
+            let (_, cols) = range_shape(&args[0]);
+            range_values(&args[0], spreadsheet)
+                .chunks(cols)
+                .map(|row| row.to_vec())
+                .collect()
+        };
+
+        let cols = values.iter().map(|r| r.len()).max().unwrap_or(0);
+        let transposed = (0..cols)
+            .map(|col| {
+                values
+                    .iter()
+                    .map(|row| row.get(col).cloned().unwrap_or_default())
+                    .collect()
+            })
+            .collect();
+        Ok(vec![Token::array(transposed)])
+    }
+}
+
+struct Text;
+impl FormulaFunction for Text {
+    fn call(&self, args: &[Token], spreadsheet: &Spreadsheet) -> Result<Vec<Token>, ()> {
+        // TEXT(value, format_code), where text that isn't a number is left as it is
+        if args.len() != 2 {
+            return Err(());
+        }
+        let text = if args[0].is_number(spreadsheet) {
+            format_with_code(
+                args[0].as_f64(spreadsheet),
+                &args[1].as_string(spreadsheet),
+                &spreadsheet.locale,
+            )
+        } else {
+            args[0].as_string(spreadsheet)
+        };
+        Ok(vec![Token::new(TokenType::String, text)])
+    }
+}
+
+struct Value;
+impl FormulaFunction for Value {
+    fn call(&self, args: &[Token], spreadsheet: &Spreadsheet) -> Result<Vec<Token>, ()> {
+        // VALUE(text), which reads text the way a typed entry is read, so currencies, percents,
+        // dates and times all become numbers. Blank cells are 0, like they are for arithmetic.
+        if args.len() != 1 {
+            return Err(());
+        }
+        if args[0].token_type == TokenType::Boolean {
+            return Ok(vec![Token::new(TokenType::Error, String::from("#VALUE!"))]);
+        }
+        let text = args[0].as_string(spreadsheet);
+        let text = text.trim();
+        let locale = &spreadsheet.locale;
+        let number = if text.is_empty() {
+            Some(0.0)
+        } else {
+            text.parse::<f64>()
+                .ok()
+                .or_else(|| locale.parse_number(text))
+                .or_else(|| {
+                    CellFormat::parse_entry(text, locale)
+                        .and_then(|(value, _)| locale.parse_number(&value))
+                })
+        };
+        Ok(vec![match number {
+            Some(number) => Token::new(TokenType::Number, number.to_string()),
+            None => Token::new(TokenType::Error, String::from("#VALUE!")),
+        }])
+    }
+}
+
+struct N;
+impl FormulaFunction for N {
+    fn call(&self, args: &[Token], spreadsheet: &Spreadsheet) -> Result<Vec<Token>, ()> {
+        // N(value), where numbers stay as they are, TRUE and FALSE are 1 and 0, and text is 0
+        if args.len() != 1 {
+            return Err(());
+        }
+        let number = match args[0].token_type {
+            TokenType::Number | TokenType::Boolean => args[0].as_f64(spreadsheet),
+            _ => 0.0,
+        };
+        Ok(vec![Token::new(TokenType::Number, number.to_string())])
+    }
+}
+
+struct Char;
+impl FormulaFunction for Char {
+    fn call(&self, args: &[Token], spreadsheet: &Spreadsheet) -> Result<Vec<Token>, ()> {
+        // CHAR(number), which takes any Unicode code point rather than only Excel's 1 to 255
+        if args.len() != 1 {
+            return Err(());
+        }
+        let code = args[0].as_f64(spreadsheet).trunc();
+        let char = Some(code)
+            .filter(|code| {
+                args[0].is_number(spreadsheet) && *code >= 1.0 && *code <= u32::MAX as f64
+            })
+            .and_then(|code| char::from_u32(code as u32));
+        Ok(vec![match char {
+            Some(char) => Token::new(TokenType::String, char.to_string()),
+            None => Token::new(TokenType::Error, String::from("#VALUE!")),
+        }])
+    }
+}
+
+struct Code;
+impl FormulaFunction for Code {
+    fn call(&self, args: &[Token], spreadsheet: &Spreadsheet) -> Result<Vec<Token>, ()> {
+        // CODE(text), the code point of the first character, so CHAR(CODE(text)) gives it back
+        if args.len() != 1 {
+            return Err(());
+        }
+        Ok(vec![match args[0].as_string(spreadsheet).chars().next() {
+            Some(char) => Token::new(TokenType::Number, (char as u32).to_string()),
+            None => Token::new(TokenType::Error, String::from("#VALUE!")),
+        }])
+    }
+}
+
+// Excel's limit on text in a cell, which also keeps REPT from filling memory
+const MAX_TEXT_LEN: usize = 32767;
+
+struct Rept;
+impl FormulaFunction for Rept {
+    fn call(&self, args: &[Token], spreadsheet: &Spreadsheet) -> Result<Vec<Token>, ()> {
+        // REPT(text, count)
+        if args.len() != 2 {
+            return Err(());
+        }
+        let text = args[0].as_string(spreadsheet);
+        let count = args[1].as_f64(spreadsheet).trunc();
+        if !args[1].is_number(spreadsheet)
+            || count < 0.0
+            || count * text.chars().count() as f64 > MAX_TEXT_LEN as f64
+        {
+            return Ok(vec![Token::new(TokenType::Error, String::from("#VALUE!"))]);
+        }
+        Ok(vec![Token::new(
+            TokenType::String,
+            text.repeat(count as usize),
+        )])
+    }
+}
+
+fn single_number(args: &[Token], spreadsheet: &Spreadsheet) -> Result<Option<f64>, ()> {
+    // The one numeric argument of functions like ISEVEN, or None when it isn't a number
+    if args.len() != 1 {
+        return Err(());
+    }
+    Ok(Some(args[0].as_f64(spreadsheet)).filter(|_| args[0].is_number(spreadsheet)))
+}
+
+fn round_to_parity(number: f64, odd: bool) -> f64 {
+    // Rounds away from zero to the next even (or odd) integer, ie. EVEN(-1) is -2 and ODD(2) is 3
+    let mut rounded = number.abs().ceil();
+    if (rounded % 2.0 == 1.0) != odd {
+        rounded += 1.0;
+    }
+    rounded.copysign(number)
+}
+
+struct IsEven;
+impl FormulaFunction for IsEven {
+    fn call(&self, args: &[Token], spreadsheet: &Spreadsheet) -> Result<Vec<Token>, ()> {
+        // Only the integer part counts, so ISEVEN(2.9) is TRUE
+        Ok(vec![match single_number(args, spreadsheet)? {
+            Some(number) => Token::new(
+                TokenType::Boolean,
+                (number.trunc() % 2.0 == 0.0).to_string().to_uppercase(),
+            ),
+            None => Token::new(TokenType::Error, String::from("#VALUE!")),
+        }])
+    }
+}
+
+struct IsOdd;
+impl FormulaFunction for IsOdd {
+    fn call(&self, args: &[Token], spreadsheet: &Spreadsheet) -> Result<Vec<Token>, ()> {
+        Ok(vec![match single_number(args, spreadsheet)? {
+            Some(number) => Token::new(
+                TokenType::Boolean,
+                (number.trunc() % 2.0 != 0.0).to_string().to_uppercase(),
+            ),
+            None => Token::new(TokenType::Error, String::from("#VALUE!")),
+        }])
+    }
+}
+
+struct Even;
+impl FormulaFunction for Even {
+    fn call(&self, args: &[Token], spreadsheet: &Spreadsheet) -> Result<Vec<Token>, ()> {
+        Ok(vec![match single_number(args, spreadsheet)? {
+            Some(number) => Token::new(
+                TokenType::Number,
+                (round_to_parity(number, false) + 0.0).to_string(),
+            ),
+            None => Token::new(TokenType::Error, String::from("#VALUE!")),
+        }])
+    }
+}
+
+struct Odd;
+impl FormulaFunction for Odd {
+    fn call(&self, args: &[Token], spreadsheet: &Spreadsheet) -> Result<Vec<Token>, ()> {
+        Ok(vec![match single_number(args, spreadsheet)? {
+            Some(number) => {
+                Token::new(TokenType::Number, round_to_parity(number, true).to_string())
+            }
+            None => Token::new(TokenType::Error, String::from("#VALUE!")),
+        }])
+    }
+}
+
+struct Subtotal;
+impl FormulaFunction for Subtotal {
+    fn call(&self, args: &[Token], spreadsheet: &Spreadsheet) -> Result<Vec<Token>, ()> {
+        // SUBTOTAL(function_num, ref1, ...), where 1 to 11 pick AVERAGE, COUNT, COUNTA, MAX, MIN,
+        // PRODUCT, STDEV, STDEVP, SUM, VAR and VARP, and 101 to 111 pick the same but also leave
+        // out rows hidden by hand or in collapsed groups. Rows filtered out and other subtotals in
+        // the ranges are always left out, as in Excel.
+        if args.len() < 2 || !args[0].is_number(spreadsheet) {
+            return Err(());
+        }
+        let function_num = args[0].as_f64(spreadsheet).trunc() as i64;
+        let skip_hidden = function_num > 100;
+        if !(1..=11).contains(&(function_num % 100)) || !(1..=111).contains(&function_num) {
+            return Ok(vec![Token::new(TokenType::Error, String::from("#VALUE!"))]);
+        }
+
+        let mut values: Vec<Token> = Vec::new();
+        for arg in &args[1..] {
+            match arg.referenced_cells() {
+                Some(cells) => values.extend(
+                    cells
+                        .iter()
+                        .filter(|c| !spreadsheet.is_row_filtered(c.row))
+                        .filter(|c| !(skip_hidden && spreadsheet.is_row_hidden(c.row)))
+                        .filter(|c| !is_subtotal(spreadsheet.get_cell(c)))
+                        .map(|c| spreadsheet.get_cell_value(c).unwrap_or_default()),
+                ),
+                None => values.extend(arg_values(std::slice::from_ref(arg), spreadsheet)),
+            }
+        }
+        let nums: Vec<f64> = values
+            .iter()
+            .filter(|t| t.token_type == TokenType::Number)
+            .filter_map(|t| t.content.parse::<f64>().ok())
+            .collect();
+
+        let count = nums.len() as f64;
+        let mean = nums.iter().sum::<f64>() / count;
+        let squares = nums.iter().map(|n| (n - mean).powi(2)).sum::<f64>();
+        let result = match function_num % 100 {
+            1 if count > 0.0 => Some(mean),
+            2 => Some(count),
+            3 => Some(values.iter().filter(|t| !t.content.is_empty()).count() as f64),
+            4 => Some(nums.iter().copied().reduce(f64::max).unwrap_or(0.0)),
+            5 => Some(nums.iter().copied().reduce(f64::min).unwrap_or(0.0)),
+            6 if count > 0.0 => Some(nums.iter().product()),
+            6 => Some(0.0),
+            7 if count > 1.0 => Some((squares / (count - 1.0)).sqrt()),
+            8 if count > 0.0 => Some((squares / count).sqrt()),
+            9 => Some(nums.iter().sum::<f64>() + 0.0),
+            10 if count > 1.0 => Some(squares / (count - 1.0)),
+            11 if count > 0.0 => Some(squares / count),
+            // Averages and deviations of too few numbers divide by zero
+            _ => None,
+        };
+        Ok(vec![match result {
+            Some(result) => Token::new(TokenType::Number, result.to_string()),
+            None => Token::new(TokenType::Error, String::from("#DIV/0!")),
+        }])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::formulas::eval_formula;
+    use crate::spreadsheet::SpreadsheetCell;
+
+    fn eval(formula: &str, spreadsheet: &Spreadsheet) -> String {
+        eval_formula(formula, spreadsheet, None).unwrap().content
+    }
+
+    #[test]
+    fn subtotal_always_leaves_out_filtered_rows() {
+        let mut spreadsheet =
+            Spreadsheet::from_text("fruit\tqty\napple\t5\nbanana\t12\ncherry\t20", '\t');
+        assert_eq!(eval("SUBTOTAL(9,B2:B4)", &spreadsheet), "37");
+
+        spreadsheet.set_filter(1, Some(String::from(">10")));
+        assert_eq!(eval("SUBTOTAL(9,B2:B4)", &spreadsheet), "32");
+        assert_eq!(eval("SUBTOTAL(109,B2:B4)", &spreadsheet), "32");
+
+        // Only the 100s leave out rows hidden by hand
+        spreadsheet.set_row_hidden(3, true);
+        assert_eq!(eval("SUBTOTAL(9,B2:B4)", &spreadsheet), "32");
+        assert_eq!(eval("SUBTOTAL(109,B2:B4)", &spreadsheet), "12");
+    }
+
+    #[test]
+    fn criteria() {
+        let number = Token::new(TokenType::Number, String::from("5"));
+        let text = Token::new(TokenType::String, String::from("Apple"));
+        assert!(meets_criteria(&number, ">=5"));
+        assert!(meets_criteria(&number, "5"));
+        assert!(!meets_criteria(&number, "<5"));
+        assert!(meets_criteria(&number, "<>3"));
+        assert!(meets_criteria(&text, "apple"));
+        assert!(meets_criteria(&text, "*PP*"));
+        assert!(meets_criteria(&text, "a?ple"));
+        assert!(meets_criteria(&text, "<>banana"));
+        assert!(meets_criteria(&text, "<b"));
+        // Numeric criteria never match text, except to say it isn't that number
+        assert!(!meets_criteria(&text, ">0"));
+        assert!(meets_criteria(&text, "<>0"));
+    }
+
+    #[test]
+    fn count_only_counts_numbers() {
+        let spreadsheet = Spreadsheet::from_text("n\n5\ninf\napple\nTRUE\n\n1e3\n", ',');
+        assert_eq!(eval("COUNT(A2:A7)", &spreadsheet), "2");
+    }
+
+    #[test]
+    fn sequence_limits_its_size() {
+        let spreadsheet = Spreadsheet::new();
+        assert_eq!(eval("SUM(SEQUENCE(2,2))", &spreadsheet), "10");
+        assert_eq!(eval("SEQUENCE(1048576,16384)", &spreadsheet), "#NUM!");
+        assert_eq!(eval("SEQUENCE(2000,2000)", &spreadsheet), "#NUM!");
+        assert_eq!(eval("SEQUENCE(1048577)", &spreadsheet), "#SPILL!");
+    }
+
+    #[test]
+    fn sequence_needs_positive_sizes() {
+        let spreadsheet = Spreadsheet::new();
+        assert_eq!(eval("SEQUENCE(0)", &spreadsheet), "#VALUE!");
+        assert_eq!(eval("SEQUENCE(-3,2)", &spreadsheet), "#VALUE!");
+        assert_eq!(eval("SEQUENCE(3,0)", &spreadsheet), "#VALUE!");
+    }
+
+    #[test]
+    fn countblank_counts_cells_past_the_data() {
+        let spreadsheet = Spreadsheet::from_text("1,,x\n\n,2\n", ',');
+        // Three rows of data, then two that were never written
+        assert_eq!(eval("COUNTBLANK(A1:C5)", &spreadsheet), "12");
+        assert_eq!(eval("COUNTBLANK(A1:A1)", &spreadsheet), "0");
+        assert_eq!(eval("COUNTBLANK(D1:D3)", &spreadsheet), "3");
+    }
+
+    #[test]
+    fn countifs_needs_every_criteria() {
+        let spreadsheet = Spreadsheet::from_text("1,5\n3,2\n4,6\n", ',');
+        assert_eq!(
+            eval("COUNTIFS(A1:A3,\">2\",B1:B3,\">3\")", &spreadsheet),
+            "1"
+        );
+        assert_eq!(eval("COUNTIFS(A1:A3,\">2\")", &spreadsheet), "2");
+        assert_eq!(eval("COUNTIF(B1:B3,\"<>2\")", &spreadsheet), "2");
+        // Ranges that don't line up can't be compared cell by cell
+        assert_eq!(
+            eval("COUNTIFS(A1:A3,\">2\",B1:B2,\">3\")", &spreadsheet),
+            "#VALUE!"
+        );
+    }
+
+    #[test]
+    fn large_and_small_pick_the_kth_number() {
+        let spreadsheet = Spreadsheet::from_text("3\n-1\nx\n7\n\n7\n", ',');
+        assert_eq!(eval("LARGE(A1:A6,1)", &spreadsheet), "7");
+        assert_eq!(eval("LARGE(A1:A6,2)", &spreadsheet), "7");
+        assert_eq!(eval("LARGE(A1:A6,3)", &spreadsheet), "3");
+        assert_eq!(eval("SMALL(A1:A6,1)", &spreadsheet), "-1");
+        assert_eq!(eval("SMALL(A1:A6,2.9)", &spreadsheet), "3");
+        // Only the four numbers count, so there's no fifth
+        assert_eq!(eval("LARGE(A1:A6,5)", &spreadsheet), "#NUM!");
+        assert_eq!(eval("SMALL(A1:A6,0)", &spreadsheet), "#NUM!");
+        assert_eq!(eval("SMALL(B1:B6,1)", &spreadsheet), "#NUM!");
+    }
+
+    #[test]
+    fn rank_shares_ties() {
+        let spreadsheet = Spreadsheet::from_text("10\n20\n20\n5\n", ',');
+        assert_eq!(eval("RANK(20,A1:A4)", &spreadsheet), "1");
+        assert_eq!(eval("RANK(10,A1:A4)", &spreadsheet), "3");
+        assert_eq!(eval("RANK(5,A1:A4)", &spreadsheet), "4");
+        // Ascending ranks count up from the smallest instead
+        assert_eq!(eval("RANK(5,A1:A4,1)", &spreadsheet), "1");
+        assert_eq!(eval("RANK(20,A1:A4,1)", &spreadsheet), "3");
+        assert_eq!(eval("RANK(15,A1:A4)", &spreadsheet), "#N/A");
+    }
+
+    #[test]
+    fn value_and_n_read_numbers() {
+        let spreadsheet = Spreadsheet::new();
+        assert_eq!(eval("VALUE(\"$1,234\")", &spreadsheet), "1234");
+        assert_eq!(eval("VALUE(\"50%\")", &spreadsheet), "0.5");
+        assert_eq!(eval("VALUE(\" 12 \")", &spreadsheet), "12");
+        assert_eq!(eval("VALUE(\"abc\")", &spreadsheet), "#VALUE!");
+        assert_eq!(eval("N(TRUE)", &spreadsheet), "1");
+        assert_eq!(eval("N(FALSE)", &spreadsheet), "0");
+        assert_eq!(eval("N(\"7\")", &spreadsheet), "0");
+        assert_eq!(eval("N(7)", &spreadsheet), "7");
+    }
+
+    #[test]
+    fn char_and_code_are_inverses() {
+        let spreadsheet = Spreadsheet::new();
+        assert_eq!(eval("CHAR(65)", &spreadsheet), "A");
+        assert_eq!(eval("CODE(\"A\")", &spreadsheet), "65");
+        assert_eq!(eval("CODE(\"abc\")", &spreadsheet), "97");
+        assert_eq!(eval("CODE(CHAR(200))", &spreadsheet), "200");
+        assert_eq!(eval("CHAR(0)", &spreadsheet), "#VALUE!");
+        assert_eq!(eval("CODE(\"\")", &spreadsheet), "#VALUE!");
+    }
+
+    #[test]
+    fn rept_repeats_whole_times() {
+        let spreadsheet = Spreadsheet::new();
+        assert_eq!(eval("REPT(\"ab\",3)", &spreadsheet), "ababab");
+        assert_eq!(eval("REPT(\"ab\",2.9)", &spreadsheet), "abab");
+        assert_eq!(eval("REPT(\"ab\",0)", &spreadsheet), "");
+        assert_eq!(eval("REPT(\"ab\",-1)", &spreadsheet), "#VALUE!");
+    }
+
+    #[test]
+    fn parity_checks_and_rounding() {
+        let spreadsheet = Spreadsheet::new();
+        assert_eq!(eval("ISEVEN(4)", &spreadsheet), "TRUE");
+        assert_eq!(eval("ISEVEN(3.5)", &spreadsheet), "FALSE");
+        assert_eq!(eval("ISODD(-3)", &spreadsheet), "TRUE");
+        // EVEN and ODD round away from zero, to the next even or odd number
+        assert_eq!(eval("ODD(2)", &spreadsheet), "3");
+        assert_eq!(eval("ODD(0)", &spreadsheet), "1");
+        assert_eq!(eval("EVEN(-1)", &spreadsheet), "-2");
+        assert_eq!(eval("EVEN(2.1)", &spreadsheet), "4");
+        assert_eq!(eval("EVEN(0)", &spreadsheet), "0");
+    }
+
+    #[test]
+    fn broken_formulas_are_skipped_by_aggregates() {
+        let mut spreadsheet = Spreadsheet::from_text("4\n\n9\n", ',');
+        spreadsheet.set_cell(&SpreadsheetCell { row: 1, col: 0 }, "=SUM(A1,");
+        assert_eq!(eval("MIN(A1:A3)", &spreadsheet), "4");
+        assert_eq!(eval("MAX(A1:A3)", &spreadsheet), "9");
+        assert_eq!(eval("SUM(A1:A3)", &spreadsheet), "13");
+        assert_eq!(eval("LARGE(A1:A3,2)", &spreadsheet), "4");
+    }
+}
@@ -1,9 +1,12 @@
-use std::collections::{BTreeSet, VecDeque};
+use std::collections::BTreeSet;
 
 use crate::formula_functions::{get_func, get_funcs};
 use crate::references::{parse_reference, Reference};
 use crate::spreadsheet::{Spreadsheet, SpreadsheetCell};
 
+const ERRORS: [&str; 8] = [
+    "#NULL!", "#DIV/0!", "#VALUE!", "#REF!", "#NAME?", "#NUM!", "#N/A", "#SPILL!",
+];
 const OPERATORS: [&'static str; 19] = [
     "-", "%", "^", "^", "*", "/", "+", "&", "=", ">=", "<=", "<>", "<", ">", "@", "#", ":", ",",
     " ",
@@ -24,6 +27,8 @@ pub enum TokenType {
     Operator,
     LeftParen,
     RightParen,
+    Error, // Excel's error values, such as #NUM!
+    Array, // Multiple values from one formula, which spill into neighboring cells
 }
 
 #[derive(Debug, Clone, Default, PartialEq, Eq)]
@@ -32,6 +37,7 @@ pub struct Token {
     pub content: String,
     pub function_n_args: Option<u8>,
     pub reference_set: Option<BTreeSet<Reference>>,
+    pub array: Option<Vec<Vec<Token>>>,
 }
 
 impl Token {
@@ -41,6 +47,7 @@ impl Token {
             content,
             function_n_args: None,
             reference_set: None,
+            array: None,
         }
     }
 
@@ -50,6 +57,7 @@ impl Token {
             content,
             function_n_args: Some(n_args),
             reference_set: None,
+            array: None,
         }
     }
 
@@ -59,13 +67,35 @@ impl Token {
             content: String::new(),
             function_n_args: None,
             reference_set: Some(refs),
+            array: None,
         }
     }
 
-    pub fn as_f32(&self, spreadsheet: &Spreadsheet) -> f32 {
+    pub fn array(values: Vec<Vec<Token>>) -> Token {
+        // The content is written like Excel's array constants (ie. {1,2;3,4}), mostly for display
+        let content = values
+            .iter()
+            .map(|row| {
+                row.iter()
+                    .map(|t| t.content.clone())
+                    .collect::<Vec<String>>()
+                    .join(",")
+            })
+            .collect::<Vec<String>>()
+            .join(";");
+        Token {
+            token_type: TokenType::Array,
+            content: format!("{{{}}}", content),
+            function_n_args: None,
+            reference_set: None,
+            array: Some(values),
+        }
+    }
+
+    pub fn as_f64(&self, spreadsheet: &Spreadsheet) -> f64 {
         // TODO: Make this a Some function, returning None if it fails instead of 0.
         match self.token_type {
-            TokenType::Number => self.content.parse::<f32>().unwrap(),
+            TokenType::Number => self.content.parse::<f64>().unwrap_or(0.0),
             TokenType::Boolean => {
                 if self.content == String::from("TRUE") {
                     1.0
@@ -78,7 +108,7 @@ impl Token {
                 if let Ok(token) =
                     spreadsheet.get_cell_value(self.referenced_cells().unwrap().first().unwrap())
                 {
-                    token.as_f32(spreadsheet)
+                    token.as_f64(spreadsheet)
                 } else {
                     0.0
                 }
@@ -91,7 +121,7 @@ impl Token {
         match self.token_type {
             TokenType::Boolean => true,
             TokenType::Number => true,
-            TokenType::String => self.content.parse::<f32>().is_ok(),
+            TokenType::String => self.content.parse::<f64>().is_ok(),
             // TODO: Handle multi-refs
             TokenType::Reference => {
                 if let Some(cells) = self.referenced_cells() {
@@ -153,13 +183,24 @@ pub fn find_close_paren(formula: &str, start_idx: usize) -> Option<usize> {
 }
 
 pub fn balance_parens(formula: &str) -> String {
-    // A naive-feeling (but functional from my tests) parenthesis balancer
-    let open = formula.matches("(").count();
-    let close = formula.matches(")").count();
+    // A naive-feeling (but functional from my tests) parenthesis balancer. Parentheses inside of
+    // strings don't count, and missing open parentheses go after the formula's leading =.
+    let mut in_string = false;
+    let (mut open, mut close) = (0, 0);
+    for c in formula.chars() {
+        match c {
+            '"' => in_string = !in_string,
+            '(' if !in_string => open += 1,
+            ')' if !in_string => close += 1,
+            _ => (),
+        }
+    }
     if open == close {
         formula.to_string()
     } else if open > close {
         formula.to_string() + &")".repeat(open - close)
+    } else if let Some(rest) = formula.strip_prefix('=') {
+        String::from("=") + &"(".repeat(close - open) + rest
     } else {
         "(".repeat(close - open) + formula
     }
@@ -172,15 +213,21 @@ pub fn parse_formula(formula: &str) -> Result<Vec<Token>, ()> {
     let mut parse_idx = 0;
     while parse_idx < formula.len() {
         let current_char = formula.chars().nth(parse_idx).unwrap_or_default();
-        if current_char.is_ascii_digit() {
+        if current_char.is_ascii_digit()
+            || (current_char == '.'
+                && formula
+                    .chars()
+                    .nth(parse_idx + 1)
+                    .is_some_and(|c| c.is_ascii_digit()))
+        {
             // Parse raw numbers
             let mut number_content = String::new();
-            // Allow for multiple numerical characters to follow one another, as is usual
+            // Allow for multiple numerical characters to follow one another, as is usual, with at
+            // most one decimal point
             while formula
                 .chars()
                 .nth(parse_idx)
-                .unwrap_or_default()
-                .is_ascii_digit()
+                .is_some_and(|c| c.is_ascii_digit() || (c == '.' && !number_content.contains('.')))
             {
                 number_content += formula
                     .chars()
@@ -190,9 +237,56 @@ pub fn parse_formula(formula: &str) -> Result<Vec<Token>, ()> {
                     .as_str();
                 parse_idx += 1;
             }
+            // Scientific notation (ie. 1e3 or 2.5E-4), only when digits follow, so the exponent is
+            // never half of something else
+            let exponent: String = formula.chars().skip(parse_idx).take(3).collect();
+            let exponent_len = match exponent.as_bytes() {
+                [b'e' | b'E', b'+' | b'-', digit, ..] if digit.is_ascii_digit() => 2,
+                [b'e' | b'E', digit, ..] if digit.is_ascii_digit() => 1,
+                _ => 0,
+            };
+            if exponent_len > 0 {
+                number_content += &exponent[..exponent_len];
+                parse_idx += exponent_len;
+                while formula
+                    .chars()
+                    .nth(parse_idx)
+                    .is_some_and(|c| c.is_ascii_digit())
+                {
+                    number_content.push(formula.chars().nth(parse_idx).unwrap_or_default());
+                    parse_idx += 1;
+                }
+                // Written out in full, so text joined to it reads like the number (ie. 1000x)
+                if let Ok(number) = number_content.parse::<f64>() {
+                    number_content = number.to_string();
+                }
+            }
 
-            parsed.push(Token::new(TokenType::Number, number_content));
+            // Whole rows (ie. 1:1) are only references when part of a range, like whole columns
+            let in_range = formula.chars().nth(parse_idx) == Some(':')
+                || parsed
+                    .last()
+                    .is_some_and(|t| t.token_type == TokenType::Operator && t.content == ":");
+            match parse_reference(&number_content).filter(|_| in_range) {
+                Some(parsed_ref) if parsed_ref.is_in_bounds() => {
+                    parsed.push(Token::reference(BTreeSet::from([parsed_ref])))
+                }
+                Some(_) => parsed.push(Token::new(TokenType::Error, String::from("#REF!"))),
+                None => parsed.push(Token::new(TokenType::Number, number_content)),
+            }
             parse_idx -= 1;
+        } else if let Some(error) = ERRORS.iter().find(|e| {
+            current_char == '#'
+                && formula
+                    .chars()
+                    .skip(parse_idx)
+                    .take(e.len())
+                    .collect::<String>()
+                    .eq_ignore_ascii_case(e)
+        }) {
+            // Parse error literals, which would otherwise be read as the # operator
+            parsed.push(Token::new(TokenType::Error, error.to_string()));
+            parse_idx += error.len() - 1;
         } else if OPERATORS.contains(&current_char.to_string().as_str()) {
             // Parse operators
             let next_char = formula.chars().nth(parse_idx + 1).unwrap_or_default();
@@ -209,12 +303,12 @@ pub fn parse_formula(formula: &str) -> Result<Vec<Token>, ()> {
             // Parse functions, booleans, and (most) cell references.
 
             let mut textual_content = String::new();
-            // Allow for multiple numerical characters to follow one another, as is usual
+            // Allow for multiple numerical characters to follow one another, as is usual, and
+            // periods for dotted function names like CEILING.MATH
             while formula
                 .chars()
                 .nth(parse_idx)
-                .unwrap_or_default()
-                .is_ascii_alphanumeric()
+                .is_some_and(|c| c.is_ascii_alphanumeric() || c == '.')
             {
                 textual_content += formula
                     .chars()
@@ -246,15 +340,31 @@ pub fn parse_formula(formula: &str) -> Result<Vec<Token>, ()> {
 
                         // Resetting with parse_idx -= 1 should NOT happen because the left parenthesis should be consumed
                     } else {
-                        eprintln!("Error: Function doesn't have an opening parenthesis");
+                        log::warn!("Function doesn't have an opening parenthesis");
                         return Err(()); // Function doesn't have an opening parenthesis
                     }
                 }
-            } else if let Some(parsed_ref) = parse_reference(&textual_content.to_uppercase()) {
-                // Only need to know if it's successful, not the resulting ref
-                parsed.push(Token::reference(BTreeSet::from([parsed_ref])));
+            } else if let Some(parsed_ref) = parse_reference(&textual_content.to_uppercase())
+                .filter(|r| {
+                    // Whole columns (ie. A:A) are only references when part of a range
+                    r.is_cell()
+                        || formula.chars().nth(parse_idx) == Some(':')
+                        || parsed.last().is_some_and(|t| {
+                            t.token_type == TokenType::Operator && t.content == ":"
+                        })
+                })
+            {
+                if parsed_ref.is_in_bounds() {
+                    parsed.push(Token::reference(BTreeSet::from([parsed_ref])));
+                } else {
+                    parsed.push(Token::new(TokenType::Error, String::from("#REF!")));
+                }
                 // Decrement parse index because it went over by one in the while loop.
                 parse_idx -= 1
+            } else {
+                // Unknown functions and bare words are reported like Excel does
+                parsed.push(Token::new(TokenType::Error, String::from("#NAME?")));
+                parse_idx -= 1
             }
         } else if current_char == '(' {
             // Parse left parentheses
@@ -315,11 +425,9 @@ pub fn parse_formula(formula: &str) -> Result<Vec<Token>, ()> {
                 parsed[idx].token_type = TokenType::FuncArgSep
             }
         } else if parsed[idx].token_type == TokenType::Operator && parsed[idx].content == " " {
-            if idx == 0 {
-                to_remove.push(idx);
-            }
-            if !(parsed[idx - 1].token_type == TokenType::Reference
-                && parsed[idx + 1].token_type == TokenType::Reference)
+            if idx == 0
+                || !(parsed[idx - 1].token_type == TokenType::Reference
+                    && parsed[idx + 1].token_type == TokenType::Reference)
             {
                 to_remove.push(idx);
             }
@@ -369,6 +477,93 @@ pub fn parse_formula(formula: &str) -> Result<Vec<Token>, ()> {
     return Ok(parsed);
 }
 
+pub fn shift_references(formula: &str, rows: i64, cols: i64) -> String {
+    // Moves every reference in a formula by an offset, as happens when copying it to another cell
+    ShiftableFormula::new(formula).shift(rows, cols)
+}
+
+#[derive(Debug, Clone)]
+enum FormulaPart {
+    Text(String),
+    Reference(Reference),
+}
+
+#[derive(Debug, Clone)]
+pub struct ShiftableFormula {
+    // A formula split into its cell references and the text between them, so it can be shifted
+    // to many cells (ie. when filling a range) while only being scanned once
+    parts: Vec<FormulaPart>,
+}
+
+impl ShiftableFormula {
+    pub fn new(formula: &str) -> ShiftableFormula {
+        if !formula.starts_with("=") {
+            return ShiftableFormula {
+                parts: vec![FormulaPart::Text(formula.to_string())],
+            };
+        }
+
+        let chars: Vec<char> = formula.chars().collect();
+        let mut parts: Vec<FormulaPart> = Vec::new();
+        let mut text = String::new();
+        let mut idx = 0;
+        while idx < chars.len() {
+            let current_char = chars[idx];
+            if current_char == '"' {
+                // Strings are copied as-is
+                let end = chars[idx + 1..]
+                    .iter()
+                    .position(|c| *c == '"')
+                    .map_or(chars.len(), |p| idx + p + 2);
+                text.extend(&chars[idx..end]);
+                idx = end;
+            } else if current_char.is_ascii_alphanumeric() || current_char == '.' {
+                let end = idx
+                    + chars[idx..]
+                        .iter()
+                        .take_while(|c| c.is_ascii_alphanumeric() || **c == '.')
+                        .count();
+                let word: String = chars[idx..end].iter().collect();
+
+                // Numbers and function names are left alone
+                let is_function = chars.get(end) == Some(&'(');
+                match parse_reference(&word.to_uppercase()) {
+                    Some(reference)
+                        if current_char.is_ascii_alphabetic()
+                            && reference.is_cell()
+                            && !is_function =>
+                    {
+                        parts.push(FormulaPart::Text(std::mem::take(&mut text)));
+                        parts.push(FormulaPart::Reference(reference));
+                    }
+                    _ => text += &word,
+                }
+                idx = end;
+            } else {
+                text.push(current_char);
+                idx += 1;
+            }
+        }
+        parts.push(FormulaPart::Text(text));
+        ShiftableFormula { parts }
+    }
+
+    pub fn shift(&self, rows: i64, cols: i64) -> String {
+        let mut shifted = String::new();
+        for part in &self.parts {
+            match part {
+                FormulaPart::Text(text) => shifted += text,
+                FormulaPart::Reference(reference) => {
+                    shifted += &reference
+                        .offset(rows, cols)
+                        .map_or(String::from("#REF!"), |r| r.to_string())
+                }
+            }
+        }
+        shifted
+    }
+}
+
 pub fn extract_references(formula: &str) -> Result<Vec<SpreadsheetCell>, ()> {
     let parsed = parse_formula(formula)?;
     // TODO: Potential issue with operators not being directly next to refs, but I'm ignoring it
@@ -382,7 +577,7 @@ pub fn extract_references(formula: &str) -> Result<Vec<SpreadsheetCell>, ()> {
         })
         .collect::<Vec<Token>>();
 
-    let result = eval_tokens(notable_tokens, &Spreadsheet::new())?;
+    let result = eval_tokens(notable_tokens, &Spreadsheet::new(), None)?;
 
     if let Some(refs) = result.referenced_cells() {
         Ok(refs)
@@ -395,9 +590,12 @@ fn get_operator_precedence(operator: &str) -> u8 {
     // TODO: Add the rest of these from Excel's docs
     match operator {
         // Reference operators
-        ":" => 9, // This needs a higher precedence than is listed on Excel's website
+        "#" => 10, // Applies to the single reference just before it
+        ":" => 9,  // This needs a higher precedence than is listed on Excel's website
         "," => 8,
         " " => 8,
+        // Implicit intersection, which applies to whole ranges but binds tighter than negation
+        "@" => 8,
         // Negation
         "-1" => 7,
         // Percent
@@ -423,7 +621,44 @@ fn get_operator_precedence(operator: &str) -> u8 {
     }
 }
 
-fn apply_arithmetic_operator(a: f32, b: f32, operator: &str) -> f32 {
+fn as_error(token: &Token, spreadsheet: &Spreadsheet) -> Option<Token> {
+    // Errors can come from the token itself, or from the single cell it references
+    match token.token_type {
+        TokenType::Error => Some(token.clone()),
+        TokenType::Reference => match token.referenced_cells()?.as_slice() {
+            [cell] => spreadsheet
+                .get_cell_value(cell)
+                .ok()
+                .filter(|t| t.token_type == TokenType::Error),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+fn implicit_intersection(token: Token, cell: Option<&SpreadsheetCell>) -> Token {
+    // Narrows a range down to the one cell sharing the formula's row (for a column) or column (for
+    // a row), or both for a 2D range. Single values are left as they are.
+    let Some(cells) = token.referenced_cells().filter(|c| c.len() > 1) else {
+        return token;
+    };
+    let single_col = cells.iter().all(|c| c.col == cells[0].col);
+    let single_row = cells.iter().all(|c| c.row == cells[0].row);
+    let intersection = cell.and_then(|cell| {
+        cells
+            .iter()
+            .find(|c| (single_col || c.col == cell.col) && (single_row || c.row == cell.row))
+    });
+
+    match intersection {
+        Some(intersection) => {
+            Token::reference(BTreeSet::from([Reference::from_cell(intersection)]))
+        }
+        None => Token::new(TokenType::Error, String::from("#VALUE!")),
+    }
+}
+
+fn apply_arithmetic_operator(a: f64, b: f64, operator: &str) -> f64 {
     match operator {
         "+" => a + b,
         "-" => a - b,
@@ -434,7 +669,7 @@ fn apply_arithmetic_operator(a: f32, b: f32, operator: &str) -> f32 {
     }
 }
 
-fn apply_comparison_operator(a: f32, b: f32, operator: &str) -> bool {
+fn apply_comparison_operator(a: f64, b: f64, operator: &str) -> bool {
     match operator {
         "=" => a == b,
         "<" => a < b,
@@ -450,10 +685,14 @@ fn apply_reference_operator(
     a: BTreeSet<Reference>,
     b: BTreeSet<Reference>,
     operator: &str,
+    spreadsheet: &Spreadsheet,
 ) -> BTreeSet<Reference> {
     BTreeSet::from_iter(
         match operator {
-            ":" => a.first().unwrap().range(b.first().unwrap()),
+            ":" => match (a.first(), b.first()) {
+                (Some(a), Some(b)) => a.range_within(b, spreadsheet),
+                _ => Vec::new(),
+            },
             "," => a.union(&b).cloned().collect::<Vec<Reference>>(),
             " " => a.intersection(&b).cloned().collect::<Vec<Reference>>(),
             _ => a.iter().cloned().collect::<Vec<Reference>>(),
@@ -462,34 +701,92 @@ fn apply_reference_operator(
     )
 }
 
-pub fn cell_to_token(cell_value: &str, spreadsheet: &Spreadsheet) -> Result<Token, ()> {
+pub fn is_zero_padded(cell_value: &str) -> bool {
+    // Values like 007 or 0123 are almost always IDs, codes, or phone numbers rather than numbers,
+    // so they're kept as text to preserve the leading zeros.
+    let mut chars = cell_value.chars();
+    chars.next() == Some('0')
+        && chars.next().is_some_and(|c| c.is_ascii_digit())
+        && cell_value.chars().all(|c| c.is_ascii_digit())
+}
+
+pub fn cell_to_token(
+    cell_value: &str,
+    spreadsheet: &Spreadsheet,
+    cell: Option<&SpreadsheetCell>,
+) -> Result<Token, ()> {
     // Parses a single cell as a single value (boolean or number), else a string
     // Unless, of course, it's another formula-
-    if cell_value.starts_with("=") {
-        let mut result = eval_formula(&cell_value[1..], spreadsheet)?;
+    if let Some(text) = cell_value.strip_prefix('\'') {
+        // A leading apostrophe forces the rest of the cell to be text, as in Excel
+        return Ok(Token::new(TokenType::String, text.to_string()));
+    }
+    if is_zero_padded(cell_value) {
+        return Ok(Token::new(TokenType::String, cell_value.to_string()));
+    }
+    if let Some(formula) = cell_value.strip_prefix("=") {
+        let mut result = eval_formula(formula, spreadsheet, cell)?;
 
         if result.token_type == TokenType::Reference {
             let cells = result.referenced_cells().unwrap();
-            result = spreadsheet.get_cell_value(&cells.first().unwrap())?;
+            result = spreadsheet.get_cell_value(cells.first().ok_or(())?)?;
         }
         return Ok(result);
     }
-    let mut token_type = TokenType::Number;
-    if cell_value.chars().all(|c| c.is_ascii_digit() || c == '.') {
-        token_type = TokenType::Number;
-    } else if cell_value.to_uppercase() == "FALSE" || cell_value.to_uppercase() == "True" {
-        token_type = TokenType::Boolean;
+    if let Some(number) = spreadsheet.locale.parse_number(cell_value) {
+        // Numbers written with separators (ie. 1,234.5 or 1.234,5) are normalized
+        if cell_value.parse::<f64>() != Ok(number) {
+            return Ok(Token::new(TokenType::Number, number.to_string()));
+        }
+    }
+    // Rust also reads inf and NaN as numbers, which are only text in a cell
+    if cell_value.parse::<f64>().is_ok_and(f64::is_finite) {
+        return Ok(Token::new(TokenType::Number, cell_value.to_string()));
+    }
+    let uppercase = cell_value.to_uppercase();
+    if uppercase == "TRUE" || uppercase == "FALSE" {
+        // Kept in capitals, which is what booleans are read as numbers from
+        return Ok(Token::new(TokenType::Boolean, uppercase));
+    }
+    Ok(Token::new(TokenType::String, cell_value.to_string()))
+}
+
+fn finite_or_error(token: Token) -> Token {
+    // Functions can give NaN or inf (ie. SQRT(-1)) where Excel gives #NUM!, like operators do
+    if let Some(values) = token.array {
+        return Token::array(
+            values
+                .into_iter()
+                .map(|row| row.into_iter().map(finite_or_error).collect())
+                .collect(),
+        );
+    }
+    match token.content.parse::<f64>() {
+        Ok(number) if token.token_type == TokenType::Number && !number.is_finite() => {
+            Token::new(TokenType::Error, String::from("#NUM!"))
+        }
+        _ => token,
     }
-    Ok(Token::new(token_type, cell_value.to_string()))
 }
 
-pub fn eval_formula(formula: &str, spreadsheet: &Spreadsheet) -> Result<Token, ()> {
+pub fn eval_formula(
+    formula: &str,
+    spreadsheet: &Spreadsheet,
+    cell: Option<&SpreadsheetCell>,
+) -> Result<Token, ()> {
+    // The cell is the one containing the formula, if any, for operators like @ that depend on it
     let parsed = parse_formula(formula)?;
 
-    eval_tokens(parsed, spreadsheet)
+    let result = eval_tokens(parsed, spreadsheet, cell);
+    log::debug!("Evaluated {:?} at {:?}: {:?}", formula, cell, result);
+    result
 }
 
-pub fn eval_tokens(tokens: Vec<Token>, spreadsheet: &Spreadsheet) -> Result<Token, ()> {
+pub fn eval_tokens(
+    tokens: Vec<Token>,
+    spreadsheet: &Spreadsheet,
+    cell: Option<&SpreadsheetCell>,
+) -> Result<Token, ()> {
     // TODO: Support for non-numbers
     let mut output_queue: Vec<Token> = Vec::new();
     let mut operator_stack: Vec<Token> = Vec::new();
@@ -508,7 +805,7 @@ pub fn eval_tokens(tokens: Vec<Token>, spreadsheet: &Spreadsheet) -> Result<Toke
                         && x.token_type != TokenType::Function
                         && x.token_type != TokenType::FuncArgSep
                     {
-                        println!("{:?}", x.token_type);
+                        log::trace!("Popped {:?} at a right parenthesis", x.token_type);
                         output_queue.push(x);
                     } else {
                         operator_stack.push(x);
@@ -531,7 +828,7 @@ pub fn eval_tokens(tokens: Vec<Token>, spreadsheet: &Spreadsheet) -> Result<Toke
                         break;
                     }
                 }
-                output_queue.push(operator_stack.pop().unwrap());
+                output_queue.push(operator_stack.pop().ok_or(())?);
             }
             TokenType::FuncArgSep => {
                 while let Some(x) = operator_stack.pop() {
@@ -565,7 +862,26 @@ pub fn eval_tokens(tokens: Vec<Token>, spreadsheet: &Spreadsheet) -> Result<Toke
 
                 operator_stack.push(token.clone());
             }
-            TokenType::String | TokenType::Boolean | TokenType::Number | TokenType::Reference => {
+            TokenType::Reference => {
+                // Parsing only knows Excel's limits, so references past a smaller sheet's are
+                // found here
+                let (max_rows, max_cols) = (spreadsheet.max_rows(), spreadsheet.max_cols());
+                let in_bounds = token
+                    .reference_set
+                    .iter()
+                    .flatten()
+                    .all(|r| r.is_within(max_rows, max_cols));
+                output_queue.push(if in_bounds {
+                    token.clone()
+                } else {
+                    Token::new(TokenType::Error, String::from("#REF!"))
+                });
+            }
+            TokenType::String
+            | TokenType::Boolean
+            | TokenType::Number
+            | TokenType::Error
+            | TokenType::Array => {
                 output_queue.push(token.clone());
             }
         }
@@ -623,55 +939,119 @@ pub fn eval_tokens(tokens: Vec<Token>, spreadsheet: &Spreadsheet) -> Result<Toke
                     Some(it) => it,
                     None => return Err(()),
                 };
+
+                // Errors propagate through operators, with the leftmost one taking precedence.
+                // Reference operators work on the references themselves, not their values.
+                let is_binary = !["-1", "%", "@", "#"].contains(&operator);
+                let is_reference_operator = [":", ",", " ", "@", "#"].contains(&operator);
+                let error_of = |t: &Token| {
+                    if is_reference_operator {
+                        (t.token_type == TokenType::Error).then(|| t.clone())
+                    } else {
+                        as_error(t, spreadsheet)
+                    }
+                };
+                let error = if is_binary {
+                    eval_stack
+                        .last()
+                        .and_then(error_of)
+                        .or_else(|| error_of(&a))
+                } else {
+                    error_of(&a)
+                };
+                if let Some(error) = error {
+                    if is_binary {
+                        eval_stack.pop().ok_or(())?;
+                    }
+                    eval_stack.push(error);
+                    continue;
+                }
+
+                // Multi-cell ranges can't be used where a single value is expected
+                let is_range = |t: &Token| {
+                    t.token_type == TokenType::Array
+                        || t.referenced_cells().is_some_and(|c| c.len() > 1)
+                };
+                if !is_reference_operator
+                    && (is_range(&a) || (is_binary && eval_stack.last().is_some_and(is_range)))
+                {
+                    if is_binary {
+                        eval_stack.pop().ok_or(())?;
+                    }
+                    eval_stack.push(Token::new(TokenType::Error, String::from("#VALUE!")));
+                    continue;
+                }
                 match operator {
                     ":" | "," | " " => {
                         let b = eval_stack.pop().ok_or(())?;
                         if !(a.token_type == TokenType::Reference
                             && b.token_type == TokenType::Reference)
                         {
-                            eprintln!("Reference operation error");
+                            log::warn!("Reference operator applied to a non-reference");
                             return Err(());
                         }
                         eval_stack.push(Token::reference(apply_reference_operator(
                             a.reference_set.unwrap(),
                             b.reference_set.unwrap(),
                             operator,
+                            spreadsheet,
                         )));
                     }
+                    "#" => {
+                        // A1# refers to the whole range spilled from A1
+                        let spill = a.referenced_cells().filter(|c| c.len() == 1).and_then(|c| {
+                            // The anchor is evaluated first, so that its spill is known
+                            spreadsheet.get_cell_value(&c[0]).ok()?;
+                            spreadsheet.spill_range(&c[0])
+                        });
+                        eval_stack.push(match spill {
+                            Some([start, end]) => Token::reference(BTreeSet::from_iter(
+                                Reference::from_cell(&start).range(&Reference::from_cell(&end)),
+                            )),
+                            None => Token::new(TokenType::Error, String::from("#REF!")),
+                        });
+                    }
+                    "@" => {
+                        eval_stack.push(implicit_intersection(a, cell));
+                    }
                     "-1" => {
                         eval_stack.push(Token::new(
                             TokenType::Number,
-                            (-a.as_f32(spreadsheet)).to_string(),
+                            (-a.as_f64(spreadsheet)).to_string(),
                         ));
                     }
                     "%" => {
                         eval_stack.push(Token::new(
                             TokenType::Number,
-                            (a.as_f32(spreadsheet) / 100.).to_string(),
+                            (a.as_f64(spreadsheet) / 100.).to_string(),
                         ));
                     }
                     "+" | "-" | "*" | "/" | "^" => {
-                        let b = eval_stack.pop().unwrap();
+                        let b = eval_stack.pop().ok_or(())?;
 
-                        eval_stack.push(Token::new(
-                            TokenType::Number,
-                            apply_arithmetic_operator(
-                                b.as_f32(spreadsheet),
-                                a.as_f32(spreadsheet),
-                                operator,
-                            )
-                            .to_string(),
-                        ));
+                        let result = apply_arithmetic_operator(
+                            b.as_f64(spreadsheet),
+                            a.as_f64(spreadsheet),
+                            operator,
+                        );
+                        // Floats would otherwise give inf or NaN where Excel gives an error
+                        eval_stack.push(if operator == "/" && a.as_f64(spreadsheet) == 0.0 {
+                            Token::new(TokenType::Error, String::from("#DIV/0!"))
+                        } else if !result.is_finite() {
+                            Token::new(TokenType::Error, String::from("#NUM!"))
+                        } else {
+                            Token::new(TokenType::Number, result.to_string())
+                        });
                     }
                     "&" => {
-                        let b = eval_stack.pop().unwrap();
+                        let b = eval_stack.pop().ok_or(())?;
 
                         let mut concatenated =
                             b.as_string(spreadsheet) + a.as_string(spreadsheet).as_str();
 
                         // Determine type of concatenated variable (it may be a string, number, or boolean)
                         let mut concatenated_type = TokenType::String;
-                        if concatenated.parse::<f32>().is_ok() {
+                        if concatenated.parse::<f64>().is_ok_and(f64::is_finite) {
                             concatenated_type = TokenType::Number
                         } else if concatenated.to_uppercase() == "TRUE"
                             || concatenated.to_uppercase() == "FALSE"
@@ -683,13 +1063,13 @@ pub fn eval_tokens(tokens: Vec<Token>, spreadsheet: &Spreadsheet) -> Result<Toke
                         eval_stack.push(Token::new(concatenated_type, concatenated));
                     }
                     "=" | "<" | ">" | "<=" | ">=" | "<>" => {
-                        let b: Token = eval_stack.pop().unwrap();
+                        let b: Token = eval_stack.pop().ok_or(())?;
 
                         eval_stack.push(Token::new(
                             TokenType::Boolean,
                             apply_comparison_operator(
-                                b.as_f32(spreadsheet),
-                                a.as_f32(spreadsheet),
+                                b.as_f64(spreadsheet),
+                                a.as_f64(spreadsheet),
                                 operator,
                             )
                             .to_string()
@@ -704,7 +1084,7 @@ pub fn eval_tokens(tokens: Vec<Token>, spreadsheet: &Spreadsheet) -> Result<Toke
                     // println!("Eval stack at {}: {:?}", &token.content, eval_stack)
                     let mut args = Vec::new();
                     for _ in 0..token.function_n_args.unwrap() {
-                        args.push(eval_stack.pop().unwrap());
+                        args.push(eval_stack.pop().ok_or(())?);
                     }
                     args.reverse(); // Makes writing the functions a hell of a lot easier
 
@@ -719,9 +1099,14 @@ pub fn eval_tokens(tokens: Vec<Token>, spreadsheet: &Spreadsheet) -> Result<Toke
                         }
                     }
 
+                    if let Some(error) = args.iter().find(|a| a.token_type == TokenType::Error) {
+                        eval_stack.push(error.clone());
+                        continue;
+                    }
+
                     if let Ok(result) = func.call(args.as_slice(), spreadsheet) {
                         // println!("Result of function {}: {:?}", token.content, result);
-                        eval_stack.extend(result);
+                        eval_stack.extend(result.into_iter().map(finite_or_error));
                     }
                 } else {
                     return Err(());
@@ -743,7 +1128,11 @@ pub fn eval_tokens(tokens: Vec<Token>, spreadsheet: &Spreadsheet) -> Result<Toke
                 // }
                 eval_stack.push(token.clone());
             }
-            TokenType::String | TokenType::Boolean | TokenType::Number => {
+            TokenType::String
+            | TokenType::Boolean
+            | TokenType::Number
+            | TokenType::Error
+            | TokenType::Array => {
                 eval_stack.push(token.clone());
             }
             _ => {
@@ -759,3 +1148,159 @@ pub fn eval_tokens(tokens: Vec<Token>, spreadsheet: &Spreadsheet) -> Result<Toke
         Err(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn eval(formula: &str) -> String {
+        eval_formula(formula, &Spreadsheet::new(), None)
+            .unwrap()
+            .content
+    }
+
+    #[test]
+    fn scientific_notation() {
+        assert_eq!(eval("1e3+1"), "1001");
+        assert_eq!(eval("2.5E-4*4"), "0.001");
+        assert_eq!(eval("1E+2"), "100");
+        assert_eq!(eval("1e3&\"x\""), "1000x");
+    }
+
+    #[test]
+    fn joined_text_is_typed_like_cells() {
+        let spreadsheet = Spreadsheet::new();
+        let joined = |formula| {
+            eval_formula(formula, &spreadsheet, None)
+                .unwrap()
+                .token_type
+        };
+        assert_eq!(joined("1&2"), TokenType::Number);
+        assert_eq!(joined("\"tr\"&\"ue\""), TokenType::Boolean);
+        assert_eq!(joined("\"in\"&\"f\""), TokenType::String);
+        assert_eq!(joined("\"Na\"&\"N\""), TokenType::String);
+    }
+
+    #[test]
+    fn functions_give_errors_rather_than_nan() {
+        assert_eq!(eval("SQRT(-1)"), "#NUM!");
+        assert_eq!(eval("SQRT(-1)+1"), "#NUM!");
+        assert_eq!(eval("SUM(1e308,1e308)"), "#NUM!");
+        assert_eq!(eval("AVERAGE(A1:A3)"), "#DIV/0!");
+        assert_eq!(eval("SQRT(4)"), "2");
+    }
+
+    #[test]
+    fn exponents_need_digits() {
+        // Without digits after it, the e is left to be something else
+        let tokens = parse_formula("1e").unwrap();
+        assert_eq!(tokens[0].content, "1");
+        let tokens = parse_formula("2*E3").unwrap();
+        assert_eq!(tokens[0].content, "2");
+        assert_eq!(tokens[2].token_type, TokenType::Reference);
+    }
+
+    #[test]
+    fn cells_in_scientific_notation_are_numbers() {
+        let spreadsheet = Spreadsheet::from_text("n\n1e3\n2.5E-4\n", ',');
+        let sum = eval_formula("SUM(A2:A3)", &spreadsheet, None).unwrap();
+        assert_eq!(sum.content, "1000.00025");
+    }
+
+    #[test]
+    fn implicit_intersection_picks_the_formulas_row_or_column() {
+        let spreadsheet = Spreadsheet::from_text("1,2,3\n4,5,6\n7,8,9\n10,11,12\n", ',');
+        // Evaluated as the formula in a cell would be
+        let at = |formula, row, col| {
+            let cell = SpreadsheetCell { row, col };
+            cell_to_token(formula, &spreadsheet, Some(&cell))
+                .unwrap()
+                .content
+        };
+        assert_eq!(at("=@A1:A10", 2, 4), "7");
+        assert_eq!(at("=@A1:C1", 5, 1), "2");
+        assert_eq!(at("=@A1:C4", 1, 2), "6");
+        // Single cells are left alone, while ranges that don't share a row or column can't be
+        assert_eq!(at("=@B4", 0, 0), "11");
+        assert_eq!(at("=@A1:A10", 12, 4), "#VALUE!");
+        assert_eq!(at("=@A1:C1", 5, 4), "#VALUE!");
+    }
+
+    #[test]
+    fn ranges_arent_single_values() {
+        let spreadsheet = Spreadsheet::from_text("1\n2\n3\n", ',');
+        let value = |formula| {
+            cell_to_token(
+                formula,
+                &spreadsheet,
+                Some(&SpreadsheetCell { row: 1, col: 2 }),
+            )
+            .unwrap()
+            .content
+        };
+        assert_eq!(value("=A1:A3+1"), "#VALUE!");
+        assert_eq!(value("=1-A1:A3"), "#VALUE!");
+        assert_eq!(value("=A1+1"), "2");
+        // Unless narrowed down to one of their cells
+        assert_eq!(value("=@A1:A3+1"), "3");
+    }
+
+    #[test]
+    fn apostrophes_keep_cells_as_text() {
+        let spreadsheet = Spreadsheet::from_text("'123,'=1+1,'TRUE,''quoted\n", ',');
+        let token = |col| {
+            let cell = SpreadsheetCell { row: 0, col };
+            cell_to_token(spreadsheet.get_cell(&cell), &spreadsheet, Some(&cell)).unwrap()
+        };
+        assert_eq!(token(0), Token::new(TokenType::String, String::from("123")));
+        assert_eq!(
+            token(1),
+            Token::new(TokenType::String, String::from("=1+1"))
+        );
+        assert_eq!(
+            token(2),
+            Token::new(TokenType::String, String::from("TRUE"))
+        );
+        // Only the first apostrophe is taken off
+        assert_eq!(token(3).content, "'quoted");
+        // Text isn't counted as a number, even when it looks like one
+        let count = eval_formula("COUNT(A1:D1)", &spreadsheet, None).unwrap();
+        assert_eq!(count.content, "0");
+    }
+
+    #[test]
+    fn zero_padded_numbers_stay_as_typed() {
+        assert!(is_zero_padded("007"));
+        assert!(is_zero_padded("0123"));
+        assert!(!is_zero_padded("0"));
+        assert!(!is_zero_padded("0.5"));
+        assert!(!is_zero_padded("100"));
+        assert!(!is_zero_padded("-007"));
+
+        let spreadsheet = Spreadsheet::from_text("007,0.5\n", ',');
+        let value = |col| {
+            let cell = SpreadsheetCell { row: 0, col };
+            spreadsheet.get_cell_value(&cell).unwrap()
+        };
+        assert_eq!(value(0), Token::new(TokenType::String, String::from("007")));
+        assert_eq!(value(1).token_type, TokenType::Number);
+        assert_eq!(
+            eval_formula("A1&\"\"", &spreadsheet, None).unwrap().content,
+            "007"
+        );
+    }
+
+    #[test]
+    fn large_integers_and_decimals_keep_their_precision() {
+        assert_eq!(eval("16777217"), "16777217");
+        assert_eq!(eval("16777216+1"), "16777217");
+        assert_eq!(eval("2^53"), "9007199254740992");
+        assert_eq!(eval("123456789012*10"), "1234567890120");
+        assert_eq!(eval("0.1234567890123"), "0.1234567890123");
+        assert_eq!(eval("1/3"), "0.3333333333333333");
+
+        let spreadsheet = Spreadsheet::from_text("16777217\n0.125\n", ',');
+        let sum = eval_formula("SUM(A1:A2)", &spreadsheet, None).unwrap();
+        assert_eq!(sum.content, "16777217.125");
+    }
+}
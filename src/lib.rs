@@ -0,0 +1,31 @@
+// Formulas fail with a bare Err(()), which callers turn into error values of their own
+#![allow(clippy::result_unit_err)]
+//! The spreadsheet model and formula engine behind excel-tui, usable without the TUI.
+//!
+//! ```
+//! use excel_tui::{eval_formula, Spreadsheet, SpreadsheetCell};
+//!
+//! let mut spreadsheet = Spreadsheet::new();
+//! spreadsheet.set_cell(&SpreadsheetCell { row: 0, col: 0 }, "2");
+//! spreadsheet.set_cell(&SpreadsheetCell { row: 1, col: 0 }, "=A1*3");
+//!
+//! // Formulas are evaluated without the = that marks them in a cell
+//! let result = eval_formula("SUM(A1:A2)", &spreadsheet, None).unwrap();
+//! assert_eq!(result.content, "8");
+//! ```
+
+pub mod config;
+pub mod dependencies;
+pub mod formats;
+pub mod formula_functions;
+pub mod formulas;
+pub mod references;
+pub mod search;
+pub mod spreadsheet;
+pub mod undo_stack;
+pub mod utils;
+pub mod validation;
+
+pub use formulas::{eval_formula, parse_formula, Token, TokenType};
+pub use references::{parse_reference, Reference};
+pub use spreadsheet::{Spreadsheet, SpreadsheetCell};
@@ -0,0 +1,53 @@
+use std::fs::{File, OpenOptions};
+use std::io::{Result, Write};
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use log::{LevelFilter, Log, Metadata, Record};
+
+// Stdout belongs to the TUI, so logs go to a file given with --log instead.
+struct FileLogger {
+    file: Mutex<File>,
+}
+
+impl Log for FileLogger {
+    fn enabled(&self, _metadata: &Metadata) -> bool {
+        true
+    }
+
+    fn log(&self, record: &Record) {
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| d.as_millis());
+        // Logging should never take the app down with it, so failures are ignored
+        if let Ok(mut file) = self.file.lock() {
+            let _ = writeln!(
+                file,
+                "[{}] {:<5} {}: {}",
+                timestamp,
+                record.level(),
+                record.target(),
+                record.args()
+            );
+        }
+    }
+
+    fn flush(&self) {
+        if let Ok(mut file) = self.file.lock() {
+            let _ = file.flush();
+        }
+    }
+}
+
+pub fn init(path: &str) -> Result<()> {
+    let file = OpenOptions::new().create(true).append(true).open(path)?;
+    // Only fails if a logger was already set, in which case that one is kept
+    if log::set_logger(Box::leak(Box::new(FileLogger {
+        file: Mutex::new(file),
+    })))
+    .is_ok()
+    {
+        log::set_max_level(LevelFilter::Trace);
+    }
+    Ok(())
+}
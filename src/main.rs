@@ -1,25 +1,32 @@
 #![allow(unused)]
-use std::env;
-use std::io::Result;
+use std::io::{self, Error, ErrorKind, IsTerminal, Read, Result};
+use std::{env, fs};
 
 use clap::Parser;
-use config::Config;
+use config::{Config, CopyDelimiter, Locale};
+use copypasta::{ClipboardContext, ClipboardProvider};
+// The spreadsheet and formula engine come from the library, so the TUI's modules can keep
+// importing them from crate::
+use excel_tui::{
+    config, dependencies, formats, formula_functions, formulas, references, search, spreadsheet,
+    undo_stack, utils, validation,
+};
 use formulas::eval_formula;
-use spreadsheet::Spreadsheet;
+use sidecar::Sidecar;
+use spreadsheet::{decode_text, Spreadsheet, DEFAULT_COL_WIDTH};
 
 mod app;
-mod config;
-mod formula_functions;
-mod formulas;
-mod references;
-mod spreadsheet;
+mod commands;
+mod logging;
+mod sidecar;
 mod ui;
-mod undo_stack;
-mod utils;
 
 #[derive(Parser, Debug)]
 struct Args {
-    #[arg(value_name = "PATH", help = "Path to a CSV or XLSX file.")]
+    #[arg(
+        value_name = "PATH",
+        help = "Path to a CSV file, or - to read CSV from stdin."
+    )]
     path: Option<String>,
 
     #[arg(
@@ -37,29 +44,260 @@ struct Args {
         help = "Replace Nerd Font icons with plain text representations."
     )]
     ascii: bool,
+
+    #[arg(
+        long,
+        value_name = "WIDTH",
+        default_value_t = DEFAULT_COL_WIDTH,
+        help = "Default width of each column, in characters."
+    )]
+    col_width: u16,
+
+    #[arg(
+        long,
+        action,
+        help = "Paste copied formulas, not just their values, when copying within the app."
+    )]
+    copy_formulas: bool,
+
+    #[arg(
+        long,
+        action,
+        help = "Ask before committing a formula with missing parentheses added."
+    )]
+    confirm_balance: bool,
+
+    #[arg(
+        long,
+        value_enum,
+        default_value_t = CopyDelimiter::Tab,
+        help = "Separator between copied cells."
+    )]
+    copy_delimiter: CopyDelimiter,
+
+    #[arg(
+        long,
+        action,
+        conflicts_with = "path",
+        help = "Start with a new sheet made from tab- or comma-separated text on the clipboard."
+    )]
+    from_clipboard: bool,
+
+    #[arg(
+        long,
+        action,
+        help = "Search the values cells show instead of the formulas typed into them."
+    )]
+    search_values: bool,
+
+    #[arg(
+        long,
+        value_name = "N",
+        default_value_t = 0,
+        help = "Freeze the first N rows as headers, which stay in view while scrolling."
+    )]
+    header_rows: usize,
+
+    #[arg(
+        long,
+        action,
+        help = "Shade every other row, to make rows easier to follow across wide sheets."
+    )]
+    zebra: bool,
+
+    #[arg(
+        long,
+        action,
+        help = "Allow toggling an overlay of performance stats with F12."
+    )]
+    debug_overlay: bool,
+
+    #[arg(
+        long,
+        value_name = "PATH",
+        help = "Append debugging logs to a file, since the TUI takes over stdout."
+    )]
+    log: Option<String>,
+
+    #[arg(
+        long,
+        value_name = "CHAR",
+        default_value_t = '.',
+        help = "Character separating the whole and decimal parts of numbers."
+    )]
+    decimal_separator: char,
+
+    #[arg(
+        long,
+        value_name = "CHAR",
+        default_value_t = ',',
+        help = "Character separating groups of thousands in numbers."
+    )]
+    thousands_separator: char,
+
+    #[arg(
+        long,
+        value_name = "CHAR",
+        help = "Character separating cells in CSV files, instead of detecting it."
+    )]
+    delimiter: Option<char>,
+
+    #[arg(
+        long,
+        value_name = "CHAR",
+        help = "Skip lines starting with CHAR (ie. #) in CSV files, keeping them when saving as CSV."
+    )]
+    comment: Option<char>,
 }
 
 fn main() -> Result<()> {
     let args = Args::parse();
+    if let Some(path) = &args.log {
+        logging::init(path)?;
+    }
+    // Piped input is read like a path of -, ie. cat data.csv | excel-tui. Events still come from
+    // the terminal once it's read, since crossterm opens /dev/tty when stdin isn't one. Formulas
+    // on their own never wait for stdin, which may be left open by whatever ran them.
+    let from_stdin = match args.path.as_deref() {
+        Some(path) => path == "-",
+        None => !args.from_clipboard && args.formula.is_none() && !io::stdin().is_terminal(),
+    };
+    // The file the sheet came from, if any, for its sidecar
+    let file_path = args.path.as_deref().filter(|_| !from_stdin);
 
-    let spreadsheet = if let Some(path) = args.path {
-        Spreadsheet::from_csv(&path)?
+    // The text is read before making the locale, since its delimiter may be detected from it
+    let bytes = if from_stdin {
+        let mut bytes = Vec::new();
+        io::stdin().read_to_end(&mut bytes)?;
+        Some(bytes)
+    } else if let Some(path) = file_path {
+        Some(fs::read(path).map_err(|_| Error::new(ErrorKind::NotFound, "File not found"))?)
     } else {
-        Spreadsheet::new()
+        None
     };
+    // Files that aren't UTF-8 still open, with a warning once the app starts
+    let (text, utf8) = match bytes.map(decode_text) {
+        Some((text, utf8)) => (Some(text), utf8),
+        None if args.from_clipboard => (
+            Some(
+                ClipboardContext::new()
+                    .and_then(|mut clipboard| clipboard.get_contents())
+                    .map_err(|e| Error::other(e.to_string()))?,
+            ),
+            true,
+        ),
+        None => (None, true),
+    };
+    let (spreadsheet, delimiter) = match &text {
+        Some(text) if args.from_clipboard => {
+            Spreadsheet::from_clipboard(text, args.delimiter, args.comment, args.decimal_separator)
+        }
+        Some(text) => {
+            Spreadsheet::from_csv(text, args.delimiter, args.comment, args.decimal_separator)
+        }
+        None => (Spreadsheet::new(), args.delimiter.unwrap_or(',')),
+    };
+
+    let locale = Locale::new(args.decimal_separator, args.thousands_separator, delimiter)
+        .map_err(|e| Error::new(ErrorKind::InvalidInput, e))?;
+    let config = Config {
+        nerd_font: !args.ascii,
+        default_col_width: args.col_width,
+        copy_formulas: args.copy_formulas,
+        copy_delimiter: args.copy_delimiter,
+        confirm_balance: args.confirm_balance,
+        debug_overlay: args.debug_overlay,
+        search_values: args.search_values,
+        zebra: args.zebra,
+        locale,
+    };
+
+    let spreadsheet = spreadsheet
+        .with_locale(config.locale.clone())
+        .with_default_col_width(config.default_col_width);
 
     if let Some(formula) = args.formula {
-        println!("{}", eval_formula(&formula, &spreadsheet).unwrap().content);
+        println!(
+            "{}",
+            eval_formula(&formula, &spreadsheet, None).unwrap().content
+        );
         return Ok(());
     }
 
     let mut terminal = app::init()?;
-    let mut app = app::App::new(Config {
-        nerd_font: !args.ascii,
-    });
-    app.spreadsheet = spreadsheet;
+    let mut app = open_app(config, spreadsheet, args.header_rows);
+    if !utf8 {
+        app.notify("Not UTF-8, so some characters show as �");
+    }
+    if let Some(sidecar) = file_path.and_then(Sidecar::load) {
+        app.infinite_table_state
+            .restore_view_state(&sidecar, &app.spreadsheet.col_widths);
+        // Columns go first, since styling a column replaces the styles of its cells
+        for (col, style) in sidecar.col_styles {
+            app.spreadsheet.set_col_text_style(col, style);
+        }
+        for (cell, style) in sidecar.cell_styles {
+            app.spreadsheet.set_text_style(&cell, style);
+        }
+    }
 
+    let opened = sidecar_for(&app);
     let app_result = app.run(&mut terminal);
     app::restore()?;
+    // Files only get a sidecar once there's something in it, and failing to write one doesn't
+    // hide how the app itself exited
+    let sidecar = sidecar_for(&app);
+    if let Some(path) = file_path.filter(|_| sidecar != opened) {
+        if let Err(e) = sidecar.save(path) {
+            log::warn!("Couldn't save {}: {}", Sidecar::path_for(path), e);
+        }
+    }
     app_result
 }
+
+fn sidecar_for(app: &app::App) -> Sidecar {
+    // Sorted, so the same view and styles always make the same sidecar
+    let mut sidecar = app.infinite_table_state.view_state();
+    sidecar.col_styles = app
+        .spreadsheet
+        .col_text_styles()
+        .map(|(col, style)| (*col, *style))
+        .collect();
+    sidecar.col_styles.sort_by_key(|(col, _)| *col);
+    sidecar.cell_styles = app
+        .spreadsheet
+        .cell_text_styles()
+        .map(|(cell, style)| (cell.clone(), *style))
+        .collect();
+    sidecar
+        .cell_styles
+        .sort_by_key(|(cell, _)| (cell.row, cell.col));
+    sidecar
+}
+
+fn open_app(config: Config, mut spreadsheet: Spreadsheet, header_rows: usize) -> app::App {
+    // Frozen headers are headers whether or not they were detected as such
+    if header_rows > 0 {
+        spreadsheet.set_header_rows(header_rows);
+    }
+    let mut app = app::App::new(config);
+    app.spreadsheet = spreadsheet;
+    app.infinite_table_state
+        .set_limits(app.spreadsheet.max_rows(), app.spreadsheet.max_cols());
+    app.infinite_table_state.set_frozen_rows(header_rows);
+    app
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn header_rows_are_frozen_on_load() {
+        let args = Args::parse_from(["excel-tui", "--header-rows", "3", "data.csv"]);
+        let spreadsheet = Spreadsheet::from_text("1,2\n3,4\n5,6\n7,8\n", ',');
+        let app = open_app(Config::default(), spreadsheet, args.header_rows);
+        assert_eq!(app.infinite_table_state.frozen_rows(), 3);
+        assert_eq!(app.spreadsheet.header_rows(), 3);
+    }
+}
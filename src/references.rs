@@ -1,6 +1,8 @@
 use std::cmp::{max, min};
 
-use crate::spreadsheet::SpreadsheetCell;
+use crate::spreadsheet::{
+    Spreadsheet, SpreadsheetCell, SPREADSHEET_MAX_COLS, SPREADSHEET_MAX_ROWS,
+};
 
 #[derive(Hash, Clone, PartialEq, Eq, PartialOrd, Ord)]
 pub struct Reference {
@@ -31,34 +33,80 @@ impl Reference {
         cells
     }
 
+    pub fn bounds(&self, other: &Reference) -> [SpreadsheetCell; 2] {
+        // The corners of the range between two references, where whole columns or rows (ie. A:A
+        // or 1:1) reach the edges of the sheet
+        let span = |a: Option<usize>, b: Option<usize>, limit: usize| match (a, b) {
+            (Some(a), Some(b)) => (min(a, b), max(a, b)),
+            _ => (0, limit - 1),
+        };
+        let (min_row, max_row) = span(self.row, other.row, SPREADSHEET_MAX_ROWS);
+        let (min_col, max_col) = span(self.col, other.col, SPREADSHEET_MAX_COLS);
+        [
+            SpreadsheetCell {
+                row: min_row,
+                col: min_col,
+            },
+            SpreadsheetCell {
+                row: max_row,
+                col: max_col,
+            },
+        ]
+    }
+
+    pub fn range_within(&self, other: &Reference, spreadsheet: &Spreadsheet) -> Vec<Reference> {
+        // Like range, but whole columns and rows only go as far as the sheet's data, rather than
+        // over a million empty cells
+        let [start, mut end] = self.bounds(other);
+        if self.row.is_none() || other.row.is_none() {
+            let Some(last_row) = spreadsheet.data_rows().checked_sub(1) else {
+                return Vec::new();
+            };
+            end.row = min(end.row, last_row);
+        }
+        if self.col.is_none() || other.col.is_none() {
+            let Some(last_col) = spreadsheet.data_cols(start.row..=end.row).checked_sub(1) else {
+                return Vec::new();
+            };
+            end.col = min(end.col, last_col);
+        }
+        if start.row > end.row || start.col > end.col {
+            return Vec::new();
+        }
+        Reference::from_cell(&start).range(&Reference::from_cell(&end))
+    }
+
     pub fn to_string(&self) -> String {
         if self.row.is_some() && self.col.is_some() {
             return format!(
-                "({},{})",
+                "{}{}",
                 Reference::index_to_alpha(self.col.unwrap() as u32 + 1),
                 self.row.unwrap() + 1
             );
         }
-        if self.row.is_some() {
-            return format!("{}", self.row.unwrap_or(0));
+        // Partial references are one side of a whole row or column reference (ie. 1:1, A:A)
+        if let Some(row) = self.row {
+            return format!("{}", row + 1);
         }
-        if self.col.is_some() {
-            return format!("{}", self.row.unwrap_or(0));
+        if let Some(col) = self.col {
+            return Reference::index_to_alpha(col as u32 + 1);
         }
         return String::new();
     }
 
     pub fn alpha_to_index(alpha: &str) -> Option<u32> {
         // Converts an Excel alphabetized column id (A, BC, XFD, etc.) into a 1-indexed number
-        let mut index = 0;
-        for (rev_idx, c) in alpha.chars().into_iter().rev().enumerate() {
+        // This is bijective base-26 (there's no zero digit), so each letter is worth 1-26.
+        let mut index: u32 = 0;
+        for c in alpha.chars() {
             if !c.is_ascii_alphabetic() {
                 return None;
             }
             // 1-indexed alphabet index, found from subtracting the unicode
             // number for @ (the character before A) from the letter's number
-            let alphabet_idx = c as u32 - '@' as u32;
-            index += alphabet_idx.wrapping_mul(26u32.pow(rev_idx as u32));
+            let alphabet_idx = c.to_ascii_uppercase() as u32 - '@' as u32;
+            // Overly long words can't be columns, so overflowing is just a failure
+            index = index.checked_mul(26)?.checked_add(alphabet_idx)?;
         }
 
         Some(index)
@@ -84,6 +132,40 @@ impl Reference {
             .join("")
     }
 
+    pub fn is_cell(&self) -> bool {
+        self.row.is_some() && self.col.is_some()
+    }
+
+    pub fn is_in_bounds(&self) -> bool {
+        // References past the last row or column of a sheet (ie. XFE1, A1048577) are Excel's #REF!
+        self.is_within(SPREADSHEET_MAX_ROWS, SPREADSHEET_MAX_COLS)
+    }
+
+    pub fn is_within(&self, max_rows: usize, max_cols: usize) -> bool {
+        // Like is_in_bounds, for sheets limited to fewer rows or columns than Excel's
+        self.row.is_none_or(|row| row < max_rows) && self.col.is_none_or(|col| col < max_cols)
+    }
+
+    pub fn offset(&self, rows: i64, cols: i64) -> Option<Reference> {
+        // Moves the reference, failing if it would move off of the sheet (Excel's #REF!)
+        let shift = |value: Option<usize>, delta: i64| match value {
+            Some(v) => usize::try_from(v as i64 + delta).ok().map(Some),
+            None => Some(None),
+        };
+        Some(Reference {
+            row: shift(self.row, rows)?,
+            col: shift(self.col, cols)?,
+        })
+        .filter(Reference::is_in_bounds)
+    }
+
+    pub fn from_cell(cell: &SpreadsheetCell) -> Reference {
+        Reference {
+            row: Some(cell.row),
+            col: Some(cell.col),
+        }
+    }
+
     pub fn get_cell(&self) -> SpreadsheetCell {
         // TODO: Handle when it's just a row or col (ie. A:A, 1:1, etc.)
         return SpreadsheetCell {
@@ -137,10 +219,52 @@ pub fn parse_reference(text: &str) -> Option<Reference> {
         } else {
             None
         },
-        row: if row.len() > 0 && row.parse::<usize>().is_ok() {
-            Some(row.parse::<usize>().unwrap() - 1) // Unwrap okay
+        row: if !row.is_empty() {
+            // Rows start at 1, so row 0 (ie. A0) isn't a reference
+            Some(row.parse::<usize>().ok()?.checked_sub(1)?)
         } else {
             None
         },
     })
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn column_ids_round_trip() {
+        for index in 1..=16384 {
+            let alpha = Reference::index_to_alpha(index);
+            assert_eq!(Reference::alpha_to_index(&alpha), Some(index), "{}", alpha);
+        }
+    }
+
+    #[test]
+    fn column_ids_have_no_zero_digit() {
+        let cases = [(1, "A"), (26, "Z"), (27, "AA"), (52, "AZ"), (53, "BA")];
+        for (index, alpha) in cases {
+            assert_eq!(Reference::index_to_alpha(index), alpha);
+            assert_eq!(Reference::alpha_to_index(alpha), Some(index));
+        }
+        assert_eq!(Reference::index_to_alpha(702), "ZZ");
+        assert_eq!(Reference::index_to_alpha(703), "AAA");
+        assert_eq!(Reference::alpha_to_index("XFD"), Some(16384));
+        assert_eq!(Reference::alpha_to_index("xfd"), Some(16384));
+        assert_eq!(Reference::alpha_to_index("A1"), None);
+    }
+
+    #[test]
+    fn whole_columns_only_reach_the_data() {
+        let spreadsheet = Spreadsheet::from_text("1,x\n2\n3\n4\n5\n", ',');
+        let column = parse_reference("A").unwrap();
+        let cells = column.range_within(&column, &spreadsheet);
+        assert_eq!(cells.len(), 5);
+        assert_eq!(cells.last(), parse_reference("A5").as_ref());
+
+        let sum = crate::formulas::eval_formula("SUM(A:A)", &spreadsheet, None).unwrap();
+        assert_eq!(sum.content, "15");
+        let sum = crate::formulas::eval_formula("SUM(A:B)", &spreadsheet, None).unwrap();
+        assert_eq!(sum.content, "15");
+    }
+}
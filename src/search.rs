@@ -0,0 +1,47 @@
+use regex::{NoExpand, Regex};
+
+#[derive(Debug, Clone)]
+pub struct SearchPattern {
+    // Plain text searches are compiled too, as an escaped case-insensitive pattern, so both
+    // kinds match and replace the same way
+    regex: Regex,
+    is_regex: bool,
+}
+
+impl SearchPattern {
+    pub fn new(pattern: &str, is_regex: bool) -> Result<SearchPattern, String> {
+        let source = if is_regex {
+            pattern.to_string()
+        } else {
+            format!("(?i){}", regex::escape(pattern))
+        };
+        // Syntax errors draw the pattern with the problem marked, then name it on the last line
+        let regex = Regex::new(&source).map_err(|e| match e {
+            regex::Error::Syntax(message) => {
+                let problem = message.lines().last().unwrap_or_default();
+                format!(
+                    "Invalid pattern: {}",
+                    problem.strip_prefix("error: ").unwrap_or(problem)
+                )
+            }
+            e => e.to_string(),
+        })?;
+        Ok(SearchPattern { regex, is_regex })
+    }
+
+    pub fn is_match(&self, text: &str) -> bool {
+        self.regex.is_match(text)
+    }
+
+    pub fn replace_all(&self, text: &str, replacement: &str) -> String {
+        // Regex replacements can use capture groups (ie. $1 or ${name}), which plain text
+        // replacements take literally
+        if self.is_regex {
+            self.regex.replace_all(text, replacement).into_owned()
+        } else {
+            self.regex
+                .replace_all(text, NoExpand(replacement))
+                .into_owned()
+        }
+    }
+}
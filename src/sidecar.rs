@@ -0,0 +1,146 @@
+use std::fs;
+use std::io::Result;
+
+use crate::formats::TextStyle;
+use crate::spreadsheet::SpreadsheetCell;
+
+// Metadata that doesn't belong in the spreadsheet file itself, stored next to it as
+// `<path>.excel-tui` in a simple `key=value` format.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct Sidecar {
+    pub active_cell: SpreadsheetCell,
+    pub vertical_scroll: u32,
+    pub horizontal_scroll: u32,
+    // Text styles, which CSV files have no way of holding
+    pub cell_styles: Vec<(SpreadsheetCell, TextStyle)>,
+    pub col_styles: Vec<(usize, TextStyle)>,
+}
+
+impl Sidecar {
+    pub fn path_for(path: &str) -> String {
+        format!("{}.excel-tui", path)
+    }
+
+    pub fn load(path: &str) -> Option<Sidecar> {
+        let contents = fs::read_to_string(Sidecar::path_for(path)).ok()?;
+        let mut sidecar = Sidecar::default();
+
+        for line in contents.lines() {
+            // Unknown or malformed lines are skipped, so older sidecars still load
+            let Some((key, value)) = line.split_once('=') else {
+                continue;
+            };
+            match (key.trim(), value.trim()) {
+                ("active_row", value) => {
+                    if let Ok(row) = value.parse() {
+                        sidecar.active_cell.row = row;
+                    }
+                }
+                ("active_col", value) => {
+                    if let Ok(col) = value.parse() {
+                        sidecar.active_cell.col = col;
+                    }
+                }
+                ("vertical_scroll", value) => {
+                    if let Ok(scroll) = value.parse() {
+                        sidecar.vertical_scroll = scroll;
+                    }
+                }
+                ("horizontal_scroll", value) => {
+                    if let Ok(scroll) = value.parse() {
+                        sidecar.horizontal_scroll = scroll;
+                    }
+                }
+                // Styles are written as row,col,flags (ie. 2,0,bi) or col,flags for columns
+                ("cell_style", value) => {
+                    let mut parts = value.splitn(3, ',');
+                    if let (Some(Ok(row)), Some(Ok(col)), Some(flags)) = (
+                        parts.next().map(str::parse),
+                        parts.next().map(str::parse),
+                        parts.next(),
+                    ) {
+                        let style = TextStyle::from_flags(flags);
+                        sidecar
+                            .cell_styles
+                            .push((SpreadsheetCell { row, col }, style));
+                    }
+                }
+                ("col_style", value) => {
+                    if let Some((Ok(col), flags)) = value
+                        .split_once(',')
+                        .map(|(col, flags)| (col.parse(), flags))
+                    {
+                        sidecar.col_styles.push((col, TextStyle::from_flags(flags)));
+                    }
+                }
+                _ => (),
+            }
+        }
+
+        Some(sidecar)
+    }
+
+    pub fn save(&self, path: &str) -> Result<()> {
+        let mut contents = format!(
+            "active_row={}\nactive_col={}\nvertical_scroll={}\nhorizontal_scroll={}\n",
+            self.active_cell.row,
+            self.active_cell.col,
+            self.vertical_scroll,
+            self.horizontal_scroll
+        );
+        for (col, style) in self.col_styles.iter() {
+            contents += &format!("col_style={},{}\n", col, style.to_flags());
+        }
+        for (cell, style) in self.cell_styles.iter() {
+            contents += &format!(
+                "cell_style={},{},{}\n",
+                cell.row,
+                cell.col,
+                style.to_flags()
+            );
+        }
+        fs::write(Sidecar::path_for(path), contents)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_path(name: &str) -> String {
+        let path = std::env::temp_dir().join(format!("excel-tui-{}-{}", std::process::id(), name));
+        path.to_string_lossy().into_owned()
+    }
+
+    #[test]
+    fn view_state_round_trips() {
+        let path = temp_path("round-trip.csv");
+        let sidecar = Sidecar {
+            active_cell: SpreadsheetCell { row: 40, col: 3 },
+            vertical_scroll: 30,
+            horizontal_scroll: 12,
+            cell_styles: vec![(
+                SpreadsheetCell { row: 2, col: 0 },
+                TextStyle::from_flags("bi"),
+            )],
+            col_styles: vec![(1, TextStyle::from_flags("u"))],
+        };
+        sidecar.save(&path).unwrap();
+        let loaded = Sidecar::load(&path);
+        fs::remove_file(Sidecar::path_for(&path)).unwrap();
+        assert_eq!(loaded, Some(sidecar));
+    }
+
+    #[test]
+    fn malformed_lines_are_skipped() {
+        let path = temp_path("malformed.csv");
+        let contents =
+            "active_row=oops\nactive_col=2\nvertical_scroll=-1\nnonsense\nhorizontal_scroll=5\n";
+        fs::write(Sidecar::path_for(&path), contents).unwrap();
+        let loaded = Sidecar::load(&path).unwrap();
+        fs::remove_file(Sidecar::path_for(&path)).unwrap();
+        assert_eq!(loaded.active_cell, SpreadsheetCell { row: 0, col: 2 });
+        assert_eq!(loaded.vertical_scroll, 0);
+        assert_eq!(loaded.horizontal_scroll, 5);
+    }
+}
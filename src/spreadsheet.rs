@@ -1,13 +1,24 @@
-use std::cmp::{max, min};
+use std::cmp::{max, min, Ordering};
+use std::collections::{HashMap, HashSet};
 use std::fmt::Display;
-use std::io::{Error, ErrorKind};
-use std::ops::Index;
-use std::{cell, fs};
+use std::fs;
+use std::io::Error;
+use std::ops::RangeInclusive;
+use std::sync::atomic::{self, AtomicU64, AtomicUsize};
+use std::sync::RwLock;
 
-use strum::Display;
+use rayon::prelude::*;
+use rust_xlsxwriter::{Format, FormatUnderline, Workbook, XlsxError};
 
-use crate::formulas::{cell_to_token, Token};
+use crate::config::Locale;
+use crate::dependencies::{can_spill, referenced_ranges, value_inputs, DependencyGraph};
+use crate::formats::{CellColors, CellFormat, TextStyle};
+use crate::formula_functions::meets_criteria;
+use crate::formulas::{cell_to_token, is_zero_padded, ShiftableFormula, Token, TokenType};
+use crate::references::Reference;
+use crate::search::SearchPattern;
 use crate::undo_stack::UndoStack;
+use crate::validation::Validation;
 
 #[derive(Debug)]
 pub struct SpreadsheetRowIteratorItem {
@@ -42,32 +53,177 @@ pub struct SpreadsheetCell {
 pub const SPREADSHEET_MAX_ROWS: usize = 2usize.pow(20);
 pub const SPREADSHEET_MAX_COLS: usize = 2usize.pow(14);
 pub const DEFAULT_COL_WIDTH: u16 = 10;
+// Narrower columns couldn't be seen, or grabbed to widen them again
+pub const MIN_COL_WIDTH: u16 = 1;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct CellLook {
+    // Formatting set directly on a cell or column, where None falls back to the column (or default)
+    format: Option<CellFormat>,
+    style: Option<TextStyle>,
+    colors: Option<CellColors>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RowGroup {
+    // Rows outlined together, which collapse into the row after them like Excel's groups
+    pub start: usize,
+    pub end: usize,
+    pub collapsed: bool,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum LookTarget {
+    Cell(SpreadsheetCell),
+    Col(usize),
+}
 
 #[derive(Debug, Clone)]
-pub struct SpreadsheetEdit {
-    cell: SpreadsheetCell,
-    before: String,
-    after: String,
+pub enum SpreadsheetEdit {
+    // A change to a cell's contents
+    Content {
+        cell: SpreadsheetCell,
+        before: String,
+        after: String,
+    },
+    // A change to how a cell or column is formatted
+    Look {
+        target: LookTarget,
+        before: CellLook,
+        after: CellLook,
+    },
+    // A column resized by dragging its border
+    Width {
+        col: usize,
+        before: u16,
+        after: u16,
+    },
+}
+
+impl SpreadsheetEdit {
+    fn cell(&self) -> SpreadsheetCell {
+        // Where the edit happened, with columns counting as their first cell
+        match self {
+            SpreadsheetEdit::Content { cell, .. } => cell.clone(),
+            SpreadsheetEdit::Look { target, .. } => match target {
+                LookTarget::Cell(cell) => cell.clone(),
+                LookTarget::Col(col) => SpreadsheetCell { row: 0, col: *col },
+            },
+            SpreadsheetEdit::Width { col, .. } => SpreadsheetCell { row: 0, col: *col },
+        }
+    }
 }
 
 impl PartialEq for SpreadsheetEdit {
     fn eq(&self, other: &Self) -> bool {
-        self.after == other.after && self.cell == other.cell
+        match (self, other) {
+            (
+                SpreadsheetEdit::Content { cell, after, .. },
+                SpreadsheetEdit::Content {
+                    cell: other_cell,
+                    after: other_after,
+                    ..
+                },
+            ) => after == other_after && cell == other_cell,
+            (
+                SpreadsheetEdit::Look { target, after, .. },
+                SpreadsheetEdit::Look {
+                    target: other_target,
+                    after: other_after,
+                    ..
+                },
+            ) => after == other_after && target == other_target,
+            (
+                SpreadsheetEdit::Width { col, after, .. },
+                SpreadsheetEdit::Width {
+                    col: other_col,
+                    after: other_after,
+                    ..
+                },
+            ) => after == other_after && col == other_col,
+            _ => false,
+        }
     }
 }
 
 impl Display for SpreadsheetEdit {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "{:?}: {} -> {}", self.cell, self.before, self.after)
+        match self {
+            SpreadsheetEdit::Content {
+                cell,
+                before,
+                after,
+            } => write!(f, "{:?}: {} -> {}", cell, before, after),
+            SpreadsheetEdit::Look {
+                target,
+                before,
+                after,
+            } => write!(f, "{:?}: {:?} -> {:?}", target, before, after),
+            SpreadsheetEdit::Width { col, before, after } => {
+                write!(f, "Col({}) width: {} -> {}", col, before, after)
+            }
+        }
     }
 }
 
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct SpreadsheetStats {
+    pub used_rows: usize,
+    pub used_cols: usize,
+    pub used_cells: usize,
+    pub undo_depth: usize,
+    pub cached_values: usize,
+    pub approx_bytes: usize, // Cell contents and undo history, not counting allocator overhead
+}
+
 #[derive(Debug, Default)]
 pub struct Spreadsheet {
     data: Vec<SpreadsheetRow>,
     pub col_widths: Vec<u16>,
-    row_heights: Vec<u16>,
+    // Hidden columns and rows keep their data and size, so showing them again restores both
+    hidden_cols: HashSet<usize>,
+    hidden_rows: HashSet<usize>,
+    row_groups: Vec<RowGroup>, // Groups can nest, with inner ones collapsing separately
+    // Criteria rows must meet in each column (ie. >5 or *apple*), and the rows they hid when they
+    // were last applied, kept apart from rows hidden by hand
+    filters: HashMap<usize, String>,
+    filtered_rows: HashSet<usize>,
+    default_col_width: u16,
+    max_rows: usize,
+    max_cols: usize,
+    header_rows: usize, // How many rows at the top hold column names rather than data
+    // Comment lines skipped when reading the file, with the row each came before, which are
+    // written back when saving as CSV
+    comments: Vec<(usize, String)>,
+    pub locale: Locale,
     pub undo_stack: UndoStack<Vec<SpreadsheetEdit>>,
+    // Formats of single cells, which take precedence over their column's format
+    formats: HashMap<SpreadsheetCell, CellFormat>,
+    col_formats: HashMap<usize, CellFormat>, // Cells with neither are General
+    // Bold, italic and underline, which also work like formats
+    styles: HashMap<SpreadsheetCell, TextStyle>,
+    col_styles: HashMap<usize, TextStyle>,
+    colors: HashMap<SpreadsheetCell, CellColors>,
+    col_colors: HashMap<usize, CellColors>,
+    // Rules for what can be entered, which also work like formats
+    validations: HashMap<SpreadsheetCell, Validation>,
+    col_validations: HashMap<usize, Validation>,
+    // Evaluated cell values, so formulas referenced many times are only computed once per edit
+    // (behind locks rather than RefCells, so formulas can be evaluated on many threads at once)
+    value_cache: RwLock<HashMap<SpreadsheetCell, Result<Token, ()>>>,
+    // The ranges each cached formula read, so an edit only drops the values depending on it
+    value_inputs: RwLock<HashMap<SpreadsheetCell, Vec<[SpreadsheetCell; 2]>>>,
+    // Values of formulas returning multiple cells, keyed by the cell they spill from
+    spills: RwLock<HashMap<SpreadsheetCell, Vec<Vec<Token>>>>,
+    // Formulas that can return arrays, found as they're entered, which are the only ones that
+    // have to be evaluated to know what spills where
+    spill_anchors: HashSet<SpreadsheetCell>,
+    // How many rows from the top have had every formula evaluated, so their spills are known
+    spill_rows_known: AtomicUsize,
+    spill_changes: AtomicU64,
+    // Counts changes to what cells show (their contents or formats), so anything drawn from the
+    // sheet can tell when it's out of date
+    revision: u64,
 }
 
 impl Spreadsheet {
@@ -75,21 +231,83 @@ impl Spreadsheet {
         Self {
             data: Vec::new(),
             col_widths: vec![DEFAULT_COL_WIDTH; SPREADSHEET_MAX_COLS],
-            row_heights: Vec::new(),
+            hidden_cols: HashSet::new(),
+            hidden_rows: HashSet::new(),
+            row_groups: Vec::new(),
+            filters: HashMap::new(),
+            filtered_rows: HashSet::new(),
+            default_col_width: DEFAULT_COL_WIDTH,
+            max_rows: SPREADSHEET_MAX_ROWS,
+            max_cols: SPREADSHEET_MAX_COLS,
+            header_rows: 0,
+            comments: Vec::new(),
+            locale: Locale::default(),
             undo_stack: UndoStack::default(),
+            formats: HashMap::new(),
+            col_formats: HashMap::new(),
+            styles: HashMap::new(),
+            col_styles: HashMap::new(),
+            colors: HashMap::new(),
+            col_colors: HashMap::new(),
+            validations: HashMap::new(),
+            col_validations: HashMap::new(),
+            value_cache: RwLock::new(HashMap::new()),
+            value_inputs: RwLock::new(HashMap::new()),
+            spills: RwLock::new(HashMap::new()),
+            spill_anchors: HashSet::new(),
+            spill_rows_known: AtomicUsize::new(0),
+            spill_changes: AtomicU64::new(0),
+            revision: 0,
         }
     }
 
     // pub fn load_rows(&mut self, lower: i32, upper: i32) {}
 
-    pub fn from_csv(path: &str) -> Result<Spreadsheet, Error> {
-        let contents = match fs::read_to_string(path) {
-            Ok(c) => c,
-            Err(_) => return Err(Error::new(ErrorKind::NotFound, "File not found")),
+    pub fn from_csv(
+        contents: &str,
+        delimiter: Option<char>,
+        comment: Option<char>,
+        decimal_separator: char,
+    ) -> (Spreadsheet, char) {
+        // Files are usually comma-separated, returning the delimiter that was used
+        Spreadsheet::load_text(contents, delimiter, comment, decimal_separator, ',')
+    }
+
+    pub fn from_clipboard(
+        text: &str,
+        delimiter: Option<char>,
+        comment: Option<char>,
+        decimal_separator: char,
+    ) -> (Spreadsheet, char) {
+        // Clipboard contents may be tab-separated (from other spreadsheets) or comma-separated
+        Spreadsheet::load_text(text, delimiter, comment, decimal_separator, '\t')
+    }
+
+    fn load_text(
+        contents: &str,
+        delimiter: Option<char>,
+        comment: Option<char>,
+        decimal_separator: char,
+        fallback: char,
+    ) -> (Spreadsheet, char) {
+        // Comments come out first, so they can't throw off delimiter detection
+        let (contents, comments) = match comment {
+            Some(comment) => strip_comments(strip_bom(contents), comment),
+            None => (strip_bom(contents).to_string(), Vec::new()),
         };
-        let parsed: Vec<SpreadsheetRow> = contents
-            .lines()
-            .map(parse_csv_line)
+        let delimiter = delimiter
+            .or_else(|| detect_delimiter(&contents, decimal_separator))
+            .unwrap_or(fallback);
+        log::debug!("Using {:?} as the CSV delimiter", delimiter);
+        (
+            Spreadsheet::from_text(&contents, delimiter).with_comments(comments),
+            delimiter,
+        )
+    }
+
+    pub fn from_text(contents: &str, delimiter: char) -> Spreadsheet {
+        let mut parsed: Vec<SpreadsheetRow> = parse_csv(strip_bom(contents), delimiter)
+            .into_iter()
             .enumerate()
             .map(|(idx, line)| SpreadsheetRow {
                 row_idx: idx,
@@ -97,25 +315,339 @@ impl Spreadsheet {
             })
             .collect();
 
-        let max_cols = parsed.iter().map(|r| r.contents.len()).max().unwrap_or(10);
+        // Blank lines at the end of the file (ie. "a,b\n\n\n") aren't real rows
+        while parsed
+            .last()
+            .is_some_and(|r| r.contents.iter().all(|c| c.is_empty()))
+        {
+            parsed.pop();
+        }
 
-        return Ok(Spreadsheet {
+        let header_rows = usize::from(detect_header(&parsed));
+        let mut spreadsheet = Spreadsheet {
             data: parsed,
-            col_widths: vec![DEFAULT_COL_WIDTH; SPREADSHEET_MAX_COLS],
-            row_heights: Vec::new(),
-            undo_stack: UndoStack::default(),
-        });
+            header_rows,
+            ..Spreadsheet::new()
+        };
+        spreadsheet.find_spill_anchors();
+        spreadsheet
+    }
+
+    pub fn with_default_col_width(mut self, width: u16) -> Self {
+        // Meant to be used right after loading, as it resets every column to the new width
+        self.default_col_width = width;
+        self.col_widths = vec![width; self.max_cols];
+        self
+    }
+
+    pub fn with_header(mut self, has_header: bool) -> Self {
+        self.set_has_header(has_header);
+        self
+    }
+
+    pub fn with_comments(mut self, comments: Vec<(usize, String)>) -> Self {
+        self.comments = comments;
+        self
+    }
+
+    pub fn has_header(&self) -> bool {
+        self.header_rows > 0
+    }
+
+    pub fn set_has_header(&mut self, has_header: bool) {
+        self.header_rows = usize::from(has_header);
+    }
+
+    pub fn header_rows(&self) -> usize {
+        self.header_rows
+    }
+
+    pub fn set_header_rows(&mut self, rows: usize) {
+        self.header_rows = rows;
+    }
+
+    pub fn with_locale(mut self, locale: Locale) -> Self {
+        self.locale = locale;
+        self
+    }
+
+    pub fn with_limits(mut self, max_rows: usize, max_cols: usize) -> Self {
+        // Shrinks the sheet from Excel's limits, dropping anything that no longer fits
+        self.max_rows = max_rows.clamp(1, SPREADSHEET_MAX_ROWS);
+        self.max_cols = max_cols.clamp(1, SPREADSHEET_MAX_COLS);
+        self.data.truncate(self.max_rows);
+        self.invalidate_values();
+        let (max_rows, max_cols) = (self.max_rows, self.max_cols);
+        self.spill_anchors
+            .retain(|cell| cell.row < max_rows && cell.col < max_cols);
+        for row in self.data.iter_mut() {
+            row.contents.truncate(self.max_cols);
+        }
+        self.col_widths
+            .resize(self.max_cols, self.default_col_width);
+        self
+    }
+
+    pub fn max_rows(&self) -> usize {
+        self.max_rows
+    }
+
+    pub fn max_cols(&self) -> usize {
+        self.max_cols
+    }
+
+    pub fn in_bounds(&self, cell: &SpreadsheetCell) -> bool {
+        cell.row < self.max_rows && cell.col < self.max_cols
+    }
+
+    pub fn to_csv(&self) -> String {
+        // Every row ends in exactly one newline, so loading and saving never adds blank lines.
+        // Cells are written as they would be typed (ie. 5% rather than 0.05), since the sidecar
+        // doesn't keep formats
+        let mut csv = String::new();
+        let mut comments = self.comments.iter().peekable();
+        for (idx, r) in self.data.iter().enumerate() {
+            while let Some((_, comment)) = comments.next_if(|(row, _)| *row <= idx) {
+                csv += comment;
+                csv += "\n";
+            }
+            csv += &r
+                .contents
+                .iter()
+                .enumerate()
+                .map(|(col, c)| {
+                    let entry = self
+                        .get_format(&SpreadsheetCell { row: idx, col })
+                        .to_entry(c, &self.locale);
+                    quote_csv_cell(&entry, self.locale.csv_delimiter)
+                })
+                .collect::<Vec<String>>()
+                .join(&self.locale.csv_delimiter.to_string());
+            csv += "\n";
+        }
+        // Comments after the last row, or in a file with no rows left
+        for (_, comment) in comments {
+            csv += comment;
+            csv += "\n";
+        }
+        csv
+    }
+
+    pub fn to_xlsx(&self, path: &str) -> Result<(), Error> {
+        let to_io_error = |e: XlsxError| Error::other(e.to_string());
+        let mut workbook = Workbook::new();
+        let worksheet = workbook.add_worksheet();
+
+        for (cell, value) in self.iter_used_cells() {
+            let (row_idx, col_idx) = (cell.row as u32, cell.col as u16);
+            let format = self.xlsx_format(&cell);
+            // Formulas are written as-is, so Excel recalculates them on open
+            if value.starts_with("=") {
+                worksheet.write_formula_with_format(row_idx, col_idx, value, &format)
+            } else if let Some(text) = value.strip_prefix('\'') {
+                worksheet.write_string_with_format(row_idx, col_idx, text, &format)
+            } else if is_zero_padded(value) {
+                worksheet.write_string_with_format(row_idx, col_idx, value, &format)
+            } else if let Some(number) = self.locale.parse_number(value) {
+                worksheet.write_number_with_format(row_idx, col_idx, number, &format)
+            } else if ["TRUE", "FALSE"].contains(&value.to_uppercase().as_str()) {
+                let value = value.to_uppercase() == "TRUE";
+                worksheet.write_boolean_with_format(row_idx, col_idx, value, &format)
+            } else {
+                worksheet.write_string_with_format(row_idx, col_idx, value, &format)
+            }
+            .map_err(to_io_error)?;
+        }
+
+        let [used_rows, used_cols] = self
+            .used_range()
+            .map_or([0, 0], |[_, end]| [end.row + 1, end.col + 1]);
+        for (col_idx, width) in self.col_widths.iter().take(used_cols).enumerate() {
+            worksheet
+                .set_column_width(col_idx as u16, *width)
+                .map_err(to_io_error)?;
+        }
+        for &col_idx in self.hidden_cols.iter().filter(|&&col| col < used_cols) {
+            worksheet
+                .set_column_hidden(col_idx as u16)
+                .map_err(to_io_error)?;
+        }
+        for &row_idx in self.hidden_rows.iter().filter(|&&row| row < used_rows) {
+            worksheet
+                .set_row_hidden(row_idx as u32)
+                .map_err(to_io_error)?;
+        }
+
+        workbook.save(path).map_err(to_io_error)
+    }
+
+    fn xlsx_format(&self, cell: &SpreadsheetCell) -> Format {
+        let decimal_places = |decimals: u8| match decimals {
+            0 => String::new(),
+            _ => format!(".{}", "0".repeat(decimals as usize)),
+        };
+        let mut format = match self.get_format(cell) {
+            CellFormat::General => Format::new(),
+            CellFormat::Percent => Format::new().set_num_format("0.00%"),
+            CellFormat::Date => Format::new().set_num_format("yyyy-mm-dd"),
+            CellFormat::Time => Format::new().set_num_format("hh:mm:ss"),
+            CellFormat::Currency(symbol) => {
+                Format::new().set_num_format(format!("\"{}\"#,##0.00", symbol))
+            }
+            CellFormat::Scientific(decimals) => {
+                Format::new().set_num_format(format!("0{}E+00", decimal_places(decimals)))
+            }
+            CellFormat::Engineering(decimals) => {
+                Format::new().set_num_format(format!("##0{}E+00", decimal_places(decimals)))
+            }
+            CellFormat::Fraction(digits) => {
+                let places = "?".repeat(digits as usize);
+                Format::new().set_num_format(format!("# {}/{}", places, places))
+            }
+        };
+        let style = self.get_text_style(cell);
+        if style.bold {
+            format = format.set_bold();
+        }
+        if style.italic {
+            format = format.set_italic();
+        }
+        if style.underline {
+            format = format.set_underline(FormatUnderline::Single);
+        }
+        format
     }
 
-    // fn from_xls(path: &str) {
-    //     todo!()
-    // }
+    pub fn save(&self, path: &str) -> Result<(), Error> {
+        // The format is picked by extension, falling back to CSV
+        if path.to_lowercase().ends_with(".xlsx") {
+            self.to_xlsx(path)
+        } else {
+            fs::write(path, self.to_csv())
+        }
+    }
 
     // TODO: Give this a range parameter.
     pub fn iter_rows(&self) -> std::slice::Iter<'_, SpreadsheetRow> {
         self.data.iter().clone()
     }
 
+    pub fn iter_used_cells(&self) -> impl Iterator<Item = (SpreadsheetCell, &str)> + '_ {
+        // Only the non-empty cells, in row-major order, skipping the blanks that pad out rows
+        self.data.iter().enumerate().flat_map(|(row, r)| {
+            r.contents
+                .iter()
+                .enumerate()
+                .filter(|(_, value)| !value.is_empty())
+                .map(move |(col, value)| (SpreadsheetCell { row, col }, value.as_str()))
+        })
+    }
+
+    pub fn data_rows(&self) -> usize {
+        // How many rows the stored data spans, including any blank ones within it. This bounds
+        // the used range without looking through every cell.
+        self.data.len()
+    }
+
+    pub fn data_cols(&self, rows: RangeInclusive<usize>) -> usize {
+        // How many columns the stored data spans in some rows
+        self.data
+            .get(*rows.start()..min(rows.end().saturating_add(1), self.data.len()))
+            .map_or(0, |rows| {
+                rows.iter().map(|row| row.contents.len()).max().unwrap_or(0)
+            })
+    }
+
+    pub fn used_range(&self) -> Option<[SpreadsheetCell; 2]> {
+        // The smallest range containing every non-empty cell, or None if the sheet is blank
+        self.iter_used_cells()
+            .fold(None, |range, (cell, _)| match range {
+                None => Some([cell.clone(), cell]),
+                Some([start, end]) => Some([
+                    SpreadsheetCell {
+                        row: min(start.row, cell.row),
+                        col: min(start.col, cell.col),
+                    },
+                    SpreadsheetCell {
+                        row: max(end.row, cell.row),
+                        col: max(end.col, cell.col),
+                    },
+                ]),
+            })
+    }
+
+    pub fn find_matches(&self, pattern: &SearchPattern, values: bool) -> Vec<SpreadsheetCell> {
+        // Cells matching the pattern in row-major order, checking either what was typed or the
+        // values formulas give
+        self.iter_used_cells()
+            .map(|(cell, _)| cell)
+            .filter(|cell| {
+                let text = if values {
+                    self.get_cell_value(cell)
+                        .map_or(String::new(), |token| token.content)
+                } else {
+                    self.get_cell_entry(cell)
+                };
+                pattern.is_match(&text)
+            })
+            .collect()
+    }
+
+    pub fn replace_matches(&mut self, pattern: &SearchPattern, replacement: &str) -> usize {
+        // Replaces within the entries of every matching cell as one undo step, giving how many
+        // cells changed. Values can't be replaced, so this always goes by entries.
+        let entries: Vec<(SpreadsheetCell, String)> = self
+            .find_matches(pattern, false)
+            .into_iter()
+            .map(|cell| {
+                let entry = pattern.replace_all(&self.get_cell_entry(&cell), replacement);
+                (cell, entry)
+            })
+            .filter(|(cell, entry)| *entry != self.get_cell_entry(cell))
+            .collect();
+        self.enter_cells(&entries);
+        entries.len()
+    }
+
+    pub fn revision(&self) -> u64 {
+        self.revision
+    }
+
+    pub fn stats(&self) -> SpreadsheetStats {
+        // Rough numbers for the debug overlay, to help pin down performance problems
+        let (used_rows, used_cols) = self
+            .used_range()
+            .map_or((0, 0), |[_, end]| (end.row + 1, end.col + 1));
+        let string_size = std::mem::size_of::<String>();
+        let data_bytes: usize = self
+            .data
+            .iter()
+            .map(|r| {
+                r.contents
+                    .iter()
+                    .map(|c| string_size + c.len())
+                    .sum::<usize>()
+            })
+            .sum();
+        let undo_bytes: usize = self
+            .undo_stack
+            .entries()
+            .flatten()
+            .map(|e| match e {
+                SpreadsheetEdit::Content { before, after, .. } => before.len() + after.len(),
+                SpreadsheetEdit::Look { .. } | SpreadsheetEdit::Width { .. } => 0,
+            } + std::mem::size_of::<SpreadsheetEdit>())
+            .sum();
+        SpreadsheetStats {
+            used_rows,
+            used_cols,
+            used_cells: self.iter_used_cells().count(),
+            undo_depth: self.undo_stack.depth(),
+            cached_values: self.value_cache.read().unwrap().len(),
+            approx_bytes: data_bytes + undo_bytes + self.col_widths.len() * 2,
+        }
+    }
+
     fn in_spreadsheet(&self, cell: &SpreadsheetCell) -> bool {
         cell.row < self.data.len()
             && cell.col < self.data[cell.row].contents.len()
@@ -129,13 +661,325 @@ impl Spreadsheet {
         return self.data[cell.row].contents[cell.col].as_str();
     }
 
+    pub fn last_used_col(&self, row: usize) -> usize {
+        if let Some(spreadsheet_row) = self.data.get(row) {
+            return spreadsheet_row
+                .contents
+                .iter()
+                .rposition(|c| !c.is_empty())
+                .unwrap_or(0);
+        }
+        0
+    }
+
+    pub fn header_names(&self) -> Vec<(String, String)> {
+        // Treats text in the first row as the names of the data below it, so that it can be
+        // suggested in formulas as the range it labels (ie. Price -> B2:B100)
+        let Some(header) = self.data.first() else {
+            return Vec::new();
+        };
+        if self.data.len() < 2 {
+            return Vec::new();
+        }
+        header
+            .contents
+            .iter()
+            .enumerate()
+            .filter(|(_, name)| !name.is_empty() && name.parse::<f64>().is_err())
+            .map(|(col, name)| {
+                let letters = Reference::index_to_alpha(col as u32 + 1);
+                (
+                    name.clone(),
+                    format!("{}2:{}{}", letters, letters, self.data.len()),
+                )
+            })
+            .collect()
+    }
+
+    pub fn find_data_edge(&self, cell: &SpreadsheetCell, dx: i32, dy: i32) -> SpreadsheetCell {
+        // Mimics Excel's Ctrl+Arrow: from inside a block of filled cells, go to the last filled
+        // cell of the block, otherwise go to the next filled cell (or the edge of the sheet).
+        let step = |c: &SpreadsheetCell| -> Option<SpreadsheetCell> {
+            let row = c.row.checked_add_signed(dy as isize)?;
+            let col = c.col.checked_add_signed(dx as isize)?;
+            let next = SpreadsheetCell { row, col };
+            self.in_bounds(&next).then_some(next)
+        };
+        let is_filled = |c: &SpreadsheetCell| !self.get_cell(c).is_empty();
+
+        let mut current = cell.clone();
+        let Some(next) = step(&current) else {
+            return current;
+        };
+
+        if is_filled(&current) && is_filled(&next) {
+            current = next;
+            while let Some(next) = step(&current) {
+                if !is_filled(&next) {
+                    break;
+                }
+                current = next;
+            }
+        } else {
+            current = next;
+            while !is_filled(&current) {
+                match step(&current) {
+                    Some(next) => current = next,
+                    None => break,
+                }
+            }
+        }
+        current
+    }
+
     fn internal_set_cell(&mut self, cell: &SpreadsheetCell, value: &str) {
+        if !self.in_bounds(cell) {
+            return;
+        }
+        self.write_cell(cell, value);
+        self.invalidate_cells(vec![cell.clone()]);
+    }
+
+    fn write_cell(&mut self, cell: &SpreadsheetCell, value: &str) {
+        // Changes a cell without invalidating values, for edits that invalidate once at the end
         self.resize_to_cell(cell);
         self.data[cell.row].contents[cell.col] = value.to_string();
+        match value.strip_prefix('=') {
+            Some(formula) if can_spill(formula) => self.spill_anchors.insert(cell.clone()),
+            _ => self.spill_anchors.remove(cell),
+        };
+    }
+
+    fn find_spill_anchors(&mut self) {
+        // For whole sheets loaded at once, rather than entered a cell at a time
+        self.spill_anchors = self
+            .iter_used_cells()
+            .filter(|(_, value)| value.strip_prefix('=').is_some_and(can_spill))
+            .map(|(cell, _)| cell)
+            .collect();
+    }
+
+    fn invalidate_values(&mut self) {
+        // Drops every value, for changes to the whole sheet
+        let cache = self.value_cache.get_mut().unwrap();
+        if !cache.is_empty() {
+            *cache = HashMap::new();
+        }
+        self.value_inputs.get_mut().unwrap().clear();
+        self.spills.get_mut().unwrap().clear();
+        self.spill_rows_known.store(0, atomic::Ordering::SeqCst);
+        self.revision += 1;
+    }
+
+    fn invalidate_cells(&mut self, cells: Vec<SpreadsheetCell>) {
+        // Drops the values of edited cells and of the formulas depending on them, so the rest
+        // aren't evaluated again until they're needed
+        self.drop_values(cells);
+        self.revision += 1;
+    }
+
+    fn invalidate_edits(&mut self, edits: &[SpreadsheetEdit]) {
+        // Formats and widths don't change values, but anything drawn from the sheet is redrawn
+        let cells = edits
+            .iter()
+            .filter_map(|edit| match edit {
+                SpreadsheetEdit::Content { cell, .. } => Some(cell.clone()),
+                _ => None,
+            })
+            .collect();
+        self.invalidate_cells(cells);
+    }
+
+    fn drop_values(&self, cells: Vec<SpreadsheetCell>) {
+        // Drops the cached values of the cells and of everything reading them, in turn. A dropped
+        // anchor's spill goes with it, since the cells it spilled into may change too.
+        let mut cache = self.value_cache.write().unwrap();
+        let mut inputs = self.value_inputs.write().unwrap();
+        let mut spills = self.spills.write().unwrap();
+        let mut first_row = usize::MAX;
+        let mut next = cells;
+        while !next.is_empty() {
+            let changed: HashSet<SpreadsheetCell> = next.drain(..).collect();
+            for cell in &changed {
+                cache.remove(cell);
+                inputs.remove(cell);
+                first_row = first_row.min(cell.row);
+                if let Some(values) = spills.remove(cell) {
+                    let [start, end] = Spreadsheet::spill_area(cell, &values);
+                    for row in start.row..=end.row {
+                        next.extend((start.col..=end.col).map(|col| SpreadsheetCell { row, col }));
+                    }
+                }
+            }
+            // Like in DependencyGraph, big ranges are cheaper to check the other way around
+            let reads = |[start, end]: &[SpreadsheetCell; 2]| {
+                let area = (end.row - start.row + 1) * (end.col - start.col + 1);
+                if area <= changed.len() {
+                    (start.row..=end.row).any(|row| {
+                        (start.col..=end.col)
+                            .any(|col| changed.contains(&SpreadsheetCell { row, col }))
+                    })
+                } else {
+                    changed.iter().any(|cell| {
+                        (start.row..=end.row).contains(&cell.row)
+                            && (start.col..=end.col).contains(&cell.col)
+                    })
+                }
+            };
+            next.extend(
+                inputs
+                    .iter()
+                    .filter(|(_, ranges)| ranges.iter().any(reads))
+                    .map(|(cell, _)| cell.clone()),
+            );
+            for cell in &next {
+                inputs.remove(cell);
+            }
+        }
+        // Any dropped formula may spill differently once it's evaluated again
+        self.spill_rows_known
+            .fetch_min(first_row, atomic::Ordering::SeqCst);
+    }
+
+    pub fn record_spill(&self, anchor: &SpreadsheetCell, values: Vec<Vec<Token>>) {
+        let area = Spreadsheet::spill_area(anchor, &values);
+        let before = self
+            .spills
+            .write()
+            .unwrap()
+            .insert(anchor.clone(), values.clone());
+        if before.as_ref() == Some(&values) {
+            return;
+        }
+        // Whatever read the cells as they were before, blank or spilled, has to be evaluated
+        // again, along with anything evaluating while the spill changed
+        self.spill_changes.fetch_add(1, atomic::Ordering::SeqCst);
+        let mut areas = vec![area];
+        areas.extend(before.map(|before| Spreadsheet::spill_area(anchor, &before)));
+        let mut cells: Vec<SpreadsheetCell> = Vec::new();
+        for [start, end] in areas {
+            for row in start.row..=end.row {
+                cells.extend((start.col..=end.col).map(|col| SpreadsheetCell { row, col }));
+            }
+        }
+        cells.retain(|cell| cell != anchor);
+        self.drop_values(cells);
+    }
+
+    pub fn recalculate(&mut self) {
+        // Evaluates every formula again, on as many threads as there are cores. Formulas only
+        // wait for the formulas they reference, so each level of the dependency graph is
+        // evaluated in parallel. Circular references are left to be evaluated when shown.
+        self.invalidate_values();
+        let graph = DependencyGraph::new(self);
+        // Spills are found by the levels as they go, instead of by blank cells looked up while
+        // they're evaluated one at a time
+        self.spill_rows_known
+            .store(usize::MAX, atomic::Ordering::SeqCst);
+        self.evaluate_levels(&graph);
+        // Anything that read a spill before it was known was dropped once it was, so those are
+        // evaluated again
+        if !self.spills.get_mut().unwrap().is_empty() {
+            self.evaluate_levels(&graph);
+        }
+    }
+
+    fn evaluate_levels(&self, graph: &DependencyGraph) {
+        // Everything a level references is already cached, so no formula waits on another
+        for level in &graph.levels {
+            level.par_iter().for_each(|cell| {
+                let _ = self.get_cell_value(cell);
+            });
+        }
+    }
+
+    fn evaluate_spills(&self, row: usize) {
+        // Spills are only known once their anchors have been evaluated. Anchors are never below
+        // the cells they spill into, so looking for a spill only evaluates the anchors above it
+        // (and only once), leaving every other formula until it's needed.
+        if self.spill_rows_known.load(atomic::Ordering::SeqCst) > row {
+            return;
+        }
+        let known = self
+            .spill_rows_known
+            .fetch_max(row + 1, atomic::Ordering::SeqCst);
+        for anchor in &self.spill_anchors {
+            if (known..=row).contains(&anchor.row) {
+                let _ = self.get_cell_value(anchor);
+            }
+        }
+    }
+
+    pub fn spilled_value(&self, cell: &SpreadsheetCell) -> Option<Token> {
+        if !self.get_cell(cell).is_empty() {
+            return None;
+        }
+        self.evaluate_spills(cell.row);
+        self.spills
+            .read()
+            .unwrap()
+            .iter()
+            .find_map(|(anchor, values)| {
+                values
+                    .get(cell.row.checked_sub(anchor.row)?)?
+                    .get(cell.col.checked_sub(anchor.col)?)
+                    .cloned()
+            })
+    }
+
+    pub fn is_spilled(&self, cell: &SpreadsheetCell) -> bool {
+        // Spilled cells are read-only, since they belong to the formula they spill from
+        self.spilled_value(cell).is_some()
+    }
+
+    fn spill_area(anchor: &SpreadsheetCell, values: &[Vec<Token>]) -> [SpreadsheetCell; 2] {
+        // The cells an array covers from its anchor, which is at least the anchor itself
+        let cols = values.iter().map(|r| r.len()).max().unwrap_or(0);
+        [
+            anchor.clone(),
+            SpreadsheetCell {
+                row: anchor.row + values.len().max(1) - 1,
+                col: anchor.col + cols.max(1) - 1,
+            },
+        ]
+    }
+
+    fn spill(&self, anchor: &SpreadsheetCell, values: Vec<Vec<Token>>) -> Token {
+        // Writes an array into the cells next to its formula, unless something is in the way
+        let [_, end] = Spreadsheet::spill_area(anchor, &values);
+        let Some(first) = values.first().and_then(|r| r.first()).cloned() else {
+            return Token::new(TokenType::Error, String::from("#VALUE!"));
+        };
+        for row in anchor.row..=end.row {
+            for col in anchor.col..=end.col {
+                let cell = SpreadsheetCell { row, col };
+                if cell != *anchor && (!self.in_bounds(&cell) || !self.get_cell(&cell).is_empty()) {
+                    return Token::new(TokenType::Error, String::from("#SPILL!"));
+                }
+            }
+        }
+        self.record_spill(anchor, values);
+        first
+    }
+
+    pub fn spill_range(&self, anchor: &SpreadsheetCell) -> Option<[SpreadsheetCell; 2]> {
+        let spills = self.spills.read().unwrap();
+        let values = spills.get(anchor)?;
+        let (rows, cols) = (values.len(), values.first()?.len());
+        Some([
+            anchor.clone(),
+            SpreadsheetCell {
+                row: anchor.row + rows - 1,
+                col: anchor.col + cols - 1,
+            },
+        ])
     }
 
     pub fn set_cell(&mut self, cell: &SpreadsheetCell, value: &str) {
-        self.undo_stack.edit(vec![SpreadsheetEdit {
+        if !self.in_bounds(cell) {
+            return;
+        }
+        self.undo_stack.edit(vec![SpreadsheetEdit::Content {
             cell: cell.clone(),
             before: if self.in_spreadsheet(cell) {
                 self.data[cell.row].contents[cell.col].clone()
@@ -147,53 +991,425 @@ impl Spreadsheet {
         self.internal_set_cell(cell, value);
     }
 
-    pub fn undo(&mut self) -> Option<[SpreadsheetCell; 2]> {
-        if let Some(edits) = self.undo_stack.undo() {
-            let min_row = edits.iter().min_by_key(|c| c.cell.row).unwrap().cell.row;
-            let max_row = edits.iter().max_by_key(|c| c.cell.row).unwrap().cell.row;
-            let min_col = edits.iter().min_by_key(|c| c.cell.col).unwrap().cell.col;
-            let max_col = edits.iter().max_by_key(|c| c.cell.col).unwrap().cell.col;
-            for edit in edits.iter() {
-                self.internal_set_cell(&edit.cell, &edit.before);
-            }
-            return Some([
-                SpreadsheetCell {
-                    row: min_row,
-                    col: min_col,
-                },
-                SpreadsheetCell {
-                    row: max_row,
-                    col: max_col,
-                },
-            ]);
+    pub fn set_cells(&mut self, edits: &[(SpreadsheetCell, String)]) {
+        // Applies many edits as a single undo step, invalidating values once rather than per cell
+        let changes = self.write_cells(edits);
+        if changes.is_empty() {
+            return;
         }
-        None
+        self.invalidate_edits(&changes);
+        self.undo_stack.edit(changes);
     }
 
-    pub fn redo(&mut self) -> Option<[SpreadsheetCell; 2]> {
-        if let Some(edits) = self.undo_stack.redo() {
-            let min_row = edits.iter().min_by_key(|c| c.cell.row).unwrap().cell.row;
-            let max_row = edits.iter().max_by_key(|c| c.cell.row).unwrap().cell.row;
-            let min_col = edits.iter().min_by_key(|c| c.cell.col).unwrap().cell.col;
-            let max_col = edits.iter().max_by_key(|c| c.cell.col).unwrap().cell.col;
+    fn write_cells(&mut self, edits: &[(SpreadsheetCell, String)]) -> Vec<SpreadsheetEdit> {
+        // Changes cells without invalidating values or recording an undo step, returning the
+        // edits for the caller to do both with.
+        let mut changes: Vec<SpreadsheetEdit> = Vec::new();
+        for (cell, value) in edits {
+            if !self.in_bounds(cell) {
+                continue;
+            }
+            changes.push(SpreadsheetEdit::Content {
+                cell: cell.clone(),
+                before: self.get_cell(cell).to_string(),
+                after: value.clone(),
+            });
+            self.write_cell(cell, value);
+        }
+        changes
+    }
 
-            for edit in edits.iter() {
-                self.internal_set_cell(&edit.cell, &edit.after);
+    pub fn enter_cell(&mut self, cell: &SpreadsheetCell, input: &str) {
+        // Sets a cell to what a user typed, where some entries also set the cell's format
+        self.enter_cells(&[(cell.clone(), input.to_string())]);
+    }
+
+    pub fn enter_cells(&mut self, entries: &[(SpreadsheetCell, String)]) {
+        // Like set_cells, but with values treated as typed ones, as one undo step along with any
+        // formats they set (ie. a pasted 5% is still a percentage)
+        let mut edits: Vec<(SpreadsheetCell, String)> = Vec::new();
+        let mut formats: Vec<(SpreadsheetCell, CellFormat)> = Vec::new();
+        for (cell, input) in entries.iter().filter(|(cell, _)| self.in_bounds(cell)) {
+            match CellFormat::parse_entry(input, &self.locale) {
+                Some((value, format)) => {
+                    formats.push((cell.clone(), format));
+                    edits.push((cell.clone(), value));
+                }
+                None => edits.push((cell.clone(), input.clone())),
             }
+        }
 
-            return Some([
-                SpreadsheetCell {
-                    row: min_row,
-                    col: min_col,
-                },
-                SpreadsheetCell {
-                    row: max_row,
-                    col: max_col,
-                },
-            ]);
+        let mut changes = self.write_cells(&edits);
+        let targets = formats
+            .iter()
+            .map(|(cell, _)| LookTarget::Cell(cell.clone()))
+            .collect();
+        changes.extend(self.change_looks(targets, |spreadsheet| {
+            for (cell, format) in formats {
+                spreadsheet.set_format(&cell, format);
+            }
+        }));
+        if changes.is_empty() {
+            return;
+        }
+        self.invalidate_edits(&changes);
+        self.undo_stack.edit(changes);
+    }
+
+    pub fn get_cell_entry(&self, cell: &SpreadsheetCell) -> String {
+        // The cell's contents as they would be typed, ie. 5% for 0.05 formatted as a percentage
+        self.get_format(cell)
+            .to_entry(self.get_cell(cell), &self.locale)
+    }
+
+    fn get_look(&self, target: &LookTarget) -> CellLook {
+        match target {
+            LookTarget::Cell(cell) => CellLook {
+                format: self.formats.get(cell).copied(),
+                style: self.styles.get(cell).copied(),
+                colors: self.colors.get(cell).copied(),
+            },
+            LookTarget::Col(col) => CellLook {
+                format: self.col_formats.get(col).copied(),
+                style: self.col_styles.get(col).copied(),
+                colors: self.col_colors.get(col).copied(),
+            },
+        }
+    }
+
+    fn put_look(&mut self, target: &LookTarget, look: CellLook) {
+        fn put<K: std::hash::Hash + Eq, V>(map: &mut HashMap<K, V>, key: K, value: Option<V>) {
+            match value {
+                Some(value) => map.insert(key, value),
+                None => map.remove(&key),
+            };
+        }
+        self.revision += 1;
+        match target {
+            LookTarget::Cell(cell) => {
+                put(&mut self.formats, cell.clone(), look.format);
+                put(&mut self.styles, cell.clone(), look.style);
+                put(&mut self.colors, cell.clone(), look.colors);
+            }
+            LookTarget::Col(col) => {
+                put(&mut self.col_formats, *col, look.format);
+                put(&mut self.col_styles, *col, look.style);
+                put(&mut self.col_colors, *col, look.colors);
+            }
+        }
+    }
+
+    fn col_look_targets(&self, min_col: usize, max_col: usize) -> Vec<LookTarget> {
+        // The columns and every cell in them with its own formatting, which column-wide changes
+        // can affect
+        let in_cols = |cell: &&SpreadsheetCell| cell.col >= min_col && cell.col <= max_col;
+        let mut cells: Vec<SpreadsheetCell> = self
+            .formats
+            .keys()
+            .chain(self.styles.keys())
+            .chain(self.colors.keys())
+            .filter(in_cols)
+            .cloned()
+            .collect();
+        cells.sort_by_key(|cell| (cell.row, cell.col));
+        cells.dedup();
+        (min_col..=max_col)
+            .map(LookTarget::Col)
+            .chain(cells.into_iter().map(LookTarget::Cell))
+            .collect()
+    }
+
+    fn change_looks(
+        &mut self,
+        targets: Vec<LookTarget>,
+        change: impl FnOnce(&mut Spreadsheet),
+    ) -> Vec<SpreadsheetEdit> {
+        // Makes a formatting change, returning edits for whatever it changed out of the targets
+        let before: Vec<CellLook> = targets.iter().map(|t| self.get_look(t)).collect();
+        change(self);
+        targets
+            .into_iter()
+            .zip(before)
+            .filter_map(|(target, before)| {
+                let after = self.get_look(&target);
+                (after != before).then_some(SpreadsheetEdit::Look {
+                    target,
+                    before,
+                    after,
+                })
+            })
+            .collect()
+    }
+
+    fn record_looks(
+        &mut self,
+        range: &[SpreadsheetCell; 2],
+        change: impl FnOnce(&mut Spreadsheet),
+    ) {
+        // Makes a formatting change to a range as a single undo step
+        let [start, end] = range;
+        let (min_col, max_col) = (min(start.col, end.col), max(start.col, end.col));
+        let targets = if self.is_whole_cols(range) {
+            self.col_look_targets(min_col, max_col)
+        } else {
+            (min(start.row, end.row)..=max(start.row, end.row))
+                .flat_map(|row| {
+                    (min_col..=max_col)
+                        .map(move |col| LookTarget::Cell(SpreadsheetCell { row, col }))
+                })
+                .collect()
+        };
+        let changes = self.change_looks(targets, change);
+        if !changes.is_empty() {
+            self.undo_stack.edit(changes);
+        }
+    }
+
+    fn is_whole_cols(&self, range: &[SpreadsheetCell; 2]) -> bool {
+        let [start, end] = range;
+        min(start.row, end.row) == 0 && max(start.row, end.row) + 1 >= self.max_rows
+    }
+
+    pub fn get_validation(&self, cell: &SpreadsheetCell) -> Option<&Validation> {
+        self.validations
+            .get(cell)
+            .or_else(|| self.col_validations.get(&cell.col))
+    }
+
+    pub fn validate_range(&mut self, range: &[SpreadsheetCell; 2], validation: Option<Validation>) {
+        // Sets (or with None, removes) the rule for a range, by column for whole columns
+        let [start, end] = range;
+        let (min_col, max_col) = (min(start.col, end.col), max(start.col, end.col));
+        if self.is_whole_cols(range) {
+            for col in min_col..=max_col {
+                self.validations.retain(|cell, _| cell.col != col);
+                match &validation {
+                    Some(validation) => self.col_validations.insert(col, validation.clone()),
+                    None => self.col_validations.remove(&col),
+                };
+            }
+            return;
+        }
+        for row in min(start.row, end.row)..=max(start.row, end.row) {
+            for col in min_col..=max_col {
+                let cell = SpreadsheetCell { row, col };
+                match &validation {
+                    Some(validation) => self.validations.insert(cell, validation.clone()),
+                    None => self.validations.remove(&cell),
+                };
+            }
+        }
+    }
+
+    pub fn check_entry(&self, cell: &SpreadsheetCell, input: &str) -> Result<String, String> {
+        // What to enter for a typed value under the cell's rule, or why it isn't allowed
+        match self.get_validation(cell) {
+            Some(validation) => validation.check(input, self, cell),
+            None => Ok(input.to_string()),
         }
-        None
     }
+
+    pub fn get_format(&self, cell: &SpreadsheetCell) -> CellFormat {
+        self.formats
+            .get(cell)
+            .or_else(|| self.col_formats.get(&cell.col))
+            .copied()
+            .unwrap_or_default()
+    }
+
+    pub fn set_format(&mut self, cell: &SpreadsheetCell, format: CellFormat) {
+        self.formats.insert(cell.clone(), format);
+        self.revision += 1;
+    }
+
+    pub fn set_col_format(&mut self, col: usize, format: CellFormat) {
+        // Replaces the format of every cell in the column, including any set on single cells
+        self.formats.retain(|cell, _| cell.col != col);
+        self.col_formats.insert(col, format);
+        self.revision += 1;
+    }
+
+    pub fn format_range(&mut self, range: &[SpreadsheetCell; 2], format: CellFormat) {
+        // Ranges spanning whole columns are formatted by column, rather than one cell at a time
+        let whole_cols = self.is_whole_cols(range);
+        let [start, end] = range.clone();
+        self.record_looks(range, |spreadsheet| {
+            let (min_col, max_col) = (min(start.col, end.col), max(start.col, end.col));
+            if whole_cols {
+                for col in min_col..=max_col {
+                    spreadsheet.set_col_format(col, format);
+                }
+                return;
+            }
+            for row in min(start.row, end.row)..=max(start.row, end.row) {
+                for col in min_col..=max_col {
+                    spreadsheet.set_format(&SpreadsheetCell { row, col }, format);
+                }
+            }
+        });
+    }
+
+    pub fn get_text_style(&self, cell: &SpreadsheetCell) -> TextStyle {
+        self.styles
+            .get(cell)
+            .or_else(|| self.col_styles.get(&cell.col))
+            .copied()
+            .unwrap_or_default()
+    }
+
+    pub fn set_text_style(&mut self, cell: &SpreadsheetCell, style: TextStyle) {
+        self.styles.insert(cell.clone(), style);
+    }
+
+    pub fn set_col_text_style(&mut self, col: usize, style: TextStyle) {
+        self.styles.retain(|cell, _| cell.col != col);
+        self.col_styles.insert(col, style);
+    }
+
+    pub fn style_range(&mut self, range: &[SpreadsheetCell; 2], change: impl Fn(&mut TextStyle)) {
+        // Changes the style of every cell in a range, keeping the parts of it that aren't changed
+        // (ie. making italic text bold keeps it italic). Whole columns are styled by column.
+        let whole_cols = self.is_whole_cols(range);
+        let [start, end] = range.clone();
+        self.record_looks(range, |spreadsheet| {
+            let (min_col, max_col) = (min(start.col, end.col), max(start.col, end.col));
+            let cells: Vec<SpreadsheetCell> = if whole_cols {
+                for col in min_col..=max_col {
+                    let mut style = spreadsheet
+                        .col_styles
+                        .get(&col)
+                        .copied()
+                        .unwrap_or_default();
+                    change(&mut style);
+                    spreadsheet.col_styles.insert(col, style);
+                }
+                // Cells styled on their own keep their own styles, with the change made to them too
+                spreadsheet
+                    .styles
+                    .keys()
+                    .filter(|cell| cell.col >= min_col && cell.col <= max_col)
+                    .cloned()
+                    .collect()
+            } else {
+                (min(start.row, end.row)..=max(start.row, end.row))
+                    .flat_map(|row| {
+                        (min_col..=max_col).map(move |col| SpreadsheetCell { row, col })
+                    })
+                    .collect()
+            };
+            for cell in cells {
+                let mut style = spreadsheet.get_text_style(&cell);
+                change(&mut style);
+                spreadsheet.set_text_style(&cell, style);
+            }
+        });
+    }
+
+    pub fn get_colors(&self, cell: &SpreadsheetCell) -> CellColors {
+        self.colors
+            .get(cell)
+            .or_else(|| self.col_colors.get(&cell.col))
+            .copied()
+            .unwrap_or_default()
+    }
+
+    pub fn color_range(&mut self, range: &[SpreadsheetCell; 2], change: impl Fn(&mut CellColors)) {
+        // Colors are only kept for cells that differ from their column, so clearing both removes
+        // the cell. Whole columns are colored by column, like styles.
+        let whole_cols = self.is_whole_cols(range);
+        let [start, end] = range.clone();
+        self.record_looks(range, |spreadsheet| {
+            let (min_col, max_col) = (min(start.col, end.col), max(start.col, end.col));
+            let cells: Vec<SpreadsheetCell> = if whole_cols {
+                for col in min_col..=max_col {
+                    let mut colors = spreadsheet
+                        .col_colors
+                        .get(&col)
+                        .copied()
+                        .unwrap_or_default();
+                    change(&mut colors);
+                    if colors == CellColors::default() {
+                        spreadsheet.col_colors.remove(&col);
+                    } else {
+                        spreadsheet.col_colors.insert(col, colors);
+                    }
+                }
+                spreadsheet
+                    .colors
+                    .keys()
+                    .filter(|cell| cell.col >= min_col && cell.col <= max_col)
+                    .cloned()
+                    .collect()
+            } else {
+                (min(start.row, end.row)..=max(start.row, end.row))
+                    .flat_map(|row| {
+                        (min_col..=max_col).map(move |col| SpreadsheetCell { row, col })
+                    })
+                    .collect()
+            };
+            for cell in cells {
+                let mut colors = spreadsheet.get_colors(&cell);
+                change(&mut colors);
+                let col_colors = spreadsheet.col_colors.get(&cell.col).copied();
+                if colors == col_colors.unwrap_or_default() {
+                    spreadsheet.colors.remove(&cell);
+                } else {
+                    spreadsheet.colors.insert(cell, colors);
+                }
+            }
+        });
+    }
+
+    pub fn cell_text_styles(&self) -> impl Iterator<Item = (&SpreadsheetCell, &TextStyle)> {
+        self.styles.iter()
+    }
+
+    pub fn col_text_styles(&self) -> impl Iterator<Item = (&usize, &TextStyle)> {
+        self.col_styles.iter()
+    }
+
+    pub fn undo(&mut self) -> Option<[SpreadsheetCell; 2]> {
+        let edits = self.undo_stack.undo()?;
+        // Undone backwards, in case a cell was edited more than once
+        for edit in edits.iter().rev() {
+            match edit {
+                SpreadsheetEdit::Content { cell, before, .. } => self.write_cell(cell, before),
+                SpreadsheetEdit::Look { target, before, .. } => self.put_look(target, *before),
+                SpreadsheetEdit::Width { col, before, .. } => self.put_col_width(*col, *before),
+            }
+        }
+        self.invalidate_edits(&edits);
+        Some(Spreadsheet::edited_range(&edits))
+    }
+
+    pub fn redo(&mut self) -> Option<[SpreadsheetCell; 2]> {
+        let edits = self.undo_stack.redo()?;
+        for edit in edits.iter() {
+            match edit {
+                SpreadsheetEdit::Content { cell, after, .. } => self.write_cell(cell, after),
+                SpreadsheetEdit::Look { target, after, .. } => self.put_look(target, *after),
+                SpreadsheetEdit::Width { col, after, .. } => self.put_col_width(*col, *after),
+            }
+        }
+        self.invalidate_edits(&edits);
+        Some(Spreadsheet::edited_range(&edits))
+    }
+
+    fn edited_range(edits: &[SpreadsheetEdit]) -> [SpreadsheetCell; 2] {
+        // The smallest range containing every edit, to select after undoing or redoing them
+        let cells: Vec<SpreadsheetCell> = edits.iter().map(|e| e.cell()).collect();
+        [
+            SpreadsheetCell {
+                row: cells.iter().map(|c| c.row).min().unwrap_or(0),
+                col: cells.iter().map(|c| c.col).min().unwrap_or(0),
+            },
+            SpreadsheetCell {
+                row: cells.iter().map(|c| c.row).max().unwrap_or(0),
+                col: cells.iter().map(|c| c.col).max().unwrap_or(0),
+            },
+        ]
+    }
+
     pub fn resize_to_cell(&mut self, cell: &SpreadsheetCell) {
         if cell.row >= self.data.len() {
             self.data.resize(cell.row + 1, SpreadsheetRow::default());
@@ -204,7 +1420,7 @@ impl Spreadsheet {
                 .resize(cell.col + 1, String::new());
         }
         if self.col_widths.len() <= cell.col {
-            self.col_widths.resize(cell.col + 1, DEFAULT_COL_WIDTH);
+            self.col_widths.resize(cell.col + 1, self.default_col_width);
         }
     }
 
@@ -212,18 +1428,338 @@ impl Spreadsheet {
         if let Some(width) = self.col_widths.get(cell.col) {
             return *width;
         }
-        return DEFAULT_COL_WIDTH;
+        self.default_col_width
     }
 
     pub fn set_col_width(&mut self, cell: &SpreadsheetCell, width: u16) {
+        // Without an undo step, for widths that aren't edits of their own (ie. while loading, or
+        // live while dragging a border). Resizing by hand goes through resize_col.
         if self.col_widths.len() > cell.col {
-            self.col_widths[cell.col] = width;
+            self.col_widths[cell.col] = width.max(MIN_COL_WIDTH);
+        }
+    }
+
+    pub fn resize_col(&mut self, col: usize, width: u16) {
+        // Like set_col_width, but as an undo step
+        let width = width.max(MIN_COL_WIDTH);
+        let before = self.get_col_width(&SpreadsheetCell { row: 0, col });
+        if col >= self.col_widths.len() || before == width {
+            return;
+        }
+        self.put_col_width(col, width);
+        self.undo_stack.edit(vec![SpreadsheetEdit::Width {
+            col,
+            before,
+            after: width,
+        }]);
+    }
+
+    fn put_col_width(&mut self, col: usize, width: u16) {
+        self.set_col_width(&SpreadsheetCell { row: 0, col }, width);
+    }
+
+    pub fn set_col_hidden(&mut self, col: usize, hidden: bool) {
+        if hidden {
+            self.hidden_cols.insert(col);
+        } else {
+            self.hidden_cols.remove(&col);
+        }
+    }
+
+    pub fn set_row_hidden(&mut self, row: usize, hidden: bool) {
+        let changed = if hidden {
+            self.hidden_rows.insert(row)
+        } else {
+            self.hidden_rows.remove(&row)
+        };
+        if changed {
+            self.invalidate_subtotals();
+        }
+    }
+
+    pub fn is_col_hidden(&self, col: usize) -> bool {
+        self.hidden_cols.contains(&col)
+    }
+
+    pub fn is_row_hidden(&self, row: usize) -> bool {
+        self.hidden_rows.contains(&row)
+            || self.filtered_rows.contains(&row)
+            || self
+                .row_groups
+                .iter()
+                .any(|group| group.collapsed && (group.start..=group.end).contains(&row))
+    }
+
+    pub fn is_row_filtered(&self, row: usize) -> bool {
+        // Filtered out rows, which are hidden but kept apart from rows hidden by hand
+        self.filtered_rows.contains(&row)
+    }
+
+    pub fn hidden_cols(&self) -> &HashSet<usize> {
+        &self.hidden_cols
+    }
+
+    pub fn hidden_rows(&self) -> HashSet<usize> {
+        // Rows hidden directly, along with those filtered out or in collapsed groups
+        let mut rows = &self.hidden_rows | &self.filtered_rows;
+        for group in self.row_groups.iter().filter(|group| group.collapsed) {
+            rows.extend(group.start..=group.end);
+        }
+        rows
+    }
+
+    pub fn filters(&self) -> &HashMap<usize, String> {
+        &self.filters
+    }
+
+    pub fn filtered_rows(&self) -> &HashSet<usize> {
+        &self.filtered_rows
+    }
+
+    pub fn set_filter(&mut self, col: usize, criteria: Option<String>) {
+        // Sets (or with None, removes) a column's filter, then hides the rows that don't meet
+        // every filter. Like in Excel, rows aren't filtered again as they're edited, only when
+        // the filters change.
+        match criteria {
+            Some(criteria) => self.filters.insert(col, criteria),
+            None => self.filters.remove(&col),
+        };
+        self.apply_filters();
+    }
+
+    pub fn clear_filters(&mut self) {
+        self.filters.clear();
+        self.apply_filters();
+    }
+
+    pub fn apply_filters(&mut self) {
+        // Header rows are never filtered out, since they name the columns being filtered
+        self.filtered_rows = (self.header_rows..self.data_rows())
+            .filter(|&row| {
+                !self.filters.iter().all(|(&col, criteria)| {
+                    let value = self
+                        .get_cell_value(&SpreadsheetCell { row, col })
+                        .unwrap_or_default();
+                    meets_criteria(&value, criteria)
+                })
+            })
+            .collect();
+        self.invalidate_subtotals();
+    }
+
+    pub fn group_rows(&mut self, start: usize, end: usize) {
+        // The same rows are only grouped once, though groups can go inside each other
+        if !self
+            .row_groups
+            .iter()
+            .any(|group| group.start == start && group.end == end)
+        {
+            self.row_groups.push(RowGroup {
+                start,
+                end,
+                collapsed: false,
+            });
+        }
+    }
+
+    pub fn ungroup_rows(&mut self, start: usize, end: usize) -> bool {
+        // Removes the groups within the rows, or otherwise the innermost one around them. Gives
+        // whether there was anything to remove.
+        let count = self.row_groups.len();
+        self.row_groups
+            .retain(|group| group.start < start || group.end > end);
+        if self.row_groups.len() == count {
+            match self.innermost_group(|group| group.start <= start && group.end >= end) {
+                Some(idx) => {
+                    self.row_groups.remove(idx);
+                }
+                None => return false,
+            }
+        }
+        // A collapsed group's rows are shown again when it goes
+        self.invalidate_subtotals();
+        true
+    }
+
+    pub fn set_group_collapsed(&mut self, row: usize, collapsed: bool) -> bool {
+        // Collapses or expands the innermost group around a row, or the one it's the summary row
+        // of (the row just after a group, which stays shown). Gives whether there was one.
+        let idx = self.innermost_group(|group| {
+            group.collapsed != collapsed && (group.start..=group.end + 1).contains(&row)
+        });
+        if let Some(idx) = idx {
+            self.row_groups[idx].collapsed = collapsed;
+            self.invalidate_subtotals();
         }
+        idx.is_some()
+    }
+
+    pub fn toggle_row_group(&mut self, row: usize) {
+        // Flips the group a summary row belongs to, as clicking its outline control does
+        if let Some(idx) = self.innermost_group(|group| group.end + 1 == row) {
+            self.row_groups[idx].collapsed = !self.row_groups[idx].collapsed;
+            self.invalidate_subtotals();
+        }
+    }
+
+    fn invalidate_subtotals(&mut self) {
+        // SUBTOTAL can leave out hidden rows, so its formulas (and whatever reads them) change
+        // with what's hidden rather than with any cell they reference
+        let cells = self
+            .iter_used_cells()
+            .filter(|(_, value)| is_subtotal(value))
+            .map(|(cell, _)| cell)
+            .collect();
+        self.invalidate_cells(cells);
+    }
+
+    pub fn row_group_above(&self, row: usize) -> Option<&RowGroup> {
+        // The group a row is the summary row of, which its outline control is drawn on
+        self.innermost_group(|group| group.end + 1 == row)
+            .map(|idx| &self.row_groups[idx])
+    }
+
+    pub fn row_outline_level(&self, row: usize) -> usize {
+        self.row_groups
+            .iter()
+            .filter(|group| (group.start..=group.end).contains(&row))
+            .count()
+    }
+
+    fn innermost_group(&self, predicate: impl Fn(&RowGroup) -> bool) -> Option<usize> {
+        self.row_groups
+            .iter()
+            .enumerate()
+            .filter(|(_, group)| predicate(group))
+            .min_by_key(|(_, group)| group.end - group.start)
+            .map(|(idx, _)| idx)
     }
 
     // TODO: Make it a Vec<Token> once functions with multiple outputs are implemented
     pub fn get_cell_value(&self, cell: &SpreadsheetCell) -> Result<Token, ()> {
-        return cell_to_token(self.get_cell(cell), self);
+        // A cached value may have read a blank that a formula entered since spills into, so the
+        // spills over what it read are found first (which drops it if there's a new one)
+        self.evaluate_spills(self.last_row_read(cell));
+        if let Some(value) = self.value_cache.read().unwrap().get(cell) {
+            return value.clone();
+        }
+        if let Some(value) = self.spilled_value(cell) {
+            return Ok(value);
+        }
+        self.evaluate_inputs(cell);
+        self.evaluate_and_cache(cell)
+    }
+
+    fn evaluate_inputs(&self, cell: &SpreadsheetCell) {
+        // Evaluating a formula looks up the cells it references, which evaluates them in turn, so
+        // a long chain (ie. A1=A2+1, A2=A3+1, ...) would recurse once per link and overflow the
+        // stack. Instead, the formulas a cell needs are found with a stack of their own and
+        // evaluated from the far end back, so each one finds what it references cached.
+        let mut seen: HashSet<SpreadsheetCell> = HashSet::from([cell.clone()]);
+        let mut stack: Vec<(SpreadsheetCell, bool)> = self
+            .unevaluated_inputs(cell)
+            .into_iter()
+            .map(|input| (input, false))
+            .collect();
+        while let Some((input, inputs_ready)) = stack.pop() {
+            if inputs_ready {
+                if !self.value_cache.read().unwrap().contains_key(&input) {
+                    let _ = self.evaluate_and_cache(&input);
+                }
+                continue;
+            }
+            // Cells seen already are either done, or part of a circular reference
+            if !seen.insert(input.clone()) {
+                continue;
+            }
+            let next = self.unevaluated_inputs(&input);
+            stack.push((input, true));
+            stack.extend(
+                next.into_iter()
+                    .filter(|next| !seen.contains(next))
+                    .map(|next| (next, false)),
+            );
+        }
+    }
+
+    fn unevaluated_inputs(&self, cell: &SpreadsheetCell) -> Vec<SpreadsheetCell> {
+        // The formulas a cell references that don't have a cached value yet
+        let Some(formula) = self.get_cell(cell).strip_prefix('=') else {
+            return Vec::new();
+        };
+        let cache = self.value_cache.read().unwrap();
+        let mut inputs: Vec<SpreadsheetCell> = Vec::new();
+        for [start, end] in referenced_ranges(formula) {
+            let rows = self
+                .data
+                .iter()
+                .enumerate()
+                .take(end.row + 1)
+                .skip(start.row);
+            for (row, data_row) in rows {
+                let cols = data_row.contents.iter().enumerate();
+                for (col, value) in cols.take(end.col + 1).skip(start.col) {
+                    let input = SpreadsheetCell { row, col };
+                    if value.starts_with('=') && !cache.contains_key(&input) {
+                        inputs.push(input);
+                    }
+                }
+            }
+        }
+        inputs
+    }
+
+    fn evaluate_and_cache(&self, cell: &SpreadsheetCell) -> Result<Token, ()> {
+        // A spill changing while evaluating may have changed cells that were already read, so
+        // the cell is evaluated once more if one does
+        let spill_changes = self.spill_changes.load(atomic::Ordering::SeqCst);
+        let (mut value, mut inputs) = self.evaluate_cell(cell);
+        if self.spill_changes.load(atomic::Ordering::SeqCst) != spill_changes {
+            (value, inputs) = self.evaluate_cell(cell);
+        }
+        self.value_cache
+            .write()
+            .unwrap()
+            .insert(cell.clone(), value.clone());
+        if !inputs.is_empty() {
+            self.value_inputs
+                .write()
+                .unwrap()
+                .insert(cell.clone(), inputs);
+        }
+        value
+    }
+
+    fn last_row_read(&self, cell: &SpreadsheetCell) -> usize {
+        if !self.get_cell(cell).starts_with('=') {
+            return cell.row;
+        }
+        let inputs = self.value_inputs.read().unwrap();
+        let ranges = inputs.get(cell).into_iter().flatten();
+        ranges.map(|[_, end]| end.row).fold(cell.row, max)
+    }
+
+    fn evaluate_cell(
+        &self,
+        cell: &SpreadsheetCell,
+    ) -> (Result<Token, ()>, Vec<[SpreadsheetCell; 2]>) {
+        // A cell's value along with the ranges it depends on. The cache isn't locked while
+        // evaluating, since formulas look up other cells.
+        let contents = self.get_cell(cell);
+        let mut inputs = contents
+            .strip_prefix('=')
+            .map(value_inputs)
+            .unwrap_or_default();
+        let value = match cell_to_token(contents, self, Some(cell)) {
+            Ok(token) if token.token_type == TokenType::Array => {
+                let values = token.array.unwrap_or_default();
+                // Whether a spill is blocked depends on what's in its way
+                inputs.push(Spreadsheet::spill_area(cell, &values));
+                Ok(self.spill(cell, values))
+            }
+            value => value,
+        };
+        (value, inputs)
     }
 
     pub fn select_matrix(&self, a: &SpreadsheetCell, b: &SpreadsheetCell) -> Vec<Vec<String>> {
@@ -237,46 +1773,674 @@ impl Spreadsheet {
         for row in min_row..=max_row {
             let mut row_items: Vec<String> = Vec::new();
             for col in min_col..=max_col {
-                row_items.push(
-                    self.get_cell_value(&SpreadsheetCell { row, col })
-                        .unwrap()
-                        .content,
-                );
+                // Values are copied as they're shown, so formatted numbers keep their formats
+                let cell = SpreadsheetCell { row, col };
+                let value = self.get_cell_value(&cell).unwrap().content;
+                row_items.push(self.get_format(&cell).to_entry(&value, &self.locale));
             }
             mat.push(row_items);
         }
         mat
     }
 
+    pub fn select_ranges(&self, ranges: &[[SpreadsheetCell; 2]]) -> Option<Vec<Vec<String>>> {
+        // Excel can only copy multiple ranges when they line up, either all spanning the same
+        // rows (joined left to right) or all spanning the same columns (joined top to bottom).
+        let first = ranges.first()?;
+        let mut ranges = ranges.to_vec();
+        if ranges
+            .iter()
+            .all(|[start, end]| start.row == first[0].row && end.row == first[1].row)
+        {
+            ranges.sort_by_key(|[start, _]| start.col);
+            let mut mat: Vec<Vec<String>> = vec![Vec::new(); first[1].row - first[0].row + 1];
+            for [start, end] in ranges.iter() {
+                for (row, items) in mat.iter_mut().zip(self.select_matrix(start, end)) {
+                    row.extend(items);
+                }
+            }
+            Some(mat)
+        } else if ranges
+            .iter()
+            .all(|[start, end]| start.col == first[0].col && end.col == first[1].col)
+        {
+            ranges.sort_by_key(|[start, _]| start.row);
+            Some(
+                ranges
+                    .iter()
+                    .flat_map(|[start, end]| self.select_matrix(start, end))
+                    .collect(),
+            )
+        } else {
+            None
+        }
+    }
+
+    pub fn select_raw_matrix(&self, a: &SpreadsheetCell, b: &SpreadsheetCell) -> Vec<Vec<String>> {
+        // Like select_matrix, but with the cells' contents (ie. formulas) instead of their values
+        (min(a.row, b.row)..=max(a.row, b.row))
+            .map(|row| {
+                (min(a.col, b.col)..=max(a.col, b.col))
+                    .map(|col| self.get_cell(&SpreadsheetCell { row, col }).to_string())
+                    .collect()
+            })
+            .collect()
+    }
+
     pub fn replace_matrix(&mut self, start: &SpreadsheetCell, mat: Vec<Vec<String>>) {
-        let mut changes: Vec<SpreadsheetEdit> = Vec::new();
-        for row in 0..mat.len() {
-            for col in 0..mat[row].len() {
+        // Pasted values are treated like typed ones, so a pasted 5% is still a percentage
+        let mut entries: Vec<(SpreadsheetCell, String)> = Vec::new();
+        for (row, values) in mat.into_iter().enumerate() {
+            for (col, value) in values.into_iter().enumerate() {
                 let cell = SpreadsheetCell {
                     row: start.row + row,
                     col: start.col + col,
                 };
-                let value = mat[row][col].clone();
-                changes.push(SpreadsheetEdit {
-                    cell: cell.clone(),
-                    before: self.get_cell(&cell).to_string(),
-                    after: value.clone(),
-                });
-                self.internal_set_cell(&cell, &value);
+                entries.push((cell, value));
             }
         }
-        self.undo_stack.edit(changes);
+        self.enter_cells(&entries);
     }
+
+    pub fn sort_by_column(&mut self, col: usize, descending: bool) {
+        // Sorts every row by one column as a single undo step, leaving header rows in place.
+        // Numbers come before text and blanks always go last, as in Excel.
+        let start = self.header_rows;
+        let width = self
+            .data
+            .iter()
+            .map(|r| r.contents.len())
+            .max()
+            .unwrap_or(0);
+        if self.data.len() <= start + 1 || col >= width {
+            return;
+        }
+
+        // Formulas are sorted by their values rather than their text
+        let mut rows: Vec<(String, Vec<String>)> = self.data[start..]
+            .iter()
+            .enumerate()
+            .map(|(idx, row)| {
+                let cell = SpreadsheetCell {
+                    row: start + idx,
+                    col,
+                };
+                let key = match self.get_cell(&cell) {
+                    value if value.starts_with("=") => {
+                        self.get_cell_value(&cell).unwrap_or_default().content
+                    }
+                    value => value.to_string(),
+                };
+                let mut contents = row.contents.clone();
+                contents.resize(width, String::new());
+                (key, contents)
+            })
+            .collect();
+
+        let number = |value: &str| {
+            value
+                .parse::<f64>()
+                .ok()
+                .or_else(|| self.locale.parse_number(value))
+        };
+        rows.sort_by(|(a, _), (b, _)| match (a.is_empty(), b.is_empty()) {
+            (true, true) => Ordering::Equal,
+            (true, false) => Ordering::Greater,
+            (false, true) => Ordering::Less,
+            (false, false) => {
+                let ordering = match (number(a), number(b)) {
+                    (Some(a), Some(b)) => a.partial_cmp(&b).unwrap_or(Ordering::Equal),
+                    (Some(_), None) => Ordering::Less,
+                    (None, Some(_)) => Ordering::Greater,
+                    (None, None) => a.to_lowercase().cmp(&b.to_lowercase()),
+                };
+                if descending {
+                    ordering.reverse()
+                } else {
+                    ordering
+                }
+            }
+        });
+
+        let mut edits: Vec<(SpreadsheetCell, String)> = Vec::new();
+        for (idx, (_, contents)) in rows.into_iter().enumerate() {
+            for (col, value) in contents.into_iter().enumerate() {
+                let cell = SpreadsheetCell {
+                    row: start + idx,
+                    col,
+                };
+                if self.get_cell(&cell) != value {
+                    edits.push((cell, value));
+                }
+            }
+        }
+        self.set_cells(&edits);
+    }
+
+    pub fn clear_ranges(&mut self, ranges: &[[SpreadsheetCell; 2]]) {
+        // Empties every range as a single undo step
+        let mut edits: Vec<(SpreadsheetCell, String)> = Vec::new();
+        for [start, end] in ranges {
+            for row in start.row..=end.row {
+                for col in start.col..=end.col {
+                    edits.push((SpreadsheetCell { row, col }, String::new()));
+                }
+            }
+        }
+        self.set_cells(&edits);
+    }
+
+    pub fn fill_down(&mut self, range: &[SpreadsheetCell; 2]) {
+        // Copies the top row of the range into the rows below as one undo step, like Excel's
+        // Ctrl+D. Each formula is only scanned once, then shifted for every row it's copied to.
+        let [start, end] = range;
+        let mut edits: Vec<(SpreadsheetCell, String)> = Vec::new();
+        for col in start.col..=end.col {
+            let source = ShiftableFormula::new(self.get_cell(&SpreadsheetCell {
+                row: start.row,
+                col,
+            }));
+            for row in start.row + 1..=end.row {
+                edits.push((
+                    SpreadsheetCell { row, col },
+                    source.shift((row - start.row) as i64, 0),
+                ));
+            }
+        }
+        self.set_cells(&edits);
+    }
+}
+
+// Delimiters that detection picks from, in order of preference on a tie. Tabs go first since
+// they're much less likely to appear inside of a cell.
+pub const DELIMITERS: [char; 4] = ['\t', ',', ';', '|'];
+
+pub fn detect_delimiter(text: &str, decimal_separator: char) -> Option<char> {
+    // Sniffs the first few lines for the delimiter splitting them most consistently, counting
+    // only outside of quotes. The decimal separator is never picked, as it'd split numbers apart.
+    const SAMPLE_LINES: usize = 10;
+    let mut lines: Vec<[usize; DELIMITERS.len()]> = Vec::new();
+    let mut counts = [0; DELIMITERS.len()];
+    let mut line_empty = true;
+    let mut in_quotes = false;
+    for c in text.chars() {
+        match c {
+            '"' => in_quotes = !in_quotes,
+            '\n' if !in_quotes => {
+                if !line_empty {
+                    lines.push(std::mem::take(&mut counts));
+                }
+                line_empty = true;
+                if lines.len() == SAMPLE_LINES {
+                    break;
+                }
+                continue;
+            }
+            c if !in_quotes => {
+                if let Some(idx) = DELIMITERS.iter().position(|d| *d == c) {
+                    counts[idx] += 1;
+                }
+            }
+            _ => (),
+        }
+        line_empty = line_empty && c.is_whitespace() && c != '\t';
+    }
+    if !line_empty && lines.len() < SAMPLE_LINES {
+        lines.push(counts);
+    }
+
+    // Delimiters found the same number of times on every line beat ones that vary, then more
+    // columns beat fewer. Candidates are checked in reverse since max_by_key takes the last of
+    // any tie, which leaves the most preferred one.
+    (0..DELIMITERS.len())
+        .rev()
+        .filter(|idx| DELIMITERS[*idx] != decimal_separator)
+        .map(|idx| {
+            let per_line = lines.iter().map(|counts| counts[idx]);
+            let fewest = per_line.clone().min().unwrap_or(0);
+            let most = per_line.clone().max().unwrap_or(0);
+            (idx, (fewest == most, fewest, per_line.sum::<usize>()))
+        })
+        .filter(|(_, (_, _, total))| *total > 0)
+        .max_by_key(|(_, score)| *score)
+        .map(|(idx, _)| DELIMITERS[idx])
+}
+
+pub fn decode_text(bytes: Vec<u8>) -> (String, bool) {
+    // Reads UTF-8, along with whether it was valid. Anything else (ie. Latin-1 from older
+    // programs) still opens, with the bytes that aren't UTF-8 replaced by �.
+    match String::from_utf8(bytes) {
+        Ok(text) => (text, true),
+        Err(e) => {
+            log::warn!("Text isn't valid UTF-8, replacing what isn't: {}", e);
+            (String::from_utf8_lossy(e.as_bytes()).into_owned(), false)
+        }
+    }
+}
+
+pub fn is_subtotal(value: &str) -> bool {
+    // Formulas using SUBTOTAL, which other subtotals leave out so totals aren't counted twice
+    value.starts_with('=') && value.to_uppercase().contains("SUBTOTAL(")
+}
+
+pub fn strip_bom(text: &str) -> &str {
+    // Files saved as UTF-8 by Excel and Notepad start with a byte order mark, which would
+    // otherwise end up hidden at the start of the first cell
+    text.strip_prefix('\u{FEFF}').unwrap_or(text)
+}
+
+pub fn strip_comments(contents: &str, comment: char) -> (String, Vec<(usize, String)>) {
+    // Takes out lines starting with a comment character (ie. #) along with the row each came
+    // before. Lines inside of a quoted cell are part of the cell, rather than comments.
+    let mut text = String::new();
+    let mut comments: Vec<(usize, String)> = Vec::new();
+    let mut rows = 0;
+    let mut in_quotes = false;
+    for line in contents.split_inclusive('\n') {
+        if !in_quotes && line.starts_with(comment) {
+            comments.push((rows, line.trim_end_matches(['\r', '\n']).to_string()));
+            continue;
+        }
+        in_quotes ^= line.matches('"').count() % 2 == 1;
+        if !in_quotes {
+            rows += 1;
+        }
+        text += line;
+    }
+    (text, comments)
 }
 
-fn parse_csv_line(line: &str) -> Vec<String> {
+fn detect_header(rows: &[SpreadsheetRow]) -> bool {
+    // Row 1 looks like a header when it's all text and the row under it has numbers in it
+    let (Some(first), Some(second)) = (rows.first(), rows.get(1)) else {
+        return false;
+    };
+    let is_number = |value: &String| value.parse::<f64>().is_ok();
+    !first.contents.is_empty()
+        && first
+            .contents
+            .iter()
+            .all(|value| !value.is_empty() && !is_number(value))
+        && second.contents.iter().any(is_number)
+}
+
+pub fn quote_csv_cell(value: &str, delimiter: char) -> String {
+    // Quotes a cell only when it would otherwise be split up or misread, doubling inner quotes
+    if value.contains(delimiter)
+        || value.contains('"')
+        || value.contains('\n')
+        || value.contains('\r')
+    {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+pub fn parse_csv(contents: &str, delimiter: char) -> Vec<Vec<String>> {
+    // The reverse of quote_csv_cell, so quoted cells can hold delimiters, line breaks and ""
+    // escaped quotes. Quotes only count at the start of a cell, like in Excel.
+    let mut rows: Vec<Vec<String>> = Vec::new();
+    let mut row: Vec<String> = Vec::new();
+    let mut cell = String::new();
     let mut inside_quote = false;
-    line.split(|c| {
-        if c == '"' {
-            inside_quote = !inside_quote;
-        }
-        c == ',' && !inside_quote
-    })
-    .map(|c| c.to_string())
-    .collect()
+    let mut chars = contents.chars().peekable();
+    while let Some(c) = chars.next() {
+        if inside_quote {
+            if c != '"' {
+                cell.push(c);
+            } else if chars.next_if_eq(&'"').is_some() {
+                cell.push('"');
+            } else {
+                inside_quote = false;
+            }
+        } else if c == '"' && cell.is_empty() {
+            inside_quote = true;
+        } else if c == delimiter {
+            row.push(std::mem::take(&mut cell));
+        } else if c == '\n' || c == '\r' {
+            if c == '\r' {
+                chars.next_if_eq(&'\n');
+            }
+            row.push(std::mem::take(&mut cell));
+            rows.push(std::mem::take(&mut row));
+        } else {
+            cell.push(c);
+        }
+    }
+    // The last line may not end in a line break
+    if !cell.is_empty() || !row.is_empty() {
+        row.push(cell);
+        rows.push(row);
+    }
+    rows
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::CopyDelimiter;
+    use crate::formats::CellColor;
+
+    #[test]
+    fn formatted_numbers_are_saved_as_typed() {
+        let mut spreadsheet = Spreadsheet::from_text("rate,price\n1,2\n", ',');
+        spreadsheet.enter_cell(&SpreadsheetCell { row: 1, col: 0 }, "5%");
+        spreadsheet.enter_cell(&SpreadsheetCell { row: 1, col: 1 }, "$4.50");
+        assert_eq!(
+            spreadsheet.get_cell(&SpreadsheetCell { row: 1, col: 0 }),
+            "0.05"
+        );
+        assert_eq!(spreadsheet.to_csv(), "rate,price\n5%,$4.5\n");
+    }
+
+    #[test]
+    fn whole_columns_are_colored_by_column() {
+        let mut spreadsheet = Spreadsheet::new();
+        let red = |colors: &mut CellColors| colors.bg = Some(CellColor::Red);
+        let column = [
+            SpreadsheetCell { row: 0, col: 1 },
+            SpreadsheetCell {
+                row: SPREADSHEET_MAX_ROWS - 1,
+                col: 1,
+            },
+        ];
+        spreadsheet.color_range(&column, red);
+        assert!(spreadsheet.colors.is_empty());
+        let cell = SpreadsheetCell { row: 500, col: 1 };
+        assert_eq!(spreadsheet.get_colors(&cell).bg, Some(CellColor::Red));
+
+        // Clearing one cell of a colored column keeps it cleared
+        spreadsheet.color_range(&[cell.clone(), cell.clone()], |colors| colors.bg = None);
+        assert_eq!(spreadsheet.get_colors(&cell).bg, None);
+        spreadsheet.undo();
+        assert_eq!(spreadsheet.get_colors(&cell).bg, Some(CellColor::Red));
+        spreadsheet.undo();
+        assert_eq!(spreadsheet.get_colors(&cell).bg, None);
+    }
+
+    #[test]
+    fn undoing_a_column_color_restores_its_cells() {
+        let mut spreadsheet = Spreadsheet::new();
+        let cell = SpreadsheetCell { row: 3, col: 0 };
+        spreadsheet.color_range(&[cell.clone(), cell.clone()], |colors| {
+            colors.fg = Some(CellColor::Red)
+        });
+        spreadsheet.enter_cell(&cell, "7");
+        let column = [
+            SpreadsheetCell { row: 0, col: 0 },
+            SpreadsheetCell {
+                row: SPREADSHEET_MAX_ROWS - 1,
+                col: 0,
+            },
+        ];
+        spreadsheet.color_range(&column, |colors| colors.fg = Some(CellColor::Blue));
+        assert_eq!(spreadsheet.get_colors(&cell).fg, Some(CellColor::Blue));
+
+        spreadsheet.undo();
+        assert_eq!(spreadsheet.get_colors(&cell).fg, Some(CellColor::Red));
+        assert_eq!(spreadsheet.get_cell(&cell), "7");
+        spreadsheet.undo();
+        assert_eq!(spreadsheet.get_cell(&cell), "");
+        spreadsheet.undo();
+        assert_eq!(spreadsheet.get_colors(&cell).fg, None);
+    }
+
+    #[test]
+    fn filters_hide_rows_until_cleared() {
+        let mut spreadsheet =
+            Spreadsheet::from_text("fruit,qty\napple,5\nbanana,12\ncherry,20\n", ',');
+        spreadsheet.set_filter(1, Some(String::from(">10")));
+        assert_eq!(spreadsheet.filtered_rows(), &HashSet::from([1]));
+        assert!(spreadsheet.is_row_hidden(1));
+
+        // Rows have to meet every column's filter
+        spreadsheet.set_filter(0, Some(String::from("<>cherry")));
+        assert_eq!(spreadsheet.filtered_rows(), &HashSet::from([1, 3]));
+        spreadsheet.set_filter(1, None);
+        assert_eq!(spreadsheet.filtered_rows(), &HashSet::from([3]));
+
+        spreadsheet.clear_filters();
+        assert!(spreadsheet.filtered_rows().is_empty());
+        assert!(spreadsheet.filters().is_empty());
+        assert!(!spreadsheet.is_row_hidden(3));
+        assert_eq!(
+            spreadsheet.get_cell(&SpreadsheetCell { row: 3, col: 0 }),
+            "cherry"
+        );
+    }
+
+    #[test]
+    fn header_rows_stay_put() {
+        let mut spreadsheet = Spreadsheet::from_text("c\nb\na\n3\n1\n2\n", ',');
+        spreadsheet.set_header_rows(3);
+        spreadsheet.sort_by_column(0, false);
+        let column: Vec<&str> = (0..6)
+            .map(|row| spreadsheet.get_cell(&SpreadsheetCell { row, col: 0 }))
+            .collect();
+        assert_eq!(column, ["c", "b", "a", "1", "2", "3"]);
+
+        spreadsheet.set_filter(0, Some(String::from(">1")));
+        assert_eq!(spreadsheet.filtered_rows(), &HashSet::from([3]));
+    }
+
+    #[test]
+    fn formulas_are_only_evaluated_once_needed() {
+        let spreadsheet = Spreadsheet::from_text(&"=RAND(),1\n".repeat(2000), ',');
+        let number = SpreadsheetCell { row: 1999, col: 1 };
+        assert_eq!(spreadsheet.get_cell_value(&number).unwrap().content, "1");
+        assert_eq!(spreadsheet.value_cache.read().unwrap().len(), 1);
+
+        let formula = SpreadsheetCell { row: 1500, col: 0 };
+        assert!(spreadsheet.get_cell_value(&formula).is_ok());
+        assert_eq!(spreadsheet.value_cache.read().unwrap().len(), 2);
+    }
+
+    #[test]
+    fn long_reference_chains_evaluate_once_per_link() {
+        // Each link references the next, far deeper than recursing per link could go
+        let links = 5000;
+        let mut text = String::from("n\n");
+        for row in 1..=links {
+            text += &format!("=A{}+1\n", row + 2);
+        }
+        text += "0\n";
+        let spreadsheet = Spreadsheet::from_text(&text, ',');
+
+        let value = spreadsheet.get_cell_value(&SpreadsheetCell { row: 1, col: 0 });
+        assert_eq!(value.unwrap().content, links.to_string());
+        // Every link and the number at the end are cached once
+        assert_eq!(spreadsheet.value_cache.read().unwrap().len(), links + 1);
+        let last = SpreadsheetCell { row: links, col: 0 };
+        assert_eq!(spreadsheet.get_cell_value(&last).unwrap().content, "1");
+    }
+
+    #[test]
+    fn loading_detects_the_delimiter_after_comments() {
+        let text = "# a,b,c,d\nname;price\nbread;2\n";
+        let (spreadsheet, delimiter) = Spreadsheet::from_csv(text, None, Some('#'), '.');
+        assert_eq!(delimiter, ';');
+        assert_eq!(
+            spreadsheet.get_cell(&SpreadsheetCell { row: 1, col: 1 }),
+            "2"
+        );
+        assert!(spreadsheet.to_csv().starts_with("# a,b,c,d\nname"));
+
+        let (_, delimiter) = Spreadsheet::from_clipboard("one", None, None, '.');
+        assert_eq!(delimiter, '\t');
+    }
+
+    fn column_values(spreadsheet: &Spreadsheet, col: usize, rows: usize) -> Vec<String> {
+        (0..rows)
+            .map(|row| {
+                let cell = SpreadsheetCell { row, col };
+                spreadsheet.get_cell_value(&cell).unwrap().content
+            })
+            .collect()
+    }
+
+    #[test]
+    fn sequences_spill_unless_blocked() {
+        let mut spreadsheet = Spreadsheet::from_text("=SEQUENCE(3)\n", ',');
+        assert_eq!(column_values(&spreadsheet, 0, 3), ["1", "2", "3"]);
+        assert!(spreadsheet.is_spilled(&SpreadsheetCell { row: 2, col: 0 }));
+
+        spreadsheet.set_cell(&SpreadsheetCell { row: 2, col: 0 }, "x");
+        assert_eq!(column_values(&spreadsheet, 0, 3), ["#SPILL!", "", "x"]);
+        spreadsheet.undo();
+        assert_eq!(column_values(&spreadsheet, 0, 3), ["1", "2", "3"]);
+    }
+
+    #[test]
+    fn arrays_spill_in_their_own_shape() {
+        let spreadsheet = Spreadsheet::from_text("\"=SEQUENCE(2,2)\",,,=TRANSPOSE(A1:B2)\n", ',');
+        let anchor = SpreadsheetCell { row: 0, col: 0 };
+        assert_eq!(column_values(&spreadsheet, 0, 2), ["1", "3"]);
+        assert_eq!(column_values(&spreadsheet, 1, 2), ["2", "4"]);
+        assert_eq!(
+            spreadsheet.spill_range(&anchor),
+            Some([anchor, SpreadsheetCell { row: 1, col: 1 }])
+        );
+
+        // Rows become columns, so the 2x2 comes out mirrored
+        let transposed = SpreadsheetCell { row: 0, col: 3 };
+        assert_eq!(column_values(&spreadsheet, 3, 2), ["1", "2"]);
+        assert_eq!(column_values(&spreadsheet, 4, 2), ["3", "4"]);
+        assert_eq!(
+            spreadsheet.spill_range(&transposed),
+            Some([transposed, SpreadsheetCell { row: 1, col: 4 }])
+        );
+    }
+
+    #[test]
+    fn spill_references_cover_the_whole_array() {
+        let mut spreadsheet =
+            Spreadsheet::from_text("=SEQUENCE(3),=SUM(A1#),=COUNT(A1#),=B1#\n", ',');
+        assert_eq!(column_values(&spreadsheet, 1, 1), ["6"]);
+        assert_eq!(column_values(&spreadsheet, 2, 1), ["3"]);
+        // Like Excel, cells that don't spill can't be referenced this way
+        assert_eq!(column_values(&spreadsheet, 3, 1), ["#REF!"]);
+
+        // The reference follows the spill as it grows
+        spreadsheet.set_cell(&SpreadsheetCell { row: 0, col: 0 }, "=SEQUENCE(4)");
+        assert_eq!(column_values(&spreadsheet, 1, 1), ["10"]);
+        assert_eq!(column_values(&spreadsheet, 2, 1), ["4"]);
+    }
+
+    #[test]
+    fn formulas_cant_reach_past_the_limits() {
+        let spreadsheet =
+            Spreadsheet::from_text("1,=D1,=A11+1,=C10,=SUM(A:A)\n", ',').with_limits(10, 5);
+        let row: Vec<String> = (1..5)
+            .map(|col| {
+                let cell = SpreadsheetCell { row: 0, col };
+                spreadsheet.get_cell_value(&cell).unwrap().content
+            })
+            .collect();
+        assert_eq!(row, ["", "#REF!", "", "1"]);
+
+        let spreadsheet = Spreadsheet::from_text("=D1,=A11+1\n", ',').with_limits(10, 3);
+        assert_eq!(column_values(&spreadsheet, 0, 1), ["#REF!"]);
+        assert_eq!(column_values(&spreadsheet, 1, 1), ["#REF!"]);
+    }
+
+    #[test]
+    fn trailing_blank_lines_arent_rows() {
+        for text in ["a\n\n\n", "a\n\n\n\n", "a\n", "a"] {
+            let spreadsheet = Spreadsheet::from_text(text, ',');
+            assert_eq!(spreadsheet.data.len(), 1, "{:?}", text);
+            assert_eq!(spreadsheet.to_csv(), "a\n");
+        }
+        // Blank rows in between are kept, with or without a newline at the end
+        for text in ["a,b\n\n1,2\n", "a,b\n\n1,2"] {
+            let spreadsheet = Spreadsheet::from_text(text, ',');
+            assert_eq!(spreadsheet.to_csv(), "a,b\n\n1,2\n");
+            let reloaded = Spreadsheet::from_text(&spreadsheet.to_csv(), ',');
+            assert_eq!(reloaded.to_csv(), spreadsheet.to_csv());
+        }
+        assert_eq!(Spreadsheet::from_text("\n\n", ',').to_csv(), "");
+    }
+
+    #[test]
+    fn bulk_edits_are_one_step() {
+        let mut spreadsheet = Spreadsheet::from_text("1,=SUM(A1:A3)\n2\n3\n", ',');
+        assert_eq!(column_values(&spreadsheet, 1, 1), ["6"]);
+        let revision = spreadsheet.revision();
+        let edits: Vec<(SpreadsheetCell, String)> = (0..3)
+            .map(|row| (SpreadsheetCell { row, col: 0 }, String::from("10")))
+            .collect();
+        spreadsheet.set_cells(&edits);
+        assert_eq!(spreadsheet.revision(), revision + 1);
+        assert_eq!(column_values(&spreadsheet, 1, 1), ["30"]);
+
+        // Every cell goes back at once
+        spreadsheet.undo();
+        assert_eq!(column_values(&spreadsheet, 0, 3), ["1", "2", "3"]);
+        assert_eq!(column_values(&spreadsheet, 1, 1), ["6"]);
+    }
+
+    #[test]
+    fn used_cells_skip_the_blanks() {
+        let spreadsheet = Spreadsheet::from_text(",,x\n\n,\n=1,,,,y\n", ',');
+        let used: Vec<(SpreadsheetCell, &str)> = spreadsheet.iter_used_cells().collect();
+        assert_eq!(
+            used,
+            [
+                (SpreadsheetCell { row: 0, col: 2 }, "x"),
+                (SpreadsheetCell { row: 3, col: 0 }, "=1"),
+                (SpreadsheetCell { row: 3, col: 4 }, "y"),
+            ]
+        );
+        assert_eq!(Spreadsheet::new().iter_used_cells().count(), 0);
+    }
+
+    #[test]
+    fn used_ranges_cover_every_used_cell() {
+        assert_eq!(Spreadsheet::new().used_range(), None);
+        assert_eq!(Spreadsheet::from_text(",\n\n", ',').used_range(), None);
+
+        let cell = SpreadsheetCell { row: 2, col: 1 };
+        let spreadsheet = Spreadsheet::from_text("\n\n,x\n", ',');
+        assert_eq!(spreadsheet.used_range(), Some([cell.clone(), cell]));
+
+        // Corners come from different cells, so neither has to be used itself
+        let spreadsheet = Spreadsheet::from_text(",,x\n\ny\n,,,,,\n,z\n", ',');
+        assert_eq!(
+            spreadsheet.used_range(),
+            Some([
+                SpreadsheetCell { row: 0, col: 0 },
+                SpreadsheetCell { row: 4, col: 2 }
+            ])
+        );
+    }
+
+    #[test]
+    fn tabs_in_cells_survive_copying() {
+        let spreadsheet = Spreadsheet::from_text("\"a\tb\"\tc\n", '\t');
+        let row = [
+            SpreadsheetCell { row: 0, col: 0 },
+            SpreadsheetCell { row: 0, col: 1 },
+        ];
+        assert_eq!(spreadsheet.get_cell(&row[0]), "a\tb");
+
+        // Copied as tab-separated values, the way the app does, then pasted into another sheet
+        let cells: Vec<String> = row
+            .iter()
+            .map(|c| spreadsheet.get_cell(c).to_string())
+            .collect();
+        let copied = CopyDelimiter::Tab.join(&cells);
+        assert_eq!(copied, "\"a\tb\"\tc");
+        let mut pasted = Spreadsheet::new();
+        let edits: Vec<(SpreadsheetCell, String)> = row
+            .iter()
+            .cloned()
+            .zip(CopyDelimiter::Tab.split(&copied).remove(0))
+            .collect();
+        pasted.set_cells(&edits);
+        assert_eq!(pasted.get_cell(&row[0]), "a\tb");
+        assert_eq!(pasted.get_cell(&row[1]), "c");
+
+        let saved = quote_csv_cell("a\tb", '\t') + "\tc\n";
+        assert_eq!(parse_csv(&saved, '\t'), [["a\tb", "c"]]);
+    }
 }
@@ -5,21 +5,45 @@ use ratatui::{
     crossterm::event::{Event, KeyCode, KeyEventKind},
     layout::Rect,
     style::{Color, Style},
+    text::Line,
     widgets::{Block, Borders, Clear, List, ListState, Paragraph, StatefulWidget, Widget, Wrap},
 };
 
 use crate::formula_functions::get_funcs;
-use crate::utils::FuzzySearch;
+use crate::utils::{FuzzySearch, StringPadding};
 
 use super::text_input::TextInputState;
 
 #[derive(Default)]
 pub struct FormulaSuggestions {}
 
+#[derive(Debug, Clone, PartialEq)]
+pub enum Suggestion {
+    Function(String),
+    Name { name: String, reference: String },
+}
+
+impl Suggestion {
+    fn label(&self) -> String {
+        match self {
+            Suggestion::Function(name) => name.clone(),
+            Suggestion::Name { name, reference } => format!("{} ({})", name, reference),
+        }
+    }
+
+    fn completion(&self) -> String {
+        match self {
+            Suggestion::Function(name) => name.clone() + "(",
+            Suggestion::Name { reference, .. } => reference.clone(),
+        }
+    }
+}
+
 #[derive(Debug, Default)]
 pub struct FormulaSuggestionsState {
     pub text_input_state: TextInputState,
     pub visible: bool,
+    pub names: Vec<(String, String)>, // Pairs of names and the references they stand for
     list_state: ListState,
 }
 
@@ -44,36 +68,87 @@ impl FormulaSuggestions {
             state.list_state.select(Some(0));
         }
 
-        let cursor = state.text_input_state.cursor();
-        let suggestions_area = Rect::new(
-            cursor as u16 + state.text_input_state.area.x,
-            state.text_input_state.area.y + state.text_input_state.area.height,
-            min(
-                area.width - state.text_input_state.area.x - cursor as u16,
-                max(
-                    suggestions.iter().max_by_key(|s| s.len()).unwrap().len(),
-                    "Functions".len(),
-                ) as u16
-                    + 2,
-            ),
-            min(
-                area.height - state.text_input_state.area.y - state.text_input_state.area.height,
-                suggestions.len() as u16 + 2,
-            ),
+        let cursor = state.text_input_state.cursor() as u16;
+        let input_area = state.text_input_state.area;
+        let width = min(
+            area.width,
+            max(
+                suggestions
+                    .iter()
+                    .map(|s| s.label().chars().count())
+                    .max()
+                    .unwrap(),
+                "Functions".len(),
+            ) as u16
+                + 2,
         );
+        let height = suggestions.len() as u16 + 2;
 
-        Clear.render(suggestions_area, buf);
-        let block = Block::new().title("Functions").borders(Borders::ALL);
+        // Follows the cursor
+        let suggestions_area = popup_area(
+            area,
+            input_area,
+            input_area.x.saturating_add(cursor),
+            width,
+            height,
+        );
+        if suggestions_area.is_empty() {
+            return;
+        }
 
-        let list = List::new(suggestions)
-            // .wrap(Wrap { trim: false })
-            // .style(Style::new().black())
-            .highlight_style(Style::new().bg(Color::White).fg(Color::Black))
-            .block(block);
-        StatefulWidget::render(list, suggestions_area, buf, &mut state.list_state);
+        // Labels too long for the popup (ie. on narrow terminals) are cut off with an ellipsis
+        let label_width = suggestions_area.width.saturating_sub(2) as usize;
+        let items = suggestions
+            .iter()
+            .map(|s| {
+                let label = s.label().ellipsize(label_width);
+                match s {
+                    Suggestion::Function(_) => Line::from(label),
+                    Suggestion::Name { .. } => Line::styled(label, Style::new().fg(Color::Cyan)),
+                }
+            })
+            .collect();
+        render_list_popup(
+            suggestions_area,
+            buf,
+            "Functions",
+            items,
+            &mut state.list_state,
+        );
+    }
+}
+
+pub fn popup_area(area: Rect, anchor: Rect, x: u16, width: u16, height: u16) -> Rect {
+    // Where a popup for something on screen goes: below it unless there's more room above it,
+    // and shifted left rather than running off the right edge
+    let x = min(x, area.right().saturating_sub(width));
+    let space_below = area.bottom().saturating_sub(anchor.bottom());
+    let space_above = anchor.y.saturating_sub(area.y);
+    if space_below >= height || space_below >= space_above {
+        Rect::new(x, anchor.bottom(), width, min(height, space_below))
+    } else {
+        let height = min(height, space_above);
+        Rect::new(x, anchor.y - height, width, height)
     }
 }
 
+pub fn render_list_popup(
+    area: Rect,
+    buf: &mut Buffer,
+    title: &str,
+    items: Vec<Line>,
+    list_state: &mut ListState,
+) {
+    Clear.render(area, buf);
+    let block = Block::new().title(title).borders(Borders::ALL);
+    let list = List::new(items)
+        // .wrap(Wrap { trim: false })
+        // .style(Style::new().black())
+        .highlight_style(Style::new().bg(Color::White).fg(Color::Black))
+        .block(block);
+    StatefulWidget::render(list, area, buf, list_state);
+}
+
 impl StatefulWidget for FormulaSuggestions {
     type State = FormulaSuggestionsState;
 
@@ -111,11 +186,9 @@ impl FormulaSuggestionsState {
                         }
                     }
                     KeyCode::Backspace => {
-                        self.visible = self
-                            .text_input_state
-                            .value
-                            .chars()
-                            .nth(cursor - 1)
+                        self.visible = cursor
+                            .checked_sub(1)
+                            .and_then(|idx| self.text_input_state.value.chars().nth(idx))
                             .is_some_and(|c| c.is_ascii_alphanumeric());
 
                         // Clear selection on hide
@@ -137,7 +210,7 @@ impl FormulaSuggestionsState {
                         if self.list_state.selected().is_none() {
                             self.list_state.select(Some(0));
                         }
-                        let word = suggestions[self.list_state.selected().unwrap()].clone() + "(";
+                        let word = suggestions[self.list_state.selected().unwrap()].completion();
                         self.text_input_state.set_word(word.as_str());
                     }
                     _ => {
@@ -149,20 +222,48 @@ impl FormulaSuggestionsState {
         }
     }
 
-    pub fn get_suggestions(&self) -> Vec<String> {
-        // println!("\n{:?}", self.text_input_state.get_word());
+    pub fn dismiss(&mut self) {
+        self.visible = false;
+        self.list_state.select(None);
+    }
+
+    pub fn get_suggestions(&self) -> Vec<Suggestion> {
         if let Some(current_word) = self.text_input_state.get_word() {
             if current_word.len() == 0 {
                 return Vec::new();
             }
+            let search = current_word.to_ascii_uppercase();
             let funcs = get_funcs()
                 .keys()
                 .map(|k| k.to_string())
                 .collect::<Vec<String>>();
-            funcs.fuzzy_search(current_word.to_ascii_uppercase().as_str(), 2)
+            let mut suggestions: Vec<Suggestion> = funcs
+                .fuzzy_search(search.as_str(), 2)
+                .into_iter()
+                .map(Suggestion::Function)
+                .collect();
+
+            // Names are matched case-insensitively, then mapped back to their original casing
+            let names = self
+                .names
+                .iter()
+                .map(|(name, _)| name.to_ascii_uppercase())
+                .collect::<Vec<String>>();
+            for matched in names.fuzzy_search(search.as_str(), 2) {
+                if let Some((name, reference)) = self
+                    .names
+                    .iter()
+                    .find(|(name, _)| name.to_ascii_uppercase() == matched)
+                {
+                    suggestions.push(Suggestion::Name {
+                        name: name.clone(),
+                        reference: reference.clone(),
+                    });
+                }
+            }
+            suggestions
         } else {
             Vec::new()
         }
-        // Vec::new()
     }
 }
@@ -1,19 +1,22 @@
 use std::{
     cmp::{max, min},
-    collections::HashMap,
+    collections::{HashMap, HashSet},
 };
 
 use ratatui::{
     buffer::Buffer,
-    crossterm::event::{Event, MouseEventKind},
+    crossterm::event::{Event, KeyModifiers, MouseEvent, MouseEventKind},
     layout::{Position, Rect},
-    style::{Color, Style},
+    style::{Color, Modifier, Style},
     widgets::StatefulWidget,
 };
 
 use crate::{
+    formats::{CellColor, CellFormat},
+    formulas::is_zero_padded,
     references::Reference,
-    spreadsheet::{Spreadsheet, SpreadsheetCell, SPREADSHEET_MAX_COLS, SPREADSHEET_MAX_ROWS},
+    sidecar::Sidecar,
+    spreadsheet::{Spreadsheet, SpreadsheetCell, MIN_COL_WIDTH},
     utils::StringPadding,
 };
 
@@ -23,69 +26,199 @@ fn render_cell(
     decimals: u32,
     spreadsheet: &Spreadsheet,
     formula_cache: &mut HashMap<SpreadsheetCell, String>,
+    cache_stats: &mut FormulaCacheStats,
 ) -> String {
     let mut cell_text = spreadsheet.get_cell(cell).to_string();
     let mut rendered: String;
-    if cell_text.starts_with("=") {
+    let is_text = cell_text.starts_with('\'') || is_zero_padded(&cell_text);
+    if cell_text.starts_with('\'') {
+        // Literal text is shown without its apostrophe and never evaluated
+        cell_text.remove(0);
+    } else if cell_text.starts_with("=") {
         if let Some(cached_value) = formula_cache.get(cell) {
+            cache_stats.hits += 1;
             cell_text = cached_value.clone();
-        } else if let Ok(cell_value) = spreadsheet.get_cell_value(cell) {
-            cell_text = cell_value.content;
-            formula_cache.insert(cell.clone(), cell_text.clone());
+        } else {
+            cache_stats.misses += 1;
+            if let Ok(cell_value) = spreadsheet.get_cell_value(cell) {
+                cell_text = cell_value.content;
+                formula_cache.insert(cell.clone(), cell_text.clone());
+            }
         }
+    } else if let Some(spilled_value) = spreadsheet.spilled_value(cell) {
+        cell_text = spilled_value.content;
     }
 
-    if let Ok(number) = cell_text.parse::<f32>() {
-        let rounding_scalar = f32::powf(10f32, (decimals) as f32);
-        rendered = ((number * rounding_scalar).round() / rounding_scalar).to_string();
-
-        if let Some(rounded_decimals) = rendered.split_once(".") {
-            for _ in 0..(decimals as usize - rounded_decimals.1.len()) {
-                rendered.push('0');
-            }
+    // Formula results are already normalized, while typed values are in the user's locale
+    let number =
+        if spreadsheet.get_cell(cell).is_empty() || spreadsheet.get_cell(cell).starts_with("=") {
+            cell_text.parse::<f64>().ok()
         } else {
-            rendered.push('.');
-            for _ in 0..decimals {
-                rendered.push('0');
-            }
+            spreadsheet.locale.parse_number(&cell_text)
+        };
+    let format = spreadsheet.get_format(cell);
+    match number {
+        Some(number) if !is_text && !number.is_finite() => {
+            // Formulas already give errors for NaN and infinities, so this only catches strays
+            rendered = String::from("#NUM!");
         }
+        Some(number) if !is_text && matches!(format, CellFormat::Date | CellFormat::Time) => {
+            // Dates and times are shown instead of the number, rather than rounding it
+            rendered = format
+                .to_date_time(number)
+                .unwrap_or_default()
+                .left_pad(max_length, ' ');
+        }
+        Some(number)
+            if !is_text
+                && matches!(
+                    format,
+                    CellFormat::Scientific(_) | CellFormat::Engineering(_)
+                ) =>
+        {
+            rendered = spreadsheet
+                .locale
+                .format_number(&format.to_exponent(number).unwrap_or_default())
+                .left_pad(max_length, ' ');
+        }
+        Some(number) if !is_text && matches!(format, CellFormat::Fraction(_)) => {
+            rendered = format
+                .to_fraction(number)
+                .unwrap_or_default()
+                .left_pad(max_length, ' ');
+        }
+        Some(number) if !is_text => {
+            let number = format.scale(number);
+            let rounding_scalar = f64::powf(10f64, (decimals) as f64);
+            let rounded = (number * rounding_scalar).round() / rounding_scalar;
+            // Adding zero turns -0 (ie. from rounding -0.001) into 0
+            rendered = (rounded + 0.0).to_string();
+
+            if let Some(rounded_decimals) = rendered.split_once(".") {
+                for _ in 0..(decimals as usize - rounded_decimals.1.len()) {
+                    rendered.push('0');
+                }
+            } else {
+                rendered.push('.');
+                for _ in 0..decimals {
+                    rendered.push('0');
+                }
+            }
 
-        rendered = rendered.left_pad(max_length, ' ');
-    } else {
-        rendered = cell_text.to_string();
+            rendered = format
+                .decorate(&spreadsheet.locale.format_number(&rendered))
+                .left_pad(max_length, ' ');
+        }
+        _ => {
+            // Tabs and line breaks are kept in the cell, but shown as spaces to keep the grid intact
+            rendered = cell_text.replace(|c: char| c.is_control(), " ");
+        }
     }
 
-    // Shouldn't ever fail, but if it does, just return an empty string
+    // Cut by characters rather than bytes, since symbols like € take up more than one byte
     rendered
-        .get(0..min(max_length, rendered.len()))
-        .unwrap_or("")
-        .to_string()
+        .chars()
+        .take(max_length)
+        .collect::<String>()
         .right_pad(max_length, ' ')
 }
 
+pub fn to_color(color: CellColor) -> Color {
+    match color {
+        CellColor::Red => Color::Red,
+        CellColor::Green => Color::Green,
+        CellColor::Yellow => Color::Yellow,
+        CellColor::Blue => Color::Blue,
+        CellColor::Magenta => Color::Magenta,
+        CellColor::Cyan => Color::Cyan,
+        CellColor::White => Color::White,
+        CellColor::Black => Color::Black,
+    }
+}
+
 pub struct InfiniteTable<'a> {
     pub is_focused: bool,
     pub col_widths: Vec<u16>,
     pub col_space: u16,
     pub spreadsheet: &'a Spreadsheet,
     pub highlights: Vec<Vec<SpreadsheetCell>>,
+    pub resize_guide: Option<usize>, // A column being resized, with a line at its right edge
+    pub stripe_style: Option<Style>, // Applied to every other row, under any other colors
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct FormulaCacheStats {
+    // How often rendering a formula found its value in the formula cache
+    pub hits: u64,
+    pub misses: u64,
+}
+
+impl FormulaCacheStats {
+    pub fn hit_rate(&self) -> f64 {
+        let lookups = self.hits + self.misses;
+        if lookups == 0 {
+            return 0.0;
+        }
+        self.hits as f64 / lookups as f64
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct FrameStats {
+    // How many visible cells the last frame had to render, rather than reusing the text from
+    // the frame before
+    pub rendered: u64,
+    pub reused: u64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ColResize {
+    // A column being resized by dragging the border after it in the header, which the app applies
+    // to the sheet as it changes
+    pub col: usize,
+    pub from: u16,
+    pub to: u16,
+    drag_start: u16, // Where the drag started, to measure how far it's gone
+    pub done: bool,  // Set when the mouse is released
 }
 
 #[derive(Debug, Default, Clone)]
 pub struct InfiniteTableState {
     pub active_cell: SpreadsheetCell,
     pub selection_end: SpreadsheetCell,
+    // Earlier ranges of a non-contiguous (Ctrl+Click) selection, not including the current one
+    pub extra_selections: Vec<[SpreadsheetCell; 2]>,
 
     vertical_scroll: u32,
     horizontal_scroll: u32,
+    // Rows pinned to the top (ie. headers), which the rest scroll beneath
+    frozen_rows: usize,
     pub formula_cache: HashMap<SpreadsheetCell, String>,
+    cache_stats: FormulaCacheStats,
+    // The text of each cell in the last frame and its column width then, which stays valid until
+    // the sheet's revision changes
+    rendered_cells: HashMap<SpreadsheetCell, (u16, String)>,
+    rendered_revision: u64,
+    frame_stats: FrameStats,
 
     visible_rows: [u32; 2],
     visible_cols: [u16; 2],
+    // The rows drawn in the last frame from the top down, which skip any hidden ones
+    shown_rows: Vec<usize>,
     cells: HashMap<SpreadsheetCell, Rect>,
+    // Where each visible column's right border is drawn in the header, with its width
+    col_borders: Vec<(u16, usize, u16)>,
+    pub col_resize: Option<ColResize>,
+    // A row whose outline control was clicked, for the app to expand or collapse its group
+    pub outline_click: Option<usize>,
 
     col_edges: [u32; 2],
 
+    max_rows: usize,
+    max_cols: usize,
+    hidden_rows: HashSet<usize>,
+    hidden_cols: HashSet<usize>,
+
     area: Rect,
 }
 
@@ -105,7 +238,13 @@ impl<'a> InfiniteTable<'a> {
 
         let mut render_x = 0;
         for col in 0..area.width {
-            let col_width = self.col_widths[col as usize] as i16;
+            if self.spreadsheet.is_col_hidden(col.into()) {
+                continue;
+            }
+            let Some(&col_width) = self.col_widths.get(col as usize) else {
+                break;
+            };
+            let col_width = col_width as i16;
             // Max renderable cols is the terminal width
             let start_x = render_x as i16 - state.horizontal_scroll as i16;
 
@@ -136,15 +275,31 @@ impl<'a> InfiniteTable<'a> {
         }
 
         // TODO: Row height, once implemented
-        for row in 1..area.height {
+        for (row, sheet_row) in state.shown_rows.iter().enumerate() {
             buf.set_string(
                 area.x,
-                area.y + row,
-                (row as u32 + state.vertical_scroll)
+                area.y + 1 + row as u16,
+                (sheet_row + 1)
                     .to_string()
                     .center(row_header_width as usize, ' '),
                 Style::new(),
             );
+            // Row groups are outlined in the gap before the data, with a control on the row
+            // each one collapses into
+            let marker = match self.spreadsheet.row_group_above(*sheet_row) {
+                Some(group) if group.collapsed => "+",
+                Some(_) => "-",
+                None if self.spreadsheet.row_outline_level(*sheet_row) > 0 => "│",
+                None => continue,
+            };
+            if row_header_gap > 0 {
+                buf.set_string(
+                    area.x + row_header_width,
+                    area.y + 1 + row as u16,
+                    marker,
+                    Style::new().fg(Color::DarkGray),
+                );
+            }
         }
     }
 
@@ -156,53 +311,110 @@ impl<'a> InfiniteTable<'a> {
     ) where
         Self: Sized,
     {
+        let scrolled_rows = state.vertical_scroll as usize..state.max_rows;
+        state.shown_rows = (0..state.frozen_rows.min(state.max_rows))
+            .chain(scrolled_rows)
+            .filter(|row| !state.hidden_rows.contains(row))
+            .take(area.height as usize)
+            .collect();
         state.visible_rows = [
             state.vertical_scroll as u32,
-            state.vertical_scroll + area.height as u32,
+            state
+                .shown_rows
+                .last()
+                .map_or(state.vertical_scroll, |row| *row as u32 + 1),
         ];
         state.visible_cols = [0, 0];
         state.cells.clear();
+        state.col_borders.clear();
 
         // NOTE TO SELF: There is very likely an issue where this will render into other cells that it shouldn't.
         // This will be addressed eventually.
 
+        let selections = state.selections();
+
+        // Only cells that were visible last frame are kept, so this never outgrows the screen
+        let mut previous_cells = std::mem::take(&mut state.rendered_cells);
+        if state.rendered_revision != self.spreadsheet.revision() {
+            previous_cells.clear();
+            state.rendered_revision = self.spreadsheet.revision();
+        }
+        state.frame_stats = FrameStats::default();
+
         // TODO: Row height, once implemented
-        for row in 0..area.height {
+        for (row, sheet_row) in state.shown_rows.clone().into_iter().enumerate() {
+            let row = row as u16;
             let mut render_x = 0;
             for col in 0..area.width {
-                let col_width = self.col_widths[col as usize] as i16;
+                if self.spreadsheet.is_col_hidden(col.into()) {
+                    continue;
+                }
+                let Some(&col_width) = self.col_widths.get(col as usize) else {
+                    break;
+                };
+                let col_width = col_width as i16;
                 // Max renderable cols is the terminal width
                 let start_x = render_x as i16 - state.horizontal_scroll as i16;
 
                 let cell = SpreadsheetCell {
-                    row: (row as u32 + state.vertical_scroll) as usize,
+                    row: sheet_row,
                     col: col.into(),
                 };
-                let text = render_cell(
-                    &cell,
-                    col_width as usize,
-                    2,
-                    &self.spreadsheet,
-                    &mut state.formula_cache,
-                );
+                let text = match previous_cells.remove(&cell) {
+                    Some((width, text)) if width == col_width as u16 => {
+                        state.frame_stats.reused += 1;
+                        text
+                    }
+                    _ => {
+                        state.frame_stats.rendered += 1;
+                        render_cell(
+                            &cell,
+                            col_width as usize,
+                            2,
+                            self.spreadsheet,
+                            &mut state.formula_cache,
+                            &mut state.cache_stats,
+                        )
+                    }
+                };
+                state
+                    .rendered_cells
+                    .insert(cell.clone(), (col_width as u16, text.clone()));
 
-                let mut cell_style = Style::new();
+                let mut cell_style = match self.stripe_style {
+                    Some(stripe_style) if sheet_row % 2 == 1 => stripe_style,
+                    _ => Style::new(),
+                };
+                let text_style = self.spreadsheet.get_text_style(&cell);
+                if text_style.bold || cell.row < self.spreadsheet.header_rows() {
+                    cell_style = cell_style.add_modifier(Modifier::BOLD);
+                }
+                if text_style.italic {
+                    cell_style = cell_style.add_modifier(Modifier::ITALIC);
+                }
+                if text_style.underline {
+                    cell_style = cell_style.add_modifier(Modifier::UNDERLINED);
+                }
+                // Chosen colors sit below the selection and highlight colors
+                let colors = self.spreadsheet.get_colors(&cell);
+                if let Some(fg) = colors.fg {
+                    cell_style = cell_style.fg(to_color(fg));
+                }
+                if let Some(bg) = colors.bg {
+                    cell_style = cell_style.bg(to_color(bg));
+                }
 
                 // Test if cell is inside selection
-                let min_row = min(state.selection_end.row, state.active_cell.row);
-                let min_col = min(state.selection_end.col, state.active_cell.col);
-                let max_row = max(state.selection_end.row, state.active_cell.row);
-                let max_col = max(state.selection_end.col, state.active_cell.col);
-
-                if cell.col >= min_col
-                    && cell.col <= max_col
-                    && cell.row >= min_row
-                    && cell.row <= max_row
-                {
-                    // TODO: If in selection
-                    cell_style = cell_style.bg(Color::DarkGray).fg(Color::Black);
+                if selections.iter().any(|[start, end]| {
+                    cell.col >= start.col
+                        && cell.col <= end.col
+                        && cell.row >= start.row
+                        && cell.row <= end.row
+                }) {
+                    // Kept dimmer than the active cell, which stays distinguishable within it
+                    cell_style = cell_style.bg(Color::DarkGray).fg(Color::White);
                     if !self.is_focused {
-                        cell_style = cell_style.bg(Color::Gray);
+                        cell_style = cell_style.fg(Color::Gray);
                     }
                 }
 
@@ -243,7 +455,8 @@ impl<'a> InfiniteTable<'a> {
                         );
                     } else {
                         state.visible_cols[0] = col;
-                        let sliced_text = text[start_x.unsigned_abs() as usize..].to_string();
+                        let sliced_text: String =
+                            text.chars().skip(start_x.unsigned_abs() as usize).collect();
                         buf.set_string(area.x, area.y + row, sliced_text, cell_style);
                         state.cells.insert(
                             cell,
@@ -256,37 +469,65 @@ impl<'a> InfiniteTable<'a> {
                         );
                     }
                 }
+                // The guide goes in the gap after the column, where its edge moves while resizing
+                let guide_x = start_x + col_width;
+                // Stripes run through the gaps between cells too, so rows read as one band
+                if let Some(stripe_style) = self.stripe_style.filter(|_| sheet_row % 2 == 1) {
+                    if (0..area.width as i16).contains(&guide_x) {
+                        let gap_width = min(self.col_space, area.width - guide_x as u16);
+                        buf.set_style(
+                            Rect::new(area.x + guide_x as u16, area.y + row, gap_width, 1),
+                            stripe_style,
+                        );
+                    }
+                }
+                if row == 0 && (0..area.width as i16).contains(&guide_x) {
+                    state
+                        .col_borders
+                        .push((area.x + guide_x as u16, col.into(), col_width as u16));
+                }
+                if self.resize_guide == Some(col.into())
+                    && (0..area.width as i16).contains(&guide_x)
+                {
+                    buf.set_string(
+                        area.x + guide_x as u16,
+                        area.y + row,
+                        "│",
+                        Style::new().fg(Color::Yellow),
+                    );
+                }
                 render_x += col_width + self.col_space as i16;
             }
         }
 
-        let a = self.col_widths[..=state.visible_cols[1] as usize + 1]
-            .iter()
-            .map(|c| (c + self.col_space) as u32)
-            .sum::<u32>();
-        // println!(
-        //     "a{} b{} c{} d{}",
-        //     a,
-        //     area.width,
-        //     self.col_space,
-        //     a - area.width as u32 - self.col_space as u32
-        // );
+        // Hidden columns take up no space at all
+        let shown_width = |(col, width): (usize, &u16)| {
+            if self.spreadsheet.is_col_hidden(col) {
+                0
+            } else {
+                (width + self.col_space) as u32
+            }
+        };
         state.col_edges = [
             if state.visible_cols[0] == 0 {
                 0
             } else {
-                self.col_widths[1..state.visible_cols[0] as usize]
+                self.col_widths
                     .iter()
-                    .map(|c| (c + self.col_space) as u32)
+                    .enumerate()
+                    .take(state.visible_cols[0] as usize)
+                    .skip(1)
+                    .map(shown_width)
                     .sum::<u32>()
             },
             // TODO: Bug here when you scroll a large amount and I haven't a clue why
-            self.col_widths[..=state.visible_cols[1] as usize + 1]
+            self.col_widths
                 .iter()
-                .map(|c| (c + self.col_space) as u32)
+                .enumerate()
+                .take(state.visible_cols[1] as usize + 2)
+                .map(shown_width)
                 .sum::<u32>()
-                - area.width as u32
-                - self.col_space as u32,
+                .saturating_sub(area.width as u32 + self.col_space as u32),
         ];
 
         state.area = area;
@@ -299,6 +540,11 @@ impl<'a> StatefulWidget for InfiniteTable<'a> {
     fn render(self, area: Rect, buf: &mut Buffer, state: &mut Self::State) {
         let row_header_width = 3;
         let row_header_gap = 1;
+        state.set_limits(self.spreadsheet.max_rows(), self.spreadsheet.max_cols());
+        state.set_hidden(
+            self.spreadsheet.hidden_rows(),
+            self.spreadsheet.hidden_cols().clone(),
+        );
 
         self.render_data(
             Rect {
@@ -315,61 +561,139 @@ impl<'a> StatefulWidget for InfiniteTable<'a> {
 }
 
 impl InfiniteTableState {
+    pub fn cache_stats(&self) -> FormulaCacheStats {
+        self.cache_stats
+    }
+
+    pub fn frame_stats(&self) -> FrameStats {
+        self.frame_stats
+    }
+
     pub fn handle_event(&mut self, event: &Event) {
         match event {
+            // A resize keeps following the mouse wherever it goes, until it's released
+            Event::Mouse(mouse_event) if self.col_resize.is_some_and(|r| !r.done) => {
+                let Some(resize) = &mut self.col_resize else {
+                    return;
+                };
+                match mouse_event.kind {
+                    MouseEventKind::Drag(_) => {
+                        resize.to =
+                            dragged_width(resize.from, resize.drag_start, mouse_event.column)
+                    }
+                    MouseEventKind::Up(_) => resize.done = true,
+                    _ => (),
+                }
+            }
+            Event::Mouse(mouse_event)
+                if matches!(mouse_event.kind, MouseEventKind::Down(_))
+                    && self
+                        .border_at(mouse_event.column, mouse_event.row)
+                        .is_some() =>
+            {
+                let (col, width) = self
+                    .border_at(mouse_event.column, mouse_event.row)
+                    .unwrap_or_default();
+                self.col_resize = Some(ColResize {
+                    col,
+                    from: width,
+                    to: width,
+                    drag_start: mouse_event.column,
+                    done: false,
+                });
+            }
+            Event::Mouse(mouse_event)
+                if matches!(mouse_event.kind, MouseEventKind::Down(_))
+                    && mouse_event.column + 1 == self.area.x
+                    && self.row_at(mouse_event.row).is_some() =>
+            {
+                self.outline_click = self.row_at(mouse_event.row);
+            }
             Event::Mouse(mouse_event)
                 if self.area.contains(Position {
                     x: mouse_event.column,
                     y: mouse_event.row,
                 }) =>
             {
-                match mouse_event.kind {
-                    MouseEventKind::ScrollDown => {
-                        self.vertical_scroll += 1;
-                    }
-                    MouseEventKind::ScrollUp => {
-                        if self.vertical_scroll >= 1 {
-                            self.vertical_scroll -= 1;
-                        }
-                    }
-                    MouseEventKind::ScrollRight => {
-                        self.horizontal_scroll += 1;
-                    }
-                    MouseEventKind::ScrollLeft => {
-                        if self.horizontal_scroll >= 1 {
-                            self.horizontal_scroll -= 1;
-                        }
-                    }
-                    MouseEventKind::Down(_) => {
-                        // TODO: Handle other mouse buttons (certainly needed here)
-                        for (cell, rect) in self.cells.iter() {
-                            if rect.contains(Position {
-                                x: mouse_event.column,
-                                y: mouse_event.row,
-                            }) {
-                                self.active_cell = cell.clone();
-                                self.selection_end = cell.clone();
-                            }
-                        }
-                    }
-                    MouseEventKind::Drag(_) => {
-                        // TODO: Handle other mouse buttons (certainly needed here)
-                        for (cell, rect) in self.cells.iter() {
-                            if rect.contains(Position {
-                                x: mouse_event.column,
-                                y: mouse_event.row,
-                            }) {
-                                self.selection_end = cell.clone();
-                            }
-                        }
-                    }
-                    _ => (),
+                self.handle_table_mouse(mouse_event)
+            }
+            _ => (),
+        }
+    }
+
+    fn handle_table_mouse(&mut self, mouse_event: &MouseEvent) {
+        match mouse_event.kind {
+            MouseEventKind::ScrollDown => {
+                self.vertical_scroll += 1;
+            }
+            MouseEventKind::ScrollUp if self.vertical_scroll as usize > self.frozen_rows => {
+                self.vertical_scroll -= 1;
+            }
+            MouseEventKind::ScrollRight => {
+                self.horizontal_scroll += 1;
+            }
+            MouseEventKind::ScrollLeft if self.horizontal_scroll >= 1 => {
+                self.horizontal_scroll -= 1;
+            }
+            MouseEventKind::Down(_) => {
+                // TODO: Handle other mouse buttons (certainly needed here)
+                let Some(cell) = self.cell_at(mouse_event.column, mouse_event.row) else {
+                    return;
+                };
+                if mouse_event.modifiers.contains(KeyModifiers::SHIFT) {
+                    // Extend from the anchor (the active cell), like Shift+arrow does
+                    self.selection_end = cell;
+                    return;
+                }
+                if mouse_event.modifiers.contains(KeyModifiers::CONTROL) {
+                    // Keep the current range and start a new one
+                    self.extra_selections
+                        .push([self.active_cell.clone(), self.selection_end.clone()]);
+                } else {
+                    self.extra_selections.clear();
+                }
+                // A click collapses the selection, so the clicked cell is the new anchor
+                self.active_cell = cell.clone();
+                self.selection_end = cell;
+            }
+            MouseEventKind::Drag(_) => {
+                // TODO: Handle other mouse buttons (certainly needed here)
+                if let Some(cell) = self.cell_at(mouse_event.column, mouse_event.row) {
+                    self.selection_end = cell;
                 }
             }
             _ => (),
         }
     }
 
+    fn border_at(&self, x: u16, y: u16) -> Option<(usize, u16)> {
+        // The column whose right border in the header is at a position, and its width
+        if y + 1 != self.area.y {
+            return None;
+        }
+        self.col_borders
+            .iter()
+            .find(|(border_x, _, _)| *border_x == x)
+            .map(|(_, col, width)| (*col, *width))
+    }
+
+    fn row_at(&self, y: u16) -> Option<usize> {
+        let idx = y.checked_sub(self.area.y)?;
+        self.shown_rows.get(idx as usize).copied()
+    }
+
+    pub fn cell_area(&self, cell: &SpreadsheetCell) -> Option<Rect> {
+        // Where a cell was drawn in the last frame, if it was
+        self.cells.get(cell).copied()
+    }
+
+    fn cell_at(&self, x: u16, y: u16) -> Option<SpreadsheetCell> {
+        self.cells
+            .iter()
+            .find(|(_, rect)| rect.contains(Position { x, y }))
+            .map(|(cell, _)| cell.clone())
+    }
+
     pub fn move_active_cell(&mut self, x: i32, y: i32, group: bool) {
         // TODO: When a box is selected, make this navigate through the box.
         let mut cell = if group {
@@ -378,16 +702,23 @@ impl InfiniteTableState {
             self.active_cell.clone()
         };
 
+        // Each step goes to the next shown column or row, passing over hidden ones
         let mut dx = x;
-        while dx > 0 && cell.col < SPREADSHEET_MAX_COLS {
-            cell.col += 1;
+        while dx > 0 {
+            let Some(col) = next_shown(cell.col, true, self.max_cols, &self.hidden_cols) else {
+                break;
+            };
+            cell.col = col;
             dx -= 1;
             if self.visible_cols[1] <= cell.col as u16 {
                 self.horizontal_scroll = self.col_edges[1];
             }
         }
-        while dx < 0 && cell.col > 0 {
-            cell.col -= 1;
+        while dx < 0 {
+            let Some(col) = next_shown(cell.col, false, self.max_cols, &self.hidden_cols) else {
+                break;
+            };
+            cell.col = col;
             dx += 1;
             if self.visible_cols[0] >= cell.col as u16 {
                 self.horizontal_scroll = self.col_edges[0];
@@ -395,43 +726,260 @@ impl InfiniteTableState {
         }
 
         let mut dy = y;
-        while dy > 0 && cell.row < SPREADSHEET_MAX_ROWS {
-            cell.row += 1;
+        while dy > 0 {
+            let Some(row) = next_shown(cell.row, true, self.max_rows, &self.hidden_rows) else {
+                break;
+            };
+            cell.row = row;
             dy -= 1;
             if self.visible_rows[1] <= cell.row as u32 {
                 // TODO: Scroll by row height, once implemented.
-                self.vertical_scroll += 1;
+                self.vertical_scroll = next_shown(
+                    self.vertical_scroll as usize,
+                    true,
+                    self.max_rows,
+                    &self.hidden_rows,
+                )
+                .unwrap_or(cell.row) as u32;
+            } else if cell.row >= self.frozen_rows && (cell.row as u32) < self.vertical_scroll {
+                // Leaving the frozen rows goes back to the top of the rest
+                self.vertical_scroll = cell.row as u32;
             }
         }
-        while dy < 0 && cell.row > 0 {
-            cell.row -= 1;
+        while dy < 0 {
+            let Some(row) = next_shown(cell.row, false, self.max_rows, &self.hidden_rows) else {
+                break;
+            };
+            cell.row = row;
             dy += 1;
-            if self.visible_rows[0] > cell.row as u32 {
+            if self.visible_rows[0] > cell.row as u32 && cell.row >= self.frozen_rows {
                 // TODO: Scroll by row height, once implemented.
-                self.vertical_scroll -= 1;
+                self.vertical_scroll = cell.row as u32;
             }
         }
         if !group {
-            self.active_cell = cell.clone()
+            self.active_cell = cell.clone();
+            self.extra_selections.clear();
         }
         self.selection_end = cell
     }
 
+    pub fn set_limits(&mut self, max_rows: usize, max_cols: usize) {
+        // Mirrors the spreadsheet's size limits, which navigation can't go past
+        self.max_rows = max_rows;
+        self.max_cols = max_cols;
+    }
+
+    pub fn set_hidden(&mut self, rows: HashSet<usize>, cols: HashSet<usize>) {
+        // Mirrors the spreadsheet's hidden rows and columns, moving the active cell off them if
+        // its own were just hidden
+        self.hidden_rows = rows;
+        self.hidden_cols = cols;
+        let mut cell = self.active_cell.clone();
+        if self.hidden_cols.contains(&cell.col) {
+            cell.col = next_shown(cell.col, true, self.max_cols, &self.hidden_cols)
+                .or(next_shown(
+                    cell.col,
+                    false,
+                    self.max_cols,
+                    &self.hidden_cols,
+                ))
+                .unwrap_or(cell.col);
+        }
+        if self.hidden_rows.contains(&cell.row) {
+            cell.row = next_shown(cell.row, true, self.max_rows, &self.hidden_rows)
+                .or(next_shown(
+                    cell.row,
+                    false,
+                    self.max_rows,
+                    &self.hidden_rows,
+                ))
+                .unwrap_or(cell.row);
+        }
+        if cell != self.active_cell {
+            self.active_cell = cell.clone();
+            self.selection_end = cell;
+            self.extra_selections.clear();
+        }
+    }
+
+    pub fn move_active_cell_to(&mut self, cell: &SpreadsheetCell, group: bool) {
+        let from = if group {
+            &self.selection_end
+        } else {
+            &self.active_cell
+        };
+        let dx = shown_steps(from.col, cell.col, &self.hidden_cols);
+        let dy = shown_steps(from.row, cell.row, &self.hidden_rows);
+        self.move_active_cell(dx, dy, group);
+    }
+
+    pub fn view_state(&self) -> Sidecar {
+        Sidecar {
+            active_cell: self.active_cell.clone(),
+            vertical_scroll: self.vertical_scroll,
+            horizontal_scroll: self.horizontal_scroll,
+            ..Default::default()
+        }
+    }
+
+    pub fn restore_view_state(&mut self, view: &Sidecar, col_widths: &[u16]) {
+        // Clamped, since the sidecar may have been edited or written for a different sheet.
+        // Neither scroll goes past the active cell, so it's always in view.
+        self.active_cell = SpreadsheetCell {
+            row: min(view.active_cell.row, self.max_rows.saturating_sub(1)),
+            col: min(view.active_cell.col, self.max_cols.saturating_sub(1)),
+        };
+        self.selection_end = self.active_cell.clone();
+        self.vertical_scroll =
+            min(view.vertical_scroll, self.active_cell.row as u32).max(self.frozen_rows as u32);
+        // Where the active column starts, with the one character gap after each column
+        let active_x = col_widths
+            .iter()
+            .enumerate()
+            .take(self.active_cell.col)
+            .filter(|(col, _)| !self.hidden_cols.contains(col))
+            .map(|(_, width)| *width as u32 + 1)
+            .sum();
+        self.horizontal_scroll = min(view.horizontal_scroll, active_x);
+    }
+
+    pub fn frozen_rows(&self) -> usize {
+        self.frozen_rows
+    }
+
+    pub fn set_frozen_rows(&mut self, rows: usize) {
+        self.frozen_rows = rows;
+        self.vertical_scroll = self.vertical_scroll.max(rows as u32);
+    }
+
+    pub fn selections(&self) -> Vec<[SpreadsheetCell; 2]> {
+        // Every range in the selection, each as [top left, bottom right]
+        let mut selections: Vec<[SpreadsheetCell; 2]> = self
+            .extra_selections
+            .iter()
+            .map(|[a, b]| normalize_range(a, b))
+            .collect();
+        selections.push(self.selection());
+        selections
+    }
+
     pub fn selection(&self) -> [SpreadsheetCell; 2] {
-        let min_row = *min(&self.selection_end.row, &self.active_cell.row);
-        let min_col = *min(&self.selection_end.col, &self.active_cell.col);
-        let max_row = *max(&self.selection_end.row, &self.active_cell.row);
-        let max_col = *max(&self.selection_end.col, &self.active_cell.col);
-
-        return [
-            SpreadsheetCell {
-                row: min_row,
-                col: min_col,
-            },
-            SpreadsheetCell {
-                row: max_row,
-                col: max_col,
-            },
-        ];
+        normalize_range(&self.active_cell, &self.selection_end)
+    }
+}
+
+fn dragged_width(from: u16, drag_start: u16, x: u16) -> u16 {
+    (from as i32 + x as i32 - drag_start as i32).clamp(MIN_COL_WIDTH as i32, u16::MAX as i32) as u16
+}
+
+fn next_shown(index: usize, forward: bool, limit: usize, hidden: &HashSet<usize>) -> Option<usize> {
+    // The closest column or row in a direction that isn't hidden
+    let mut index = index;
+    loop {
+        index = if forward {
+            index + 1
+        } else {
+            index.checked_sub(1)?
+        };
+        if index >= limit {
+            return None;
+        }
+        if !hidden.contains(&index) {
+            return Some(index);
+        }
+    }
+}
+
+fn shown_steps(from: usize, to: usize, hidden: &HashSet<usize>) -> i32 {
+    // How many steps it takes to go between columns or rows, as hidden ones are skipped
+    let (low, high) = (min(from, to), max(from, to));
+    let skipped = hidden.iter().filter(|&&i| i > low && i <= high).count();
+    let steps = (high - low - skipped) as i32;
+    if to >= from {
+        steps
+    } else {
+        -steps
+    }
+}
+
+fn normalize_range(a: &SpreadsheetCell, b: &SpreadsheetCell) -> [SpreadsheetCell; 2] {
+    [
+        SpreadsheetCell {
+            row: min(a.row, b.row),
+            col: min(a.col, b.col),
+        },
+        SpreadsheetCell {
+            row: max(a.row, b.row),
+            col: max(a.col, b.col),
+        },
+    ]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn restored_scrolls_keep_the_active_cell_in_view() {
+        let mut state = InfiniteTableState::default();
+        state.set_limits(100, 10);
+        let view = Sidecar {
+            active_cell: SpreadsheetCell { row: 500, col: 2 },
+            vertical_scroll: 1000,
+            horizontal_scroll: 1000,
+            ..Default::default()
+        };
+        state.restore_view_state(&view, &[10, 5, 10, 10]);
+        assert_eq!(state.active_cell, SpreadsheetCell { row: 99, col: 2 });
+        assert_eq!(state.view_state().vertical_scroll, 99);
+        assert_eq!(state.view_state().horizontal_scroll, 17);
+
+        // Scrolls that already show the active cell are kept
+        let view = Sidecar {
+            vertical_scroll: 40,
+            horizontal_scroll: 4,
+            ..view
+        };
+        state.restore_view_state(&view, &[10, 5, 10, 10]);
+        assert_eq!(state.view_state().vertical_scroll, 40);
+        assert_eq!(state.view_state().horizontal_scroll, 4);
+    }
+
+    fn render(text: &str) -> String {
+        let spreadsheet = Spreadsheet::from_text(text, ',');
+        let cell = SpreadsheetCell { row: 0, col: 0 };
+        let (mut cache, mut stats) = (HashMap::new(), FormulaCacheStats::default());
+        render_cell(&cell, 10, 2, &spreadsheet, &mut cache, &mut stats)
+            .trim()
+            .to_string()
+    }
+
+    #[test]
+    fn odd_numbers_render_cleanly() {
+        assert_eq!(render("-0"), "0.00");
+        assert_eq!(render("-0.001"), "0.00");
+        assert_eq!(render("=-0.001"), "0.00");
+        // Typed NaN and infinities (even as too big a number) are text, while formulas giving
+        // them are errors
+        assert_eq!(render("NaN"), "NaN");
+        assert_eq!(render("inf"), "inf");
+        assert_eq!(render("1e400"), "1e400");
+        assert_eq!(render("=SQRT(-1)"), "#NUM!");
+        assert_eq!(render("=10^400"), "#NUM!");
+    }
+
+    #[test]
+    fn movement_stops_at_smaller_limits() {
+        let spreadsheet = Spreadsheet::new().with_limits(10, 3);
+        let mut state = InfiniteTableState::default();
+        state.set_limits(spreadsheet.max_rows(), spreadsheet.max_cols());
+        state.move_active_cell(100, 100, false);
+        assert_eq!(state.active_cell, SpreadsheetCell { row: 9, col: 2 });
+
+        state.move_active_cell_to(&SpreadsheetCell { row: 50, col: 50 }, false);
+        assert_eq!(state.active_cell, SpreadsheetCell { row: 9, col: 2 });
+        state.move_active_cell(-1, -1, false);
+        assert_eq!(state.active_cell, SpreadsheetCell { row: 8, col: 1 });
     }
 }
@@ -52,7 +52,7 @@ impl TextInputState {
             Event::Key(key_event) if key_event.kind == KeyEventKind::Press => {
                 match key_event.code {
                     KeyCode::Right => {
-                        if self.selection[1] < self.value.len() {
+                        if self.selection[1] < self.char_count() {
                             self.selection[1] += 1;
                         }
                         if !key_event.modifiers.contains(KeyModifiers::SHIFT) {
@@ -70,8 +70,8 @@ impl TextInputState {
                     KeyCode::Backspace => {
                         if self.selection[0] != self.selection[1] {
                             // Delete the selected text
-                            self.value = self.value[..self.sel_min()].to_string()
-                                + &self.value[self.sel_max()..];
+                            self.value = self.value[..self.byte_offset(self.sel_min())].to_string()
+                                + &self.value[self.byte_offset(self.sel_max())..];
                             self.set_cursor(self.sel_min());
                         } else {
                             // Delete the character before the cursor
@@ -90,11 +90,11 @@ impl TextInputState {
                     }
                     KeyCode::Char(c) => {
                         if self.selection[0] != self.selection[1] {
-                            self.value = self.value[..self.sel_min()].to_string()
-                                + &self.value[self.sel_max()..];
+                            self.value = self.value[..self.byte_offset(self.sel_min())].to_string()
+                                + &self.value[self.byte_offset(self.sel_max())..];
                             self.set_cursor(self.sel_min());
                         }
-                        self.value.insert(self.selection[1], c);
+                        self.value.insert(self.byte_offset(self.selection[1]), c);
                         self.set_cursor(self.selection[1] + 1);
                     }
                     _ => (),
@@ -111,8 +111,8 @@ impl TextInputState {
 
                     // Handle single clicks
                     let input_x = mouse_event.column - self.area.x;
-                    if self.value.len() < input_x.into() {
-                        self.set_cursor(self.value.len());
+                    if self.char_count() < input_x.into() {
+                        self.set_cursor(self.char_count());
                     } else {
                         self.set_cursor(input_x.into());
                     }
@@ -124,8 +124,8 @@ impl TextInputState {
                     }) =>
                 {
                     let input_x = mouse_event.column - self.area.x;
-                    if self.value.len() < input_x.into() {
-                        self.selection[1] = self.value.len()
+                    if self.char_count() < input_x.into() {
+                        self.selection[1] = self.char_count()
                     } else {
                         self.selection[1] = input_x.into()
                     }
@@ -153,6 +153,19 @@ impl TextInputState {
         self.selection[1] = x;
     }
 
+    pub fn char_count(&self) -> usize {
+        // The cursor and selection count characters rather than bytes, so they never land inside
+        // one like é
+        self.value.chars().count()
+    }
+
+    fn byte_offset(&self, idx: usize) -> usize {
+        self.value
+            .char_indices()
+            .nth(idx)
+            .map_or(self.value.len(), |(offset, _)| offset)
+    }
+
     fn sel_min(&self) -> usize {
         *self.selection.iter().min().unwrap()
     }
@@ -162,53 +175,85 @@ impl TextInputState {
     }
 
     pub fn get_word_bounds(&self) -> Option<[usize; 2]> {
-        let mut idx = self.cursor();
-        let mut bounds: [usize; 2] = [0, 0];
-
-        if self.value.len() == 0 {
+        // The run of alphanumeric characters touching the cursor, as [start, end)
+        if self.value.is_empty() {
             return None;
         }
+        let chars: Vec<char> = self.value.chars().collect();
+        let cursor = self.cursor().min(chars.len());
 
-        while let Some(char) = self.value.chars().nth(idx - 1) {
-            if char.is_ascii_alphanumeric() {
-                idx -= 1;
-                bounds[0] = idx;
-            } else {
-                break;
-            }
+        let mut start = cursor;
+        while start > 0 && chars[start - 1].is_ascii_alphanumeric() {
+            start -= 1;
         }
-
-        idx = self.cursor();
-        while let Some(char) = self.value.chars().nth(idx - 1) {
-            if char.is_ascii_alphanumeric() {
-                bounds[1] = idx;
-                idx += 1;
-            } else {
-                break;
-            }
+        let mut end = cursor;
+        while end < chars.len() && chars[end].is_ascii_alphanumeric() {
+            end += 1;
         }
 
-        return Some(bounds);
+        Some([start, end])
     }
 
     pub fn get_word(&self) -> Option<String> {
-        if let Some(bounds) = self.get_word_bounds() {
-            Some(self.value[bounds[0]..bounds[1]].to_string())
-        } else {
-            None
-        }
+        let [start, end] = self.get_word_bounds()?;
+        Some(self.value[self.byte_offset(start)..self.byte_offset(end)].to_string())
     }
 
     pub fn set_word(&mut self, word: &str) {
-        println!("here");
-        if let Some(bounds) = self.get_word_bounds() {
-            println!("\n\n\n{:?}'{}'", bounds, self.value);
-            self.value = self.value[..bounds[0]].to_string() + &self.value[bounds[1]..];
-            self.value.insert_str(bounds[0], word);
-            self.set_cursor(bounds[0] + word.len());
-        } else {
-            self.value.insert_str(self.cursor(), word);
-            self.set_cursor(self.cursor() + word.len());
+        let [start, end] = self
+            .get_word_bounds()
+            .unwrap_or([self.cursor(), self.cursor()]);
+        let (start_byte, end_byte) = (self.byte_offset(start), self.byte_offset(end));
+        self.value.replace_range(start_byte..end_byte, word);
+        self.set_cursor(start + word.chars().count());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ratatui::crossterm::event::KeyEvent;
+
+    fn input(value: &str, cursor: usize) -> TextInputState {
+        let mut state = TextInputState::default();
+        state.set_value(value.to_string());
+        state.set_cursor(cursor);
+        state
+    }
+
+    #[test]
+    fn words_at_the_start_and_in_empty_input() {
+        let state = input("SUM", 0);
+        assert_eq!(state.get_word_bounds(), Some([0, 3]));
+        assert_eq!(state.get_word(), Some(String::from("SUM")));
+
+        let state = input("", 0);
+        assert_eq!(state.get_word_bounds(), None);
+        assert_eq!(state.get_word(), None);
+    }
+
+    #[test]
+    fn words_after_multibyte_characters() {
+        let mut state = input("=\"é\"&SU", 7);
+        assert_eq!(state.get_word_bounds(), Some([5, 7]));
+        assert_eq!(state.get_word(), Some(String::from("SU")));
+
+        state.set_word("SUM(");
+        assert_eq!(state.value(), "=\"é\"&SUM(");
+        assert_eq!(state.cursor(), 9);
+    }
+
+    #[test]
+    fn typing_after_multibyte_characters() {
+        let mut state = input("", 0);
+        for c in "é€x".chars() {
+            state.handle_event(&Event::Key(KeyEvent::from(KeyCode::Char(c))));
         }
+        assert_eq!(state.value(), "é€x");
+        assert_eq!(state.cursor(), 3);
+
+        state.handle_event(&Event::Key(KeyEvent::from(KeyCode::Left)));
+        state.handle_event(&Event::Key(KeyEvent::from(KeyCode::Backspace)));
+        assert_eq!(state.value(), "éx");
     }
 }
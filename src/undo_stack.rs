@@ -1,7 +1,5 @@
 use std::fmt::{Debug, Display};
 
-use crate::undo_stack;
-
 #[derive(Debug)]
 pub struct UndoStack<T: Clone> {
     undo: Vec<T>,
@@ -51,6 +49,16 @@ where
         None
     }
 
+    pub fn depth(&self) -> usize {
+        // How many edits can be undone
+        self.undo.len()
+    }
+
+    pub fn entries(&self) -> impl Iterator<Item = &T> {
+        // Every edit still kept, whether it can be undone or redone
+        self.undo.iter().chain(self.redo.iter())
+    }
+
     pub fn edit(&mut self, edit: T) {
         if let Some(last) = self.undo.last() {
             if last == &edit {
@@ -1,84 +1,43 @@
-use std::{
-    collections::{BTreeMap, HashMap, HashSet},
-    hash::Hash,
-    iter::zip,
-};
-
-trait Memoizable {
-    type Args;
-    type Result;
-
-    fn call(&self, args: Self::Args) -> Self::Result;
-}
-
-struct Memoizer<F>
-where
-    F: Memoizable,
-{
-    cache: HashMap<F::Args, F::Result>,
-    func: F,
-}
-
-impl<F> Memoizer<F>
-where
-    F: Memoizable,
-    F::Args: Eq + Hash + Clone,
-    F::Result: Clone,
-{
-    fn new(func: F) -> Self {
-        Memoizer {
-            cache: HashMap::new(),
-            func,
-        }
-    }
-
-    fn call(&mut self, args: F::Args) -> F::Result {
-        if let Some(result) = self.cache.get(&args) {
-            return result.clone();
-        }
-        let result = self.func.call(args.clone());
-        self.cache.insert(args.clone(), result.clone());
-        return result;
-    }
-}
+use std::iter::zip;
 
 pub trait StringPadding {
     fn left_pad(&self, length: usize, pad_char: char) -> String;
     fn right_pad(&self, length: usize, pad_char: char) -> String;
     fn center(&self, length: usize, pad_char: char) -> String;
+    fn ellipsize(&self, length: usize) -> String;
 }
 
 impl StringPadding for String {
     fn left_pad(&self, length: usize, pad_char: char) -> String {
-        if self.len() >= length {
+        if self.chars().count() >= length {
             return self.clone();
         }
         let mut working = self.clone();
-        while working.len() < length {
+        while working.chars().count() < length {
             working.insert(0, pad_char);
         }
         working
     }
 
     fn right_pad(&self, length: usize, pad_char: char) -> String {
-        if self.len() >= length {
+        if self.chars().count() >= length {
             return self.clone();
         }
         let mut working = self.clone();
-        while working.len() < length {
+        while working.chars().count() < length {
             working.push(pad_char);
         }
         working
     }
 
     fn center(&self, length: usize, pad_char: char) -> String {
-        if self.len() >= length {
+        if self.chars().count() >= length {
             return self.clone();
         }
         let mut working = self.clone();
-        while working.len() < length {
+        while working.chars().count() < length {
             // Alternate adding to the start and the end
-            if working.len() % 2 == 0 {
+            if working.chars().count().is_multiple_of(2) {
                 working.insert(0, pad_char);
             } else {
                 working.push(pad_char);
@@ -86,6 +45,17 @@ impl StringPadding for String {
         }
         working
     }
+
+    fn ellipsize(&self, length: usize) -> String {
+        // Cuts the string down to a length, ending it with an ellipsis if anything was cut
+        if self.chars().count() <= length {
+            return self.clone();
+        }
+        if length == 0 {
+            return String::new();
+        }
+        self.chars().take(length - 1).chain(['…']).collect()
+    }
 }
 
 pub trait LevenshteinDistance {
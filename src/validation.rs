@@ -0,0 +1,105 @@
+use crate::formulas::cell_to_token;
+use crate::references::parse_reference;
+use crate::spreadsheet::{Spreadsheet, SpreadsheetCell};
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ListSource {
+    // Values given with the rule itself, ie. Yes,No
+    Values(Vec<String>),
+    // Values read from cells, which keep up with changes to them
+    Range([SpreadsheetCell; 2]),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Validation {
+    // Only values from a list, like Excel's list validation with its dropdown
+    List(ListSource),
+}
+
+impl Validation {
+    pub fn parse_list(source: &str) -> Result<Validation, String> {
+        // A range like A1:A5, or otherwise values separated by commas
+        let source = source.trim();
+        if let Some((start, end)) = source.split_once(':') {
+            let references = (
+                parse_reference(&start.replace('$', "").to_uppercase()),
+                parse_reference(&end.replace('$', "").to_uppercase()),
+            );
+            if let (Some(start), Some(end)) = references {
+                if !start.is_cell() || !end.is_cell() {
+                    return Err("List ranges need both corners, ie. A1:A5".to_string());
+                }
+                let (start, end) = (start.get_cell(), end.get_cell());
+                return Ok(Validation::List(ListSource::Range([
+                    SpreadsheetCell {
+                        row: start.row.min(end.row),
+                        col: start.col.min(end.col),
+                    },
+                    SpreadsheetCell {
+                        row: start.row.max(end.row),
+                        col: start.col.max(end.col),
+                    },
+                ])));
+            }
+        }
+        let values: Vec<String> = source
+            .split(',')
+            .map(|value| value.trim().to_string())
+            .filter(|value| !value.is_empty())
+            .collect();
+        if values.is_empty() {
+            return Err("Lists need values, ie. Yes,No or A1:A5".to_string());
+        }
+        Ok(Validation::List(ListSource::Values(values)))
+    }
+
+    pub fn allowed_values(&self, spreadsheet: &Spreadsheet) -> Vec<String> {
+        // The values a cell can take, in the order they're offered, without blanks or repeats
+        let Validation::List(source) = self;
+        let values = match source {
+            ListSource::Values(values) => values.clone(),
+            ListSource::Range([start, end]) => (start.row..=end.row)
+                .flat_map(|row| (start.col..=end.col).map(move |col| SpreadsheetCell { row, col }))
+                .filter_map(|cell| spreadsheet.get_cell_value(&cell).ok())
+                .map(|value| value.content)
+                .collect(),
+        };
+        let mut allowed: Vec<String> = Vec::new();
+        for value in values {
+            if !value.is_empty() && !allowed.contains(&value) {
+                allowed.push(value);
+            }
+        }
+        allowed
+    }
+
+    pub fn check(
+        &self,
+        input: &str,
+        spreadsheet: &Spreadsheet,
+        cell: &SpreadsheetCell,
+    ) -> Result<String, String> {
+        // What to enter for a typed value, or why it isn't allowed. Values are matched ignoring
+        // case and entered as the list has them, formulas are checked by their result, and cells
+        // can always be cleared.
+        if input.is_empty() {
+            return Ok(String::new());
+        }
+        let value = if input.starts_with('=') {
+            cell_to_token(input, spreadsheet, Some(cell))
+                .map_err(|_| "This formula can't be checked against the list".to_string())?
+                .content
+        } else {
+            input.to_string()
+        };
+        let allowed = self.allowed_values(spreadsheet);
+        match allowed
+            .iter()
+            .find(|allowed| allowed.to_lowercase() == value.to_lowercase())
+        {
+            Some(_) if input.starts_with('=') => Ok(input.to_string()),
+            Some(allowed) => Ok(allowed.clone()),
+            None => Err(format!("\"{}\" isn't in this cell's list", value)),
+        }
+    }
+}
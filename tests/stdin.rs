@@ -0,0 +1,44 @@
+use std::io::Write;
+use std::process::{Command, Stdio};
+use std::thread;
+use std::time::{Duration, Instant};
+
+fn excel_tui(args: &[&str]) -> Command {
+    let mut command = Command::new(env!("CARGO_BIN_EXE_excel-tui"));
+    command
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped());
+    command
+}
+
+#[test]
+fn piped_csv_loads() {
+    let mut child = excel_tui(&["-", "--formula", "A1&B2"]).spawn().unwrap();
+    child
+        .stdin
+        .take()
+        .unwrap()
+        .write_all(b"name,qty\napple,5\n")
+        .unwrap();
+    let output = child.wait_with_output().unwrap();
+    assert!(output.status.success());
+    assert_eq!(String::from_utf8_lossy(&output.stdout).trim(), "name5");
+}
+
+#[test]
+fn formulas_without_a_path_dont_wait_for_stdin() {
+    // Stdin stays open, like under cron or CI, so reading it would never finish
+    let mut child = excel_tui(&["--formula", "1+1"]).spawn().unwrap();
+    let _stdin = child.stdin.take();
+    let started = Instant::now();
+    while child.try_wait().unwrap().is_none() {
+        if started.elapsed() > Duration::from_secs(10) {
+            child.kill().unwrap();
+            panic!("excel-tui waited for stdin");
+        }
+        thread::sleep(Duration::from_millis(20));
+    }
+    let output = child.wait_with_output().unwrap();
+    assert_eq!(String::from_utf8_lossy(&output.stdout).trim(), "2");
+}